@@ -0,0 +1,421 @@
+//! Async client for the HTTP API of the ITSF player database.
+//!
+//! The request and response bodies are the ones of the server, see [`api`].
+//!
+//! ```no_run
+//! # async fn example() -> itsf_playerdb_client::Result<()> {
+//! let client = itsf_playerdb_client::Client::new("https://players.example.org")?.with_token("pdb_...");
+//! let player = client.player(12345).await?;
+//! println!("{} {}", player.first_name, player.last_name);
+//! # Ok(())
+//! # }
+//! ```
+
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::io::Write;
+use std::sync::Mutex;
+
+pub use itsf_playerdb::api;
+pub use itsf_playerdb::data::{dtfb, itsf};
+
+use api::{
+    AddCommentInfo, AddTokenInfo, AddUserInfo, ChangeFeed, ChangePasswordInfo, ChangesParams, CreatedToken,
+    DownloadParams, DownloadStatus, ErrorCode, ErrorInfo, ExportFormat, ExportTable, ImportMode, ImportParams,
+    ImportReport, JsonErr, JsonOk, LoginInfo, MergePlayersInfo, Permission, PlayerBatch, PlayerBatchInfo, PlayerChange,
+    PlayerData, PlayerFilterParams, PlayerJson, PlayerMerge, PlayerViewParams, RemoveUserInfo, RevertMergeInfo,
+    RevokeTokenInfo, Role, Seed, SeedingRequest, SessionInfo, TokenInfo, UserInfo, API_PREFIX, CSRF_HEADER,
+    REQUEST_ID_HEADER,
+};
+
+#[derive(Debug)]
+pub enum Error {
+    /// The request couldn't be sent, or the response couldn't be read
+    Http(reqwest::Error),
+    /// The server refused the request
+    Api { status: StatusCode, error: ErrorInfo },
+    /// Writing a download failed
+    Io(std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http(err) => write!(f, "HTTP error: {}", err),
+            Self::Api { status, error } => write!(f, "{}: {}", status, error.message),
+            Self::Io(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Self::Http(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl Error {
+    /// The status code of a refused request
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Self::Api { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// The error code of a refused request
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            Self::Api { error, .. } => Some(error.code),
+            _ => None,
+        }
+    }
+
+    /// What the server said about a refused request
+    pub fn info(&self) -> Option<&ErrorInfo> {
+        match self {
+            Self::Api { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Credentials {
+    None,
+    Basic { user: String, password: String },
+    Bearer(String),
+}
+
+/// Sends requests to one server. Without credentials, only data that is public can be read.
+///
+/// A session started with [`Client::login`] is kept in a cookie, and its CSRF token is sent along
+/// with all requests that change something.
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    credentials: Credentials,
+    csrf_token: Mutex<Option<String>>,
+}
+
+/// Returns the name that a value is serialized as, e.g. `players` for `ExportTable::Players`.
+fn serialized_name<T: Serialize>(value: T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+/// Turns error responses into `Error::Api`, with the server's JSON error, or one made up from the status code
+/// if the response didn't come from the API, e.g. from a proxy.
+async fn check(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let request_id = response
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let body = response.text().await?;
+    let error = match serde_json::from_str::<JsonErr<ErrorInfo>>(&body) {
+        Ok(JsonErr { error }) => error,
+        Err(_) => ErrorInfo {
+            code: ErrorCode::from_status(status.as_u16()),
+            message: if body.is_empty() {
+                status.canonical_reason().unwrap_or_default().to_string()
+            } else {
+                body
+            },
+            details: Vec::new(),
+            request_id,
+        },
+    };
+    Err(Error::Api { status, error })
+}
+
+impl Client {
+    /// `base_url` is the URL of the server's root, e.g. `https://players.example.org`.
+    pub fn new(base_url: &str) -> Result<Self> {
+        let http = reqwest::Client::builder().cookie_store(true).build()?;
+        Ok(Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            credentials: Credentials::None,
+            csrf_token: Mutex::new(None),
+        })
+    }
+
+    pub fn with_basic_auth(mut self, user: &str, password: &str) -> Self {
+        self.credentials = Credentials::Basic {
+            user: user.to_string(),
+            password: password.to_string(),
+        };
+        self
+    }
+
+    /// Authenticates with an API token, as created by [`Client::add_token`].
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.credentials = Credentials::Bearer(token.to_string());
+        self
+    }
+
+    fn set_csrf_token(&self, csrf_token: Option<String>) {
+        if let Ok(mut current) = self.csrf_token.lock() {
+            *current = csrf_token;
+        }
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let is_safe = method == Method::GET;
+        let url = format!("{}{}{}", self.base_url, API_PREFIX, path);
+        let mut request = self.http.request(method, url);
+        request = match &self.credentials {
+            Credentials::None => request,
+            Credentials::Basic { user, password } => request.basic_auth(user, Some(password)),
+            Credentials::Bearer(token) => request.bearer_auth(token),
+        };
+        if !is_safe {
+            if let Some(csrf_token) = self.csrf_token.lock().ok().and_then(|token| token.clone()) {
+                request = request.header(CSRF_HEADER, csrf_token);
+            }
+        }
+        request
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T> {
+        let response = check(request.send().await?).await?;
+        Ok(response.json::<JsonOk<T>>().await?.data)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        self.send(self.request(Method::GET, path)).await
+    }
+
+    async fn post<T: DeserializeOwned, B: Serialize>(&self, path: &str, body: &B) -> Result<T> {
+        self.send(self.request(Method::POST, path).json(body)).await
+    }
+
+    async fn get_bytes(&self, request: RequestBuilder) -> Result<Vec<u8>> {
+        let response = check(request.send().await?).await?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    pub async fn player(&self, itsf_lic: i32) -> Result<PlayerJson> {
+        self.get(&format!("/player/{}", itsf_lic)).await
+    }
+
+    /// Returns a player with other sections, years or sort order than [`Client::player`].
+    pub async fn player_view(&self, itsf_lic: i32, view: &PlayerViewParams) -> Result<PlayerJson> {
+        self.send(self.request(Method::GET, &format!("/player/{}", itsf_lic)).query(view))
+            .await
+    }
+
+    /// Returns many players at once, in the order of `itsf_lics`, all from the same version of the DB.
+    pub async fn player_batch(&self, itsf_lics: &[i32], view: &PlayerViewParams) -> Result<PlayerBatch> {
+        let info = PlayerBatchInfo {
+            itsf_lics: itsf_lics.to_vec(),
+        };
+        self.send(self.request(Method::POST, "/players/batch").query(view).json(&info))
+            .await
+    }
+
+    pub async fn player_by_dtfb_id(&self, dtfb_id: i32) -> Result<PlayerJson> {
+        self.get(&format!("/player/by-dtfb/{}", dtfb_id)).await
+    }
+
+    pub async fn player_by_slug(&self, slug: &str) -> Result<PlayerJson> {
+        self.get(&format!("/player/by-slug/{}", slug)).await
+    }
+
+    pub async fn player_history(&self, itsf_lic: i32) -> Result<Vec<PlayerChange>> {
+        self.get(&format!("/player/{}/history", itsf_lic)).await
+    }
+
+    /// Returns the JPEG image of a player.
+    pub async fn player_image(&self, itsf_lic: i32) -> Result<Vec<u8>> {
+        self.get_bytes(self.request(Method::GET, &format!("/image/{}.jpg", itsf_lic)))
+            .await
+    }
+
+    /// Orders the participants of a tournament by their stored rankings.
+    pub async fn seeding(&self, request: &SeedingRequest) -> Result<Vec<Seed>> {
+        self.post("/seeding", request).await
+    }
+
+    pub async fn list_players(&self, filter: &PlayerFilterParams) -> Result<Vec<PlayerData>> {
+        self.send(self.request(Method::GET, "/listplayers").query(filter)).await
+    }
+
+    /// Returns a table of player data as CSV or NDJSON.
+    pub async fn export(
+        &self,
+        table: ExportTable,
+        format: ExportFormat,
+        filter: &PlayerFilterParams,
+    ) -> Result<String> {
+        let path = format!("/export/{}.{}", serialized_name(table), serialized_name(format));
+        let bytes = self.get_bytes(self.request(Method::GET, &path).query(filter)).await?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Returns all players that changed after the change sequence number `since`, or all players for 0.
+    pub async fn changes(&self, since: i64) -> Result<ChangeFeed> {
+        let params = ChangesParams { since: Some(since) };
+        self.send(self.request(Method::GET, "/changes").query(&params)).await
+    }
+
+    pub async fn add_comment(&self, itsf_lic: i32, comment: &str) -> Result<String> {
+        let info = AddCommentInfo {
+            itsf_lic,
+            comment: comment.to_string(),
+        };
+        self.post("/add_comment", &info).await
+    }
+
+    pub async fn merge_players(&self, source_itsf_lic: i32, target_itsf_lic: i32) -> Result<PlayerMerge> {
+        let info = MergePlayersInfo {
+            source_itsf_lic,
+            target_itsf_lic,
+        };
+        self.post("/merge_players", &info).await
+    }
+
+    pub async fn revert_merge(&self, merge_id: i32) -> Result<PlayerMerge> {
+        self.post("/revert_merge", &RevertMergeInfo { merge_id }).await
+    }
+
+    pub async fn list_merges(&self) -> Result<Vec<PlayerMerge>> {
+        self.get("/listmerges").await
+    }
+
+    /// Writes the zip archive of the DB and all images to `writer` as it is received, and returns its size.
+    pub async fn download_db_zip<W: Write>(&self, writer: &mut W) -> Result<u64> {
+        let mut response = check(self.request(Method::GET, "/db.zip").send().await?).await?;
+        let mut size = 0;
+        while let Some(chunk) = response.chunk().await? {
+            writer.write_all(&chunk)?;
+            size += chunk.len() as u64;
+        }
+        writer.flush()?;
+        Ok(size)
+    }
+
+    /// Imports an archive in the format of [`Client::download_db_zip`].
+    pub async fn import_db_zip(&self, archive: Vec<u8>, mode: ImportMode) -> Result<ImportReport> {
+        let params = ImportParams { mode: Some(mode) };
+        self.send(self.request(Method::POST, "/import").query(&params).body(archive))
+            .await
+    }
+
+    /// Returns the state of the current download, if one is running.
+    pub async fn download_status(&self) -> Result<DownloadStatus> {
+        self.get("/download_status").await
+    }
+
+    /// Starts downloading the ITSF rankings of one year, see [`Client::download_status`].
+    pub async fn download_itsf(&self, params: &DownloadParams) -> Result<String> {
+        self.send(self.request(Method::POST, "/download_itsf").query(params))
+            .await
+    }
+
+    /// Starts downloading the ITSF rankings of all years.
+    pub async fn download_itsf_all(&self) -> Result<String> {
+        self.send(self.request(Method::POST, "/download_itsf_all")).await
+    }
+
+    /// Starts downloading the DTFB rankings of one season.
+    pub async fn download_dtfb(&self, params: &DownloadParams) -> Result<String> {
+        self.send(self.request(Method::POST, "/download_dtfb").query(params))
+            .await
+    }
+
+    /// Starts downloading the DTFB rankings of all seasons.
+    pub async fn download_dtfb_all(&self) -> Result<String> {
+        self.send(self.request(Method::POST, "/download_dtfb_all")).await
+    }
+
+    pub async fn list_users(&self) -> Result<Vec<UserInfo>> {
+        self.get("/listusers").await
+    }
+
+    pub async fn add_user(&self, name: &str, password: &str, role: Role) -> Result<String> {
+        let info = AddUserInfo {
+            name: name.to_string(),
+            password: password.to_string(),
+            role,
+        };
+        self.post("/add_user", &info).await
+    }
+
+    pub async fn remove_user(&self, name: &str) -> Result<String> {
+        let info = RemoveUserInfo { name: name.to_string() };
+        self.post("/remove_user", &info).await
+    }
+
+    /// Changes the password of `name`, or of the authenticated user if `name` is `None`.
+    pub async fn change_password(&self, name: Option<&str>, password: &str) -> Result<String> {
+        let info = ChangePasswordInfo {
+            name: name.map(String::from),
+            password: password.to_string(),
+        };
+        self.post("/change_password", &info).await
+    }
+
+    /// Starts a session, which is used for all further requests of this client.
+    pub async fn login(&self, name: &str, password: &str) -> Result<SessionInfo> {
+        let info = LoginInfo {
+            name: name.to_string(),
+            password: password.to_string(),
+        };
+        let session: SessionInfo = self.post("/login", &info).await?;
+        self.set_csrf_token(Some(session.csrf_token.clone()));
+        Ok(session)
+    }
+
+    pub async fn logout(&self) -> Result<String> {
+        let message = self.send(self.request(Method::POST, "/logout")).await?;
+        self.set_csrf_token(None);
+        Ok(message)
+    }
+
+    /// Returns the current session, e.g. to check whether it expired.
+    pub async fn session(&self) -> Result<SessionInfo> {
+        let session: SessionInfo = self.get("/session").await?;
+        self.set_csrf_token(Some(session.csrf_token.clone()));
+        Ok(session)
+    }
+
+    pub async fn list_tokens(&self) -> Result<Vec<TokenInfo>> {
+        self.get("/listtokens").await
+    }
+
+    /// Creates an API token. The returned secret can't be retrieved again later.
+    pub async fn add_token(&self, name: &str, scopes: &[Permission], valid_days: i64) -> Result<CreatedToken> {
+        let info = AddTokenInfo {
+            name: name.to_string(),
+            scopes: scopes.to_vec(),
+            valid_days,
+        };
+        self.post("/add_token", &info).await
+    }
+
+    pub async fn revoke_token(&self, id: &str) -> Result<String> {
+        let info = RevokeTokenInfo { id: id.to_string() };
+        self.post("/revoke_token", &info).await
+    }
+}
@@ -0,0 +1,567 @@
+//! Runs the client against a server on a free local port, with a fresh DB and users file for each test.
+
+use itsf_playerdb::config::{Args, Config};
+use itsf_playerdb::data::{itsf, ChangeSource, DatabaseRef, Player, PlayerImage};
+use itsf_playerdb::server;
+use itsf_playerdb_client::api::{
+    ChangeAction, DownloadParams, ErrorCode, ExportFormat, ExportTable, ImportMode, Permission, PlayerFilterParams,
+    PlayerJson, PlayerViewParams, Role, SeedingParticipant, SeedingPlayer, SeedingRanking, SeedingRequest, TieBreak,
+};
+use itsf_playerdb_client::{Client, Error};
+use reqwest::StatusCode;
+use std::net::TcpListener;
+
+const ADMIN: &str = "admin";
+const ADMIN_PASSWORD: &str = "admin-password";
+const IMAGE: &[u8] = b"\xff\xd8\xff\xe0 not really a jpeg";
+
+struct TestServer {
+    url: String,
+    /// Authenticated with an admin token, since checking passwords is slow
+    admin: Client,
+    _dir: tempfile::TempDir,
+}
+
+fn player(itsf_id: i32, first_name: &str, last_name: &str, dtfb_id: Option<i32>) -> Player {
+    Player {
+        itsf_id,
+        first_name: String::from(first_name),
+        last_name: String::from(last_name),
+        birth_year: 1990,
+        country_code: Some(String::from("GER")),
+        category: itsf::PlayerCategory::Men,
+        itsf_rankings: Vec::new(),
+        dtfb_id,
+        dtfb_national_rankings: Vec::new(),
+        dtfb_championship_results: Vec::new(),
+        dtfb_league_teams: Vec::new(),
+        comments: Vec::new(),
+    }
+}
+
+fn ranking(year: i32, place: i32, class: itsf::RankingClass) -> itsf::Ranking {
+    itsf::Ranking {
+        year,
+        place,
+        category: itsf::RankingCategory::Open,
+        class,
+    }
+}
+
+impl TestServer {
+    async fn start() -> Self {
+        Self::start_with(|_| ()).await
+    }
+
+    /// Starts a server without rate limits, unless `configure` sets some.
+    async fn start_with(configure: impl FnOnce(&mut Config)) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        std::fs::create_dir(path("images")).unwrap();
+        std::fs::create_dir(path("html")).unwrap();
+        std::fs::write(path("users.txt"), format!("{}:{}\n", ADMIN, ADMIN_PASSWORD)).unwrap();
+
+        let db = DatabaseRef::load(&path("db.sqlite"), &path("images")).unwrap();
+        let source = ChangeSource::Admin {
+            user: String::from(ADMIN),
+        };
+        let mut thomas = player(1001, "Thomas", "Müller", Some(77));
+        thomas.itsf_rankings = vec![
+            ranking(2022, 12, itsf::RankingClass::Singles),
+            ranking(2023, 5, itsf::RankingClass::Singles),
+            ranking(2023, 3, itsf::RankingClass::Combined),
+        ];
+        db.add_player(thomas, &source).unwrap();
+        db.add_player(player(1002, "Thomas", "Müller", None), &source).unwrap();
+        let mut anna = player(2001, "Anna", "Schmidt", None);
+        anna.itsf_rankings = vec![
+            ranking(2022, 3, itsf::RankingClass::Singles),
+            ranking(2023, 5, itsf::RankingClass::Singles),
+        ];
+        db.add_player(anna, &source).unwrap();
+        db.set_player_image(PlayerImage {
+            itsf_id: 1001,
+            image_data: IMAGE.to_vec(),
+            image_format: String::from("jpg"),
+        })
+        .unwrap();
+        drop(db);
+
+        let args = Args {
+            database: Some(path("db.sqlite")),
+            images: Some(path("images")),
+            html_root: Some(path("html")),
+            users_file: Some(path("users.txt")),
+            tokens_file: Some(path("tokens.json")),
+            ..Default::default()
+        };
+        let mut config = Config::load(&args).unwrap();
+        config.auth.rate_limit_per_ip = 0;
+        config.auth.rate_limit_per_user = 0;
+        config.auth.rate_limit_expensive = 0;
+        configure(&mut config);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        actix_web::rt::spawn(server::serve_listener(config, listener));
+
+        let token = Client::new(&url)
+            .unwrap()
+            .with_basic_auth(ADMIN, ADMIN_PASSWORD)
+            .add_token(
+                "tests",
+                &[
+                    Permission::Read,
+                    Permission::Comment,
+                    Permission::Scrape,
+                    Permission::Admin,
+                ],
+                1,
+            )
+            .await
+            .unwrap();
+        let admin = Client::new(&url).unwrap().with_token(&token.token);
+        Self { url, admin, _dir: dir }
+    }
+
+    fn anonymous(&self) -> Client {
+        Client::new(&self.url).unwrap()
+    }
+
+    async fn token_client(&self, scopes: &[Permission]) -> Client {
+        let token = self.admin.add_token("scoped", scopes, 1).await.unwrap();
+        self.anonymous().with_token(&token.token)
+    }
+}
+
+fn status<T: std::fmt::Debug>(result: Result<T, Error>) -> StatusCode {
+    result.unwrap_err().status().expect("expected an error response")
+}
+
+#[actix_web::test]
+async fn reads_players() {
+    let server = TestServer::start().await;
+    let client = server.anonymous();
+
+    let player = client.player(1001).await.unwrap();
+    assert_eq!(player.first_name, "Thomas");
+    assert_eq!(player.country_code, "GER");
+    assert_eq!(player.image_url, "/api/v1/image/1001.jpg");
+    assert_eq!(client.player_by_dtfb_id(77).await.unwrap().last_name, "Müller");
+    assert_eq!(status(client.player(9999).await), StatusCode::NOT_FOUND);
+    assert_eq!(status(client.player_by_dtfb_id(78).await), StatusCode::NOT_FOUND);
+
+    let players = client.list_players(&PlayerFilterParams::default()).await.unwrap();
+    assert_eq!(players.len(), 3);
+    let thomas = players.iter().find(|p| p.itsf_lic == 1002).unwrap();
+    assert_eq!(client.player_by_slug(&thomas.slug).await.unwrap().first_name, "Thomas");
+
+    let filter = PlayerFilterParams {
+        name: Some(String::from("schmidt")),
+        ..Default::default()
+    };
+    let players = client.list_players(&filter).await.unwrap();
+    assert_eq!(players.iter().map(|p| p.itsf_lic).collect::<Vec<_>>(), vec![2001]);
+
+    let history = client.player_history(1001).await.unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].action, ChangeAction::Created);
+
+    assert_eq!(client.player_image(1001).await.unwrap(), IMAGE);
+    assert_eq!(status(client.player_image(1002).await), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn player_views() {
+    let server = TestServer::start().await;
+    let client = server.anonymous();
+    let years = |player: &PlayerJson| player.itsf_rankings.iter().map(|r| r.year).collect::<Vec<_>>();
+
+    let player = client.player(1001).await.unwrap();
+    assert_eq!(years(&player), vec![2023, 2022]);
+    assert!(player.dtfb_rankings.is_some());
+    assert!(player.ids.is_none() && player.comments.is_none() && player.image.is_none());
+
+    let view = PlayerViewParams {
+        include: Some(String::from("combined_rankings,ids,image,comments")),
+        exclude: Some(String::from("dtfb")),
+        sort: Some(String::from("oldest_first")),
+        ..Default::default()
+    };
+    let player = client.player_view(1001, &view).await.unwrap();
+    assert_eq!(years(&player), vec![2022, 2023, 2023]);
+    assert!(player.dtfb_rankings.is_none() && player.dtfl_teams.is_none());
+    assert_eq!(player.ids.unwrap().dtfb_id, Some(77));
+    assert_eq!(player.image.unwrap().size, IMAGE.len() as u64);
+    assert_eq!(player.comments.unwrap().len(), 0);
+
+    let view = PlayerViewParams {
+        from_year: Some(2023),
+        ..Default::default()
+    };
+    assert_eq!(years(&client.player_view(1001, &view).await.unwrap()), vec![2023]);
+    let view = PlayerViewParams {
+        include: Some(String::from("everything")),
+        ..Default::default()
+    };
+    assert_eq!(status(client.player_view(1001, &view).await), StatusCode::BAD_REQUEST);
+
+    // the ETag depends on the query
+    let http = reqwest::Client::new();
+    let url = format!("{}/api/v1/player/1001", server.url);
+    let etag = |response: reqwest::Response| response.headers()["etag"].to_str().unwrap().to_string();
+    let plain = etag(http.get(&url).send().await.unwrap());
+    let with_ids = etag(http.get(format!("{}?include=ids", url)).send().await.unwrap());
+    assert_ne!(plain, with_ids);
+    let response = http
+        .get(format!("{}?include=ids", url))
+        .header("If-None-Match", &with_ids)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+}
+
+#[actix_web::test]
+async fn player_batches() {
+    let server = TestServer::start().await;
+    let client = server.anonymous();
+
+    server.admin.merge_players(1002, 1001).await.unwrap();
+    let batch = client
+        .player_batch(&[2001, 9999, 1002], &PlayerViewParams::default())
+        .await
+        .unwrap();
+    assert_eq!(batch.seq, client.changes(0).await.unwrap().seq);
+    let ids: Vec<_> = batch
+        .players
+        .iter()
+        .map(|entry| (entry.itsf_lic, entry.found))
+        .collect();
+    assert_eq!(ids, vec![(2001, true), (9999, false), (1002, true)]);
+    assert_eq!(batch.players[0].player.as_ref().unwrap().last_name, "Schmidt");
+    assert!(batch.players[1].player.is_none());
+    assert_eq!(batch.players[2].merged_into, Some(1001));
+
+    let view = PlayerViewParams {
+        include: Some(String::from("ids")),
+        ..Default::default()
+    };
+    let batch = client.player_batch(&[1001], &view).await.unwrap();
+    assert_eq!(
+        batch.players[0].player.as_ref().unwrap().ids.as_ref().unwrap().itsf_id,
+        1001
+    );
+    assert_eq!(
+        status(client.player_batch(&[1; 1001], &view).await),
+        StatusCode::BAD_REQUEST
+    );
+}
+
+#[actix_web::test]
+async fn seeding() {
+    let server = TestServer::start().await;
+    let client = server.anonymous();
+    let singles = |year| SeedingRanking::Itsf {
+        category: itsf::RankingCategory::Open,
+        class: itsf::RankingClass::Singles,
+        year,
+    };
+    let participant = |players: &[SeedingPlayer]| SeedingParticipant {
+        players: players.to_vec(),
+    };
+    let mut request = SeedingRequest {
+        participants: vec![
+            participant(&[SeedingPlayer::ItsfLic(1002)]),
+            participant(&[SeedingPlayer::DtfbId(77)]),
+            participant(&[SeedingPlayer::ItsfLic(2001)]),
+        ],
+        rankings: vec![singles(2023), singles(2022)],
+        tie_break: TieBreak::NextRanking,
+    };
+
+    // both are 5th in 2023, Anna was better in 2022
+    let seeds = client.seeding(&request).await.unwrap();
+    let order: Vec<_> = seeds.iter().map(|seed| seed.itsf_lics[0]).collect();
+    assert_eq!(order, vec![2001, 1001, 1002]);
+    assert_eq!((seeds[0].ranking, seeds[0].places.clone()), (Some(0), vec![5]));
+    assert!(seeds[0].reason.contains("ITSF open singles 2022: place 3 vs. place 12"));
+    assert_eq!(seeds[2].ranking, None);
+
+    request.tie_break = TieBreak::InputOrder;
+    let seeds = client.seeding(&request).await.unwrap();
+    assert_eq!(seeds[0].itsf_lics, vec![1001]);
+    assert!(seeds[0].reason.contains("the order of the participants"));
+
+    // a pair that is only ranked in the fallback
+    request.rankings = vec![singles(2024), singles(2022)];
+    request.participants = vec![
+        participant(&[SeedingPlayer::ItsfLic(1001), SeedingPlayer::ItsfLic(2001)]),
+        participant(&[SeedingPlayer::ItsfLic(1002)]),
+    ];
+    let seeds = client.seeding(&request).await.unwrap();
+    assert_eq!((seeds[0].ranking, seeds[0].places.clone()), (Some(1), vec![12, 3]));
+    assert!(seeds[0].reason.contains("(not ranked in ITSF open singles 2024)"));
+
+    request.participants.push(participant(&[SeedingPlayer::DtfbId(78)]));
+    let err = client.seeding(&request).await.unwrap_err();
+    assert_eq!(err.code(), Some(ErrorCode::InvalidRequest));
+    assert!(err.to_string().contains("DTFB ID 78"));
+}
+
+#[actix_web::test]
+async fn exports_and_changes() {
+    let server = TestServer::start().await;
+    let client = server.anonymous();
+
+    let csv = client
+        .export(ExportTable::Players, ExportFormat::Csv, &PlayerFilterParams::default())
+        .await
+        .unwrap();
+    assert_eq!(csv.lines().count(), 4);
+    assert!(csv.contains("Schmidt"));
+
+    let feed = client.changes(0).await.unwrap();
+    assert_eq!(feed.added.len(), 3);
+    assert!(client.changes(feed.seq).await.unwrap().added.is_empty());
+
+    server.admin.add_comment(2001, "left-handed").await.unwrap();
+    assert_eq!(client.player(2001).await.unwrap().comment, "left-handed");
+    let feed = client.changes(feed.seq).await.unwrap();
+    assert_eq!(feed.updated.iter().map(|p| p.itsf_id).collect::<Vec<_>>(), vec![2001]);
+}
+
+#[actix_web::test]
+async fn tokens_and_permissions() {
+    let server = TestServer::start().await;
+
+    assert_eq!(status(server.anonymous().list_users().await), StatusCode::UNAUTHORIZED);
+    let invalid = server.anonymous().with_token("invalid");
+    assert_eq!(status(invalid.list_users().await), StatusCode::UNAUTHORIZED);
+
+    let commenter = server.token_client(&[Permission::Read, Permission::Comment]).await;
+    commenter.add_comment(1001, "fast").await.unwrap();
+    assert_eq!(status(commenter.list_users().await), StatusCode::FORBIDDEN);
+    assert_eq!(status(commenter.merge_players(1002, 1001).await), StatusCode::FORBIDDEN);
+
+    let tokens = server.admin.list_tokens().await.unwrap();
+    let scoped = tokens.iter().find(|t| t.name == "scoped").unwrap();
+    assert_eq!(scoped.scopes, vec![Permission::Read, Permission::Comment]);
+    assert!(scoped.last_used.is_some());
+
+    server.admin.revoke_token(&scoped.id).await.unwrap();
+    assert_eq!(
+        status(commenter.add_comment(1001, "slow").await),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[actix_web::test]
+async fn sessions_and_users() {
+    let server = TestServer::start().await;
+    server
+        .admin
+        .add_user("viewer", "viewer-password", Role::Viewer)
+        .await
+        .unwrap();
+    let users = server.admin.list_users().await.unwrap();
+    assert_eq!(users.len(), 2);
+    assert!(users
+        .iter()
+        .any(|user| user.name == "viewer" && user.role == Role::Viewer));
+    assert_eq!(
+        status(server.admin.add_user("short", "short", Role::Viewer).await),
+        StatusCode::BAD_REQUEST
+    );
+
+    let client = server.anonymous();
+    assert_eq!(
+        status(client.login("viewer", "wrong-password").await),
+        StatusCode::UNAUTHORIZED
+    );
+    let session = client.login("viewer", "viewer-password").await.unwrap();
+    assert_eq!(session.name, "viewer");
+    assert_eq!(session.role, Role::Viewer);
+    assert_eq!(client.session().await.unwrap().csrf_token, session.csrf_token);
+
+    // the CSRF token is sent along, so only the role decides
+    assert_eq!(status(client.add_comment(1001, "hi").await), StatusCode::FORBIDDEN);
+    client.change_password(None, "new-viewer-password").await.unwrap();
+
+    client.logout().await.unwrap();
+    assert_eq!(status(client.session().await), StatusCode::UNAUTHORIZED);
+
+    server.admin.remove_user("viewer").await.unwrap();
+    assert_eq!(server.admin.list_users().await.unwrap().len(), 1);
+}
+
+#[actix_web::test]
+async fn merges_and_imports() {
+    let server = TestServer::start().await;
+    let admin = &server.admin;
+
+    let mut archive = Vec::new();
+    let size = admin.download_db_zip(&mut archive).await.unwrap();
+    assert_eq!(size as usize, archive.len());
+    assert!(archive.starts_with(b"PK"));
+
+    let merge = admin.merge_players(1002, 1001).await.unwrap();
+    assert_eq!((merge.source_itsf_id, merge.target_itsf_id), (1002, 1001));
+    assert_eq!(admin.list_merges().await.unwrap().len(), 1);
+    assert_eq!(
+        admin.list_players(&PlayerFilterParams::default()).await.unwrap().len(),
+        2
+    );
+    assert!(admin.revert_merge(merge.id).await.unwrap().reverted);
+    assert_eq!(
+        admin.list_players(&PlayerFilterParams::default()).await.unwrap().len(),
+        3
+    );
+
+    admin.add_comment(2001, "after export").await.unwrap();
+    let report = admin.import_db_zip(archive, ImportMode::Replace).await.unwrap();
+    assert_eq!(report.mode, ImportMode::Replace);
+    assert_eq!(report.images, 1);
+    assert_eq!(admin.player(2001).await.unwrap().comment, "");
+    assert_eq!(admin.player_image(1001).await.unwrap(), IMAGE);
+
+    let viewer = server.token_client(&[Permission::Read]).await;
+    assert_eq!(
+        status(viewer.import_db_zip(Vec::new(), ImportMode::Merge).await),
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[actix_web::test]
+async fn downloads() {
+    let server = TestServer::start().await;
+
+    let status_info = server.anonymous().download_status().await.unwrap();
+    assert!(!status_info.running);
+    assert!(status_info.log.is_empty());
+
+    let old_year = DownloadParams {
+        year: Some(String::from("1999")),
+        ..Default::default()
+    };
+    assert_eq!(
+        status(server.admin.download_itsf(&old_year).await),
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        status(server.admin.download_dtfb(&old_year).await),
+        StatusCode::BAD_REQUEST
+    );
+
+    let viewer = server.token_client(&[Permission::Read]).await;
+    assert_eq!(status(viewer.download_itsf_all().await), StatusCode::FORBIDDEN);
+    assert_eq!(status(viewer.download_dtfb_all().await), StatusCode::FORBIDDEN);
+    assert_eq!(
+        status(viewer.download_itsf(&DownloadParams::default()).await),
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[actix_web::test]
+async fn openapi_and_deprecated_aliases() {
+    let server = TestServer::start().await;
+    let http = reqwest::Client::new();
+
+    let document: serde_json::Value = http
+        .get(format!("{}/api/v1/openapi.json", server.url))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(document["servers"][0]["url"], "/api/v1");
+    assert!(document["paths"]["/player/{itsf_lic}"]["get"].is_object());
+    assert!(document["paths"]["/download_itsf"]["post"].is_object());
+
+    let docs = http.get(format!("{}/api/v1/docs/", server.url)).send().await.unwrap();
+    assert_eq!(docs.status(), StatusCode::OK);
+
+    let current = http
+        .get(format!("{}/api/v1/player/1001", server.url))
+        .send()
+        .await
+        .unwrap();
+    assert!(current.headers().get("deprecation").is_none());
+    let alias = http.get(format!("{}/player/1001", server.url)).send().await.unwrap();
+    assert_eq!(alias.status(), StatusCode::OK);
+    assert_eq!(alias.headers()["deprecation"], "true");
+    assert_eq!(
+        alias.headers()["link"],
+        "</api/v1/player/1001>; rel=\"successor-version\""
+    );
+}
+
+#[actix_web::test]
+async fn structured_errors() {
+    let server = TestServer::start().await;
+    let http = reqwest::Client::new();
+
+    let err = server.anonymous().list_users().await.unwrap_err();
+    assert_eq!(err.code(), Some(ErrorCode::Unauthenticated));
+    assert!(!err.info().unwrap().request_id.is_empty());
+    let err = server.admin.player(9999).await.unwrap_err();
+    assert_eq!(err.code(), Some(ErrorCode::NotFound));
+
+    // actix' own extractor errors use the same envelope
+    let response = http
+        .get(format!("{}/api/v1/player/abc", server.url))
+        .header("X-Request-Id", "test-request-1")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(response.headers()["x-request-id"], "test-request-1");
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "invalid_request");
+    assert_eq!(body["error"]["details"][0]["field"], "itsf_lic");
+    assert_eq!(body["error"]["request_id"], "test-request-1");
+
+    let response = http
+        .post(format!("{}/api/v1/merge_players", server.url))
+        .basic_auth(ADMIN, Some(ADMIN_PASSWORD))
+        .header("Content-Type", "application/json")
+        .body("{\"source_itsf_lic\": \"x\"}")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["details"][0]["field"], "body");
+
+    let response = http.get(format!("{}/api/v1/nothing", server.url)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "not_found");
+}
+
+#[actix_web::test]
+async fn rate_limits_apply_to_optional_authentication() {
+    let server = TestServer::start_with(|config| config.auth.rate_limit_per_user = 3).await;
+    let token = server.admin.add_token("reader", &[Permission::Read], 1).await.unwrap();
+    let http = reqwest::Client::new();
+
+    let get_player = || {
+        http.get(format!("{}/api/v1/player/1001", server.url))
+            .bearer_auth(&token.token)
+            .send()
+    };
+    for _ in 0..3 {
+        assert_eq!(get_player().await.unwrap().status(), StatusCode::OK);
+    }
+    // instead of being served as an anonymous caller
+    let response = get_player().await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: u64 = response.headers()["retry-after"].to_str().unwrap().parse().unwrap();
+    assert!(retry_after >= 1);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "rate_limited");
+
+    // the limit is per user or token, anonymous callers aren't affected
+    assert!(server.anonymous().player(1001).await.is_ok());
+}
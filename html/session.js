@@ -0,0 +1,71 @@
+// Login session of the web UI. Pages that change data contain a <div id="session"></div>,
+// which shows either a login form or the logged-in user.
+var csrfToken = null;
+
+function showSession(session) {
+    var box = document.getElementById("session");
+    if (session) {
+        csrfToken = session.csrf_token;
+        box.innerHTML = "<p> Logged in as <b></b> (" + session.role + ") "
+            + "<button onclick=\"logout()\"> Log out </button> </p>";
+        box.getElementsByTagName("b")[0].innerText = session.name;
+    } else {
+        csrfToken = null;
+        box.innerHTML = "<p> <input type=\"text\" id=\"login_name\" placeholder=\"user\"> "
+            + "<input type=\"password\" id=\"login_password\" placeholder=\"password\"> "
+            + "<button onclick=\"login()\"> Log in </button> <span id=\"login_error\"></span> </p>";
+    }
+}
+
+function loadSession() {
+    var xhr = new XMLHttpRequest();
+    xhr.onreadystatechange = function() {
+        if (this.readyState == 4) {
+            showSession(this.status == 200 ? JSON.parse(this.responseText).data : null);
+        }
+    }
+    xhr.open("GET", "/api/v1/session", true);
+    xhr.setRequestHeader("Accept", "application/json");
+    xhr.send();
+}
+
+function login() {
+    var json = {
+        "name": document.getElementById("login_name").value,
+        "password": document.getElementById("login_password").value
+    };
+    var xhr = new XMLHttpRequest();
+    xhr.onreadystatechange = function() {
+        if (this.readyState == 4) {
+            let json = JSON.parse(this.responseText);
+            if (this.status == 200) {
+                showSession(json.data);
+            } else {
+                document.getElementById("login_error").innerText = json.error.message;
+            }
+        }
+    }
+    xhr.open("POST", "/api/v1/login", true);
+    xhr.setRequestHeader("Content-Type", "application/json;charset=UTF-8");
+    xhr.send(JSON.stringify(json));
+}
+
+function logout() {
+    var xhr = new XMLHttpRequest();
+    xhr.onreadystatechange = function() {
+        if (this.readyState == 4) {
+            showSession(null);
+        }
+    }
+    xhr.open("POST", "/api/v1/logout", true);
+    xhr.send();
+}
+
+// has to be called on all requests that change something
+function setCsrfHeader(xhr) {
+    if (csrfToken) {
+        xhr.setRequestHeader("X-CSRF-Token", csrfToken);
+    }
+}
+
+window.addEventListener("load", loadSession);
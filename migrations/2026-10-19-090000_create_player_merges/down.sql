@@ -0,0 +1,2 @@
+DROP TABLE player_merges;
+DROP TABLE player_redirects;
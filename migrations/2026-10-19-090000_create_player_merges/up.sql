@@ -0,0 +1,16 @@
+CREATE TABLE player_redirects (
+	old_itsf_id INTEGER PRIMARY KEY NOT NULL,
+	new_itsf_id INTEGER NOT NULL
+);
+
+CREATE TABLE player_merges (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	timestamp BIGINT NOT NULL,
+	user TEXT NOT NULL,
+	source_itsf_id INTEGER NOT NULL,
+	target_itsf_id INTEGER NOT NULL,
+	source_json BLOB NOT NULL,
+	target_json BLOB NOT NULL,
+	image_moved BOOLEAN NOT NULL,
+	reverted BOOLEAN NOT NULL DEFAULT 0
+);
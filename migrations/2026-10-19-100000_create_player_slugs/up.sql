@@ -0,0 +1,4 @@
+CREATE TABLE player_slugs (
+	slug TEXT PRIMARY KEY NOT NULL,
+	itsf_id INTEGER NOT NULL
+);
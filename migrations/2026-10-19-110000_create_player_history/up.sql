@@ -0,0 +1,10 @@
+CREATE TABLE player_history (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	itsf_id INTEGER NOT NULL,
+	timestamp BIGINT NOT NULL,
+	action TEXT NOT NULL,
+	source BLOB NOT NULL,
+	changes BLOB NOT NULL
+);
+
+CREATE INDEX player_history_itsf_id ON player_history (itsf_id);
@@ -0,0 +1,7 @@
+CREATE TABLE player_quarantine (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	itsf_id INTEGER NOT NULL,
+	timestamp BIGINT NOT NULL,
+	json_data BLOB NOT NULL,
+	error TEXT NOT NULL
+);
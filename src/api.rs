@@ -0,0 +1,362 @@
+//! Request and response bodies of the HTTP API, shared by the server and its clients.
+//!
+//! Successful responses wrap their data in [`JsonOk`], failed ones an [`ErrorInfo`] in [`JsonErr`]. The OpenAPI
+//! document of all endpoints is served at `/api/v1/openapi.json`.
+
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::data::{dtfb, itsf};
+
+pub use crate::data::{
+    ChangeAction, ChangeFeed, ChangeSource, DeletedPlayer, ExportFormat, ExportTable, FieldChange, ImportMode,
+    ImportReport, PlayerChange, PlayerComment, PlayerFilterParams, PlayerMerge, PlayerSection, PlayerViewParams, Seed,
+    SeedingParticipant, SeedingPlayer, SeedingRanking, SeedingRequest, TieBreak,
+};
+
+/// All endpoints are below this path. The same endpoints at the root are deprecated aliases.
+pub const API_PREFIX: &str = "/api/v1";
+
+/// Header with the ID of a request, which is also part of error responses
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Header that has to repeat the session's CSRF token on all requests that change something
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct JsonOk<T> {
+    pub data: T,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct JsonErr<T> {
+    pub error: T,
+}
+
+/// Stable causes of errors, for clients to match on instead of the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request is malformed, e.g. a path parameter or JSON body of the wrong type, or doesn't fit the data
+    InvalidRequest,
+    /// No credentials were sent, or they were wrong or expired
+    Unauthenticated,
+    /// The caller may not do this
+    Forbidden,
+    /// A request with the session cookie lacked the session's CSRF token
+    InvalidCsrfToken,
+    NotFound,
+    /// A download is running, which has to finish first
+    JobRunning,
+    PayloadTooLarge,
+    /// Too many requests, see the `Retry-After` header
+    RateLimited,
+    Internal,
+    /// The DB is busy, the request may succeed if it is retried later
+    Unavailable,
+}
+
+impl ErrorCode {
+    pub fn status(self) -> u16 {
+        match self {
+            Self::InvalidRequest => 400,
+            Self::Unauthenticated => 401,
+            Self::Forbidden | Self::InvalidCsrfToken => 403,
+            Self::NotFound => 404,
+            Self::JobRunning => 409,
+            Self::PayloadTooLarge => 413,
+            Self::RateLimited => 429,
+            Self::Internal => 500,
+            Self::Unavailable => 503,
+        }
+    }
+
+    /// The most likely code of an error response without one, e.g. from a proxy.
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            401 => Self::Unauthenticated,
+            403 => Self::Forbidden,
+            404 => Self::NotFound,
+            409 => Self::JobRunning,
+            413 => Self::PayloadTooLarge,
+            429 => Self::RateLimited,
+            503 => Self::Unavailable,
+            400..=499 => Self::InvalidRequest,
+            _ => Self::Internal,
+        }
+    }
+}
+
+/// A problem with one part of a request.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ErrorDetail {
+    /// A parameter or body field, or `path`, `query` or `body` if the part is unknown
+    pub field: String,
+    pub message: String,
+}
+
+/// The error of all failed requests, wrapped in [`JsonErr`].
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ErrorInfo {
+    pub code: ErrorCode,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<ErrorDetail>,
+    /// Also sent as `X-Request-Id` header, and logged by the server
+    pub request_id: String,
+}
+
+/// A player as returned by `/player/{itsf_lic}`. Rankings are sorted by year, newest first, unless the query
+/// asks for something else. The optional fields are only sent if their section is included.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PlayerJson {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ids: Option<PlayerIds>,
+    pub first_name: String,
+    pub last_name: String,
+    pub birth_year: i32,
+    pub country_code: String,
+    /// Empty if the image is hidden
+    pub image_url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<PlayerImageInfo>,
+    pub itsf_rankings: Vec<itsf::Ranking>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dtfb_rankings: Option<Vec<dtfb::NationalRanking>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dm_placements: Option<Vec<dtfb::NationalChampionshipResult>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dtfl_teams: Option<Vec<dtfb::NationalTeam>>,
+    /// The most recent comment
+    pub comment: String,
+    /// All comments, oldest first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comments: Option<Vec<PlayerComment>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PlayerIds {
+    pub itsf_id: i32,
+    pub dtfb_id: Option<i32>,
+    pub category: itsf::PlayerCategory,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PlayerImageInfo {
+    pub url: String,
+    pub size: u64,
+    /// Unix timestamp
+    pub modified: i64,
+}
+
+/// Body of `/players/batch`.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PlayerBatchInfo {
+    pub itsf_lics: Vec<i32>,
+}
+
+/// The players of `/players/batch`, in the order of the request.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PlayerBatch {
+    /// Change sequence number of the DB that all players were read at, see `/changes`
+    pub seq: i64,
+    pub players: Vec<PlayerBatchEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PlayerBatchEntry {
+    /// The requested ID
+    pub itsf_lic: i32,
+    /// `false` if there is no such player
+    pub found: bool,
+    /// The player that `itsf_lic` was merged into
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merged_into: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player: Option<PlayerJson>,
+}
+
+/// An entry of `/listplayers`.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PlayerData {
+    pub itsf_lic: i32,
+    pub slug: String,
+    pub first_name: String,
+    pub last_name: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportParams {
+    pub mode: Option<ImportMode>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ChangesParams {
+    pub since: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DownloadStatus {
+    pub running: bool,
+    pub job_id: i64,
+    pub title: String,
+    pub progress: (usize, usize),
+    pub log: Vec<String>,
+}
+
+/// Query of `/download_itsf` and `/download_dtfb`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DownloadParams {
+    /// Defaults to the current year
+    pub year: Option<String>,
+    pub max_rank: Option<usize>,
+    /// `true` downloads players again, even if they are known already
+    pub force: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AddCommentInfo {
+    pub itsf_lic: i32,
+    pub comment: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MergePlayersInfo {
+    pub source_itsf_lic: i32,
+    pub target_itsf_lic: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RevertMergeInfo {
+    pub merge_id: i32,
+}
+
+/// Something that a user can be allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    Read,
+    Comment,
+    Scrape,
+    Admin,
+}
+
+/// Roles are ordered, every role has all permissions of the roles below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    Viewer,
+    Commenter,
+    ScraperOperator,
+    Admin,
+}
+
+impl Role {
+    pub fn allows(self, permission: Permission) -> bool {
+        let required = match permission {
+            Permission::Read => Role::Viewer,
+            Permission::Comment => Role::Commenter,
+            Permission::Scrape => Role::ScraperOperator,
+            Permission::Admin => Role::Admin,
+        };
+        self >= required
+    }
+
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Viewer => "viewer",
+            Self::Commenter => "commenter",
+            Self::ScraperOperator => "scraper-operator",
+            Self::Admin => "admin",
+        }
+    }
+
+    pub fn try_from_str(role: &str) -> std::result::Result<Self, String> {
+        match role {
+            "viewer" => Ok(Self::Viewer),
+            "commenter" => Ok(Self::Commenter),
+            "scraper-operator" => Ok(Self::ScraperOperator),
+            "admin" => Ok(Self::Admin),
+            _ => Err(format!("invalid role: '{}'", role)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UserInfo {
+    pub name: String,
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AddUserInfo {
+    pub name: String,
+    pub password: String,
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RemoveUserInfo {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChangePasswordInfo {
+    /// Defaults to the authenticated user. Only admins may change the passwords of other users.
+    pub name: Option<String>,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LoginInfo {
+    pub name: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SessionInfo {
+    pub name: String,
+    pub role: Role,
+    /// Has to be sent as `X-CSRF-Token` header on all requests that change something
+    pub csrf_token: String,
+}
+
+/// Everything about a token except its hash.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TokenInfo {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<Permission>,
+    pub created: i64,
+    pub created_by: String,
+    pub expires: i64,
+    pub last_used: Option<i64>,
+}
+
+/// A newly created token. This is the only time that the secret is known.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreatedToken {
+    pub token: String,
+    #[serde(flatten)]
+    pub info: TokenInfo,
+}
+
+fn default_token_days() -> i64 {
+    365
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AddTokenInfo {
+    pub name: String,
+    pub scopes: Vec<Permission>,
+    #[serde(default = "default_token_days")]
+    pub valid_days: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RevokeTokenInfo {
+    pub id: String,
+}
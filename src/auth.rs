@@ -0,0 +1,300 @@
+use actix_web::dev::Payload;
+use actix_web::http::header::Header;
+use actix_web::http::Method;
+use actix_web::{web, FromRequest, HttpRequest};
+use actix_web_httpauth::headers::authorization::{Authorization, Basic, Bearer};
+use futures_util::future::LocalBoxFuture;
+
+use crate::api::{Permission, Role, CSRF_HEADER};
+use crate::data::{PrivateField, Redaction};
+use crate::server::errors::ApiError;
+use crate::server::AppState;
+use crate::sessions::SESSION_COOKIE;
+
+#[derive(Debug, Clone)]
+enum Access {
+    /// The credentials were wrong, or the token expired
+    Denied,
+    /// A request with the session cookie didn't include the session's CSRF token
+    MissingCsrfToken,
+    User(Role),
+    Token(Vec<Permission>),
+}
+
+/// The caller of a request, authenticated with basic auth, an API token, or the session cookie of the web UI.
+///
+/// Requests without any credentials are rejected with a basic auth challenge, so that browsers ask for a password.
+/// Requests with wrong credentials are let through, and it is up to the handler to refuse them.
+#[derive(Debug, Clone)]
+pub struct Caller {
+    name: String,
+    is_user: bool,
+    access: Access,
+}
+
+impl Caller {
+    pub fn allows(&self, permission: Permission) -> bool {
+        match &self.access {
+            Access::Denied | Access::MissingCsrfToken => false,
+            Access::User(role) => role.allows(permission),
+            Access::Token(scopes) => scopes.contains(&permission),
+        }
+    }
+
+    /// Refuses callers without the permission: with `401` if their credentials were wrong, `403` otherwise.
+    pub fn require(&self, permission: Permission) -> Result<(), ApiError> {
+        match self.access {
+            _ if self.allows(permission) => Ok(()),
+            Access::Denied => Err(ApiError::unauthenticated()),
+            Access::MissingCsrfToken => Err(ApiError::invalid_csrf_token()),
+            _ => Err(ApiError::forbidden()),
+        }
+    }
+
+    /// User name, or `token:<name>` for API tokens. Used to attribute changes.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The name of the user, if the caller authenticated as one.
+    pub fn user(&self) -> Option<&str> {
+        self.is_user.then_some(self.name.as_str())
+    }
+
+    /// Returns `None` if the request has no credentials at all.
+    async fn authenticate(req: HttpRequest) -> Result<Option<Self>, actix_web::Error> {
+        let state = req
+            .app_data::<web::Data<AppState>>()
+            .cloned()
+            .ok_or_else(|| actix_web::error::ErrorInternalServerError("missing app state"))?;
+
+        let caller = Self::check_credentials(&req, &state).await?;
+        if let Some(caller) = &caller {
+            if !matches!(caller.access, Access::Denied) {
+                state.rate_limits.check_user(&caller.name)?;
+            }
+        }
+        Ok(caller)
+    }
+
+    /// Clients that sent too many wrong passwords or tokens are rejected without checking their credentials.
+    async fn check_credentials(
+        req: &HttpRequest,
+        state: &web::Data<AppState>,
+    ) -> Result<Option<Self>, actix_web::Error> {
+        let client = state.rate_limits.client_ip(req);
+
+        if let Ok(auth) = Authorization::<Bearer>::parse(req) {
+            state.rate_limits.logins.check(&client)?;
+            // updating the last use of the token writes the tokens file
+            let (tokens, secret) = (state.clone(), auth.as_ref().token().to_string());
+            let token = web::block(move || tokens.tokens.authenticate(&secret)).await?;
+            return Ok(Some(match token {
+                Some((name, scopes)) => Self {
+                    name: format!("token:{}", name),
+                    is_user: false,
+                    access: Access::Token(scopes),
+                },
+                None => {
+                    state.rate_limits.logins.record_failure(&client);
+                    Self {
+                        name: String::from("token:?"),
+                        is_user: false,
+                        access: Access::Denied,
+                    }
+                }
+            }));
+        }
+
+        let auth = match Authorization::<Basic>::parse(req) {
+            Ok(auth) => auth,
+            Err(_) => match req.cookie(SESSION_COOKIE) {
+                Some(cookie) => return Ok(Some(Self::from_session(req, state, cookie.value()))),
+                None => return Ok(None),
+            },
+        };
+        let auth = auth.as_ref();
+        state.rate_limits.logins.check(&client)?;
+        let name = auth.user_id().to_string();
+        let role = match auth.password() {
+            // verifying the password takes a while, which would block other requests on this thread
+            Some(password) => {
+                let (users, name, password) = (state.clone(), name.clone(), password.to_string());
+                web::block(move || users.users.authenticate(&name, &password)).await?
+            }
+            None => None,
+        };
+        if role.is_none() {
+            state.rate_limits.logins.record_failure(&client);
+        }
+        Ok(Some(Self {
+            name,
+            is_user: role.is_some(),
+            access: role.map_or(Access::Denied, Access::User),
+        }))
+    }
+
+    /// Browsers send the session cookie along with every request, so requests that change something
+    /// also have to include the CSRF token, which other sites can't read.
+    fn from_session(req: &HttpRequest, state: &AppState, session_id: &str) -> Self {
+        let session = state.sessions.get(session_id);
+        let name = session.as_ref().map(|(user, _)| user.clone()).unwrap_or_default();
+        let denied = Self {
+            name: name.clone(),
+            is_user: false,
+            access: Access::Denied,
+        };
+        let Some((user, csrf_token)) = session else {
+            return denied;
+        };
+
+        let is_safe = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+        if !is_safe && !has_csrf_token(req, &csrf_token) {
+            return Self {
+                access: Access::MissingCsrfToken,
+                ..denied
+            };
+        }
+
+        // the user may have been removed or changed since logging in
+        match state.users.role(&user) {
+            Some(role) => Self {
+                name: user,
+                is_user: true,
+                access: Access::User(role),
+            },
+            None => denied,
+        }
+    }
+}
+
+/// Whether the request sent the CSRF token of its session.
+pub(crate) fn has_csrf_token(req: &HttpRequest, csrf_token: &str) -> bool {
+    let header = req.headers().get(CSRF_HEADER).and_then(|value| value.to_str().ok());
+    let valid = header.is_some_and(|header| constant_time_eq(header.as_bytes(), csrf_token.as_bytes()));
+    if !valid {
+        log::warn!("Missing or wrong CSRF token for {} {}", req.method(), req.path());
+    }
+    valid
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+impl FromRequest for Caller {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let caller = Self::authenticate(req.clone());
+        Box::pin(async move { caller.await?.ok_or_else(|| ApiError::unauthenticated().into()) })
+    }
+}
+
+/// The caller of an endpoint that can also be used without credentials, `None` if the request has none.
+///
+/// Unlike `Option<Caller>`, which turns every error into `None`, this keeps the errors of requests with
+/// credentials, so that callers over their rate limit are refused instead of being served anonymously.
+pub struct MaybeCaller(pub Option<Caller>);
+
+impl FromRequest for MaybeCaller {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let caller = Caller::authenticate(req.clone());
+        Box::pin(async move { caller.await.map(MaybeCaller) })
+    }
+}
+
+/// Decides which player data can be read by callers without the `read` permission.
+pub struct PrivacyRules {
+    /// Callers without the `read` permission can't read anything
+    require_auth: bool,
+    /// Fields that are hidden from callers without the `read` permission
+    hidden: Vec<PrivateField>,
+}
+
+impl PrivacyRules {
+    pub fn new(require_auth: bool, hidden: Vec<PrivateField>) -> Self {
+        Self { require_auth, hidden }
+    }
+
+    /// Returns the fields that have to be hidden from the caller, or `None` if it may not read at all.
+    pub fn redaction(&self, caller: Option<&Caller>) -> Option<Redaction> {
+        if caller.is_some_and(|caller| caller.allows(Permission::Read)) {
+            Some(Redaction::default())
+        } else if self.require_auth {
+            None
+        } else {
+            let hidden = self.hidden.iter().copied().chain([PrivateField::ChangeAuthors]);
+            Some(Redaction::new(hidden))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caller(access: Access) -> Caller {
+        Caller {
+            name: String::from("anna"),
+            is_user: true,
+            access,
+        }
+    }
+
+    #[test]
+    fn callers_without_read_permission_get_redacted_data() {
+        let rules = PrivacyRules::new(false, vec![PrivateField::BirthYear]);
+        let reader = caller(Access::Token(vec![Permission::Read]));
+        assert_eq!(rules.redaction(Some(&reader)), Some(Redaction::default()));
+
+        let expected = Redaction::new([PrivateField::BirthYear, PrivateField::ChangeAuthors]);
+        assert_eq!(rules.redaction(None), Some(expected.clone()));
+        let admin_token = caller(Access::Token(vec![Permission::Admin]));
+        assert_eq!(rules.redaction(Some(&admin_token)), Some(expected.clone()));
+        assert_eq!(rules.redaction(Some(&caller(Access::Denied))), Some(expected));
+
+        let closed = PrivacyRules::new(true, Vec::new());
+        assert_eq!(closed.redaction(None), None);
+        assert_eq!(
+            closed.redaction(Some(&caller(Access::User(Role::Viewer)))),
+            Some(Redaction::default())
+        );
+    }
+
+    #[test]
+    fn checks_the_csrf_token() {
+        let request = |token: Option<&str>| {
+            let req = actix_web::test::TestRequest::post().uri("/logout");
+            match token {
+                Some(token) => req.insert_header((CSRF_HEADER, token)),
+                None => req,
+            }
+            .to_http_request()
+        };
+        assert!(has_csrf_token(&request(Some("secret")), "secret"));
+        assert!(!has_csrf_token(&request(Some("secreT")), "secret"));
+        assert!(!has_csrf_token(&request(Some("")), "secret"));
+        assert!(!has_csrf_token(&request(None), "secret"));
+    }
+
+    #[test]
+    fn require_explains_refusals() {
+        let status = |caller: Caller, permission| match caller.require(permission) {
+            Ok(()) => 200,
+            Err(err) => actix_web::ResponseError::status_code(&err).as_u16(),
+        };
+        assert_eq!(status(caller(Access::User(Role::Commenter)), Permission::Comment), 200);
+        assert_eq!(status(caller(Access::User(Role::Commenter)), Permission::Scrape), 403);
+        assert_eq!(
+            status(caller(Access::Token(vec![Permission::Admin])), Permission::Read),
+            403
+        );
+        assert_eq!(status(caller(Access::Denied), Permission::Read), 401);
+        assert_eq!(status(caller(Access::MissingCsrfToken), Permission::Comment), 403);
+    }
+}
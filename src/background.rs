@@ -1,21 +1,51 @@
+//! Progress and log of long-running jobs, so that clients can follow them while they run.
+
+use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::{Arc, Mutex, Weak};
 
+use crate::data::ChangeSource;
+
+static LAST_JOB_ID: AtomicI64 = AtomicI64::new(0);
+
+/// Job IDs are based on the start time, so that they stay unique across server restarts.
+fn next_job_id() -> i64 {
+    let now = chrono::Utc::now().timestamp_millis();
+    let previous = LAST_JOB_ID
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(now.max(last + 1)))
+        .expect("failed to update job ID");
+    now.max(previous + 1)
+}
+
 struct BackgroundOperationInner {
     progress: usize,
     max: usize,
     log: Vec<String>,
 }
 
+/// Shared between a job and the clients that follow it. The job ends when its last `Arc` is dropped.
 pub struct BackgroundOperationProgress {
+    id: i64,
     title: String,
     inner: Mutex<BackgroundOperationInner>,
 }
 
 impl BackgroundOperationProgress {
+    pub fn get_id(&self) -> i64 {
+        self.id
+    }
+
     pub fn get_title(&self) -> &str {
         &self.title
     }
 
+    /// The source that is recorded in the history of all players modified by this operation.
+    pub fn change_source(&self) -> ChangeSource {
+        ChangeSource::Scrape {
+            job_id: self.id,
+            job: self.title.clone(),
+        }
+    }
+
     pub fn get_progress(&self) -> (usize, usize) {
         let inner = self.inner.lock().expect("failed to lock mutex");
         (inner.progress, inner.max)
@@ -38,13 +68,9 @@ impl BackgroundOperationProgress {
         inner.log.push(entry);
     }
 
-    pub fn has_finished(&self) -> bool {
-        let progress = self.get_progress();
-        progress.0 >= progress.1
-    }
-
     pub fn new(title: &str, max: usize) -> (Arc<BackgroundOperationProgress>, Weak<BackgroundOperationProgress>) {
         let this = BackgroundOperationProgress {
+            id: next_job_id(),
             title: title.into(),
             inner: Mutex::new(BackgroundOperationInner {
                 progress: 0,
@@ -0,0 +1,118 @@
+use actix_web::http::header::{
+    CacheControl, CacheDirective, ETag, EntityTag, Header, HttpDate, IfModifiedSince, IfNoneMatch, LastModified,
+};
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Cache validators of a response, used to answer conditional requests with `304 Not Modified`.
+pub struct Validators {
+    etag: EntityTag,
+    last_modified: SystemTime,
+}
+
+impl Validators {
+    pub fn new(etag: String, last_modified: SystemTime) -> Self {
+        Self {
+            etag: EntityTag::new_strong(etag),
+            last_modified,
+        }
+    }
+
+    pub fn from_timestamp(etag: String, timestamp: i64) -> Self {
+        let last_modified = UNIX_EPOCH + Duration::from_secs(timestamp.max(0) as u64);
+        Self::new(etag, last_modified)
+    }
+
+    fn is_modified(&self, req: &HttpRequest) -> bool {
+        // If-None-Match takes precedence over If-Modified-Since, see RFC 7232, section 6
+        if req.headers().contains_key(IfNoneMatch::name()) {
+            return match IfNoneMatch::parse(req) {
+                Ok(IfNoneMatch::Any) => false,
+                Ok(IfNoneMatch::Items(etags)) => !etags.iter().any(|etag| etag.weak_eq(&self.etag)),
+                Err(_) => true,
+            };
+        }
+
+        if let Ok(IfModifiedSince(since)) = IfModifiedSince::parse(req) {
+            // HTTP dates only have a resolution of seconds
+            let last_modified = HttpDate::from(self.last_modified);
+            return SystemTime::from(last_modified) > SystemTime::from(since);
+        }
+
+        true
+    }
+
+    /// Returns a `304 Not Modified` response if the client's cached copy is still valid.
+    pub fn check(&self, req: &HttpRequest) -> Option<HttpResponse> {
+        if self.is_modified(req) {
+            None
+        } else {
+            let mut response = HttpResponse::NotModified();
+            self.apply(&mut response);
+            Some(response.finish())
+        }
+    }
+
+    /// Adds the validator headers to a response. Caches have to revalidate before re-using it.
+    pub fn apply(&self, response: &mut HttpResponseBuilder) {
+        response
+            .insert_header(ETag(self.etag.clone()))
+            .insert_header(LastModified(self.last_modified.into()))
+            .insert_header(CacheControl(vec![CacheDirective::NoCache]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test::TestRequest;
+
+    fn validators() -> Validators {
+        Validators::from_timestamp(String::from("players-7-all"), 1_700_000_000)
+    }
+
+    #[test]
+    fn matching_etag_is_not_modified() {
+        let req = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, "\"players-6-all\", \"players-7-all\""))
+            .to_http_request();
+        let response = validators().check(&req).unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers().get(header::ETAG).unwrap(), "\"players-7-all\"");
+
+        let weak = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, "W/\"players-7-all\""))
+            .to_http_request();
+        assert!(validators().check(&weak).is_some());
+        let any = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, "*"))
+            .to_http_request();
+        assert!(validators().check(&any).is_some());
+    }
+
+    #[test]
+    fn etag_takes_precedence_over_date() {
+        let later = HttpDate::from(UNIX_EPOCH + Duration::from_secs(1_800_000_000)).to_string();
+        let req = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, "\"players-6-all\""))
+            .insert_header((header::IF_MODIFIED_SINCE, later.as_str()))
+            .to_http_request();
+        assert!(validators().check(&req).is_none());
+    }
+
+    #[test]
+    fn modified_since() {
+        let not_modified = |timestamp: u64| {
+            let date = HttpDate::from(UNIX_EPOCH + Duration::from_secs(timestamp)).to_string();
+            let req = TestRequest::default()
+                .insert_header((header::IF_MODIFIED_SINCE, date.as_str()))
+                .to_http_request();
+            validators().check(&req).is_some()
+        };
+        assert!(not_modified(1_700_000_000));
+        assert!(not_modified(1_700_000_001));
+        assert!(!not_modified(1_699_999_999));
+        assert!(validators().check(&TestRequest::default().to_http_request()).is_none());
+    }
+}
@@ -0,0 +1,306 @@
+//! Maintenance commands, which work directly on the DB and the users file instead of through the server.
+
+#[cfg(feature = "scraping")]
+use chrono::Datelike;
+use clap::{Args, Subcommand};
+use serde::de::value::{Error as ValueError, StrDeserializer};
+use serde::Deserialize;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+use crate::api::Role;
+use crate::config::Config;
+#[cfg(feature = "scraping")]
+use crate::data::itsf;
+use crate::data::{self, ExportFormat, ExportTable, ImportMode};
+#[cfg(feature = "scraping")]
+use crate::scraping::{self, DownloadOptions};
+use crate::users::UserStore;
+
+/// Recorded as the user of changes that are made from the command line
+const CLI_USER: &str = "cli";
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Runs the web server (the default)
+    Serve,
+    /// Applies pending DB migrations
+    Migrate,
+    /// Downloads rankings and the profiles of their players
+    #[cfg(feature = "scraping")]
+    #[command(subcommand)]
+    Scrape(ScrapeCommand),
+    /// Writes a zip archive of the DB and images, or a table of player data
+    Export(ExportArgs),
+    /// Imports a zip archive that was written by `export` or `/db.zip`
+    Import {
+        archive: PathBuf,
+        #[arg(long, default_value = "merge", value_parser = parse_value::<ImportMode>)]
+        mode: ImportMode,
+    },
+    #[command(subcommand)]
+    Player(PlayerCommand),
+    #[command(subcommand)]
+    Users(UsersCommand),
+}
+
+#[cfg(feature = "scraping")]
+#[derive(Debug, Subcommand)]
+pub enum ScrapeCommand {
+    Itsf {
+        #[command(flatten)]
+        download: DownloadArgs,
+        /// Comma-separated ranking categories, all by default
+        #[arg(long, value_delimiter = ',', value_parser = parse_value::<itsf::RankingCategory>)]
+        categories: Vec<itsf::RankingCategory>,
+        /// Comma-separated ranking classes, all by default
+        #[arg(long, value_delimiter = ',', value_parser = parse_value::<itsf::RankingClass>)]
+        classes: Vec<itsf::RankingClass>,
+    },
+    Dtfb {
+        #[command(flatten)]
+        download: DownloadArgs,
+    },
+}
+
+#[cfg(feature = "scraping")]
+#[derive(Debug, Args)]
+pub struct DownloadArgs {
+    /// A year, or an inclusive range like `2019..2024`. Defaults to the current year
+    #[arg(long)]
+    years: Option<String>,
+    /// Only players up to this place of each ranking are downloaded
+    #[arg(long)]
+    max_rank: Option<usize>,
+    /// Downloads players again, even if they are known already
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// Output file, or standard output if missing
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+    /// Exports one table of player data instead of the whole DB
+    #[arg(long, value_parser = parse_value::<ExportTable>)]
+    table: Option<ExportTable>,
+    #[arg(long, default_value = "csv", value_parser = parse_value::<ExportFormat>)]
+    format: ExportFormat,
+    /// Only exports players of this country, e.g. `GER`
+    #[arg(long)]
+    country: Option<String>,
+    /// Only exports players of this ITSF category, e.g. `men` or `junior_female`
+    #[arg(long)]
+    category: Option<String>,
+    /// Only exports players whose name contains this
+    #[arg(long)]
+    name: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum PlayerCommand {
+    /// Prints a player as JSON
+    Show { itsf_id: i32 },
+    /// Merges the source player into the target player
+    Merge { source_itsf_id: i32, target_itsf_id: i32 },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum UsersCommand {
+    /// Adds a user, whose password is read from standard input
+    Add {
+        name: String,
+        #[arg(long, default_value = "viewer", value_parser = Role::try_from_str)]
+        role: Role,
+    },
+}
+
+/// Parses the values of enums that are deserialized from their snake_case names.
+fn parse_value<T: for<'de> Deserialize<'de>>(value: &str) -> Result<T, String> {
+    T::deserialize(StrDeserializer::<ValueError>::new(value)).map_err(|err| err.to_string())
+}
+
+#[cfg(feature = "scraping")]
+impl DownloadArgs {
+    fn years(&self, min_year: i32) -> Result<Vec<i32>, String> {
+        let curr_year = chrono::Utc::now().naive_local().year();
+        let (first, last) = match &self.years {
+            None => (curr_year, curr_year),
+            Some(years) => {
+                let parse = |year: &str| {
+                    year.trim()
+                        .parse::<i32>()
+                        .map_err(|_| format!("invalid year: '{}'", year))
+                };
+                match years.split_once("..") {
+                    Some((first, last)) => (parse(first)?, parse(last)?),
+                    None => (parse(years)?, parse(years)?),
+                }
+            }
+        };
+        if first < min_year || last > curr_year || first > last {
+            return Err(format!("years must be between {} and {}", min_year, curr_year));
+        }
+        Ok((first..=last).collect())
+    }
+
+    fn options(&self, config: &Config) -> DownloadOptions {
+        DownloadOptions {
+            max_rank: self.max_rank.unwrap_or(config.scraping.max_rank),
+            force: self.force,
+            max_concurrent: config.scraping.max_concurrent,
+        }
+    }
+}
+
+fn load_database(config: &Config) -> Result<data::DatabaseRef, String> {
+    data::DatabaseRef::load(&config.storage.database, &config.storage.images)
+        .map_err(|err| format!("Failed to load DB: {}", err))
+}
+
+/// Commands that change the DB are refused while the server runs, since it would overwrite their changes.
+fn lock_database(config: &Config) -> Result<data::DatabaseLock, String> {
+    data::DatabaseLock::acquire(&config.storage.database)
+        .map_err(|err| format!("{}; stop it first, or use the API of the server", err))
+}
+
+fn print_json<T: serde::Serialize>(value: &T) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(value).map_err(|err| err.to_string())?;
+    writeln!(std::io::stdout(), "{}", json).map_err(|err| err.to_string())
+}
+
+/// Runs a command directly on the DB and the users file. Commands that change the DB fail while the server runs;
+/// the users file is re-read by the server when it changes.
+pub async fn run(command: Command, config: &Config) -> Result<(), String> {
+    match command {
+        Command::Serve => unreachable!("the server is started by main"),
+        Command::Migrate => {
+            let _lock = lock_database(config)?;
+            let versions = data::migrate_database(&config.storage.database).map_err(|err| err.to_string())?;
+            if versions.is_empty() {
+                println!("DB is up to date");
+            }
+            for version in versions {
+                println!("Applied migration {}", version);
+            }
+            Ok(())
+        }
+        #[cfg(feature = "scraping")]
+        Command::Scrape(ScrapeCommand::Itsf {
+            download,
+            mut categories,
+            mut classes,
+        }) => {
+            let years = download.years(config.scraping.min_year)?;
+            if categories.is_empty() {
+                categories = vec![
+                    itsf::RankingCategory::Open,
+                    itsf::RankingCategory::Women,
+                    itsf::RankingCategory::Senior,
+                    itsf::RankingCategory::Junior,
+                ];
+            }
+            if classes.is_empty() {
+                classes = vec![
+                    itsf::RankingClass::Singles,
+                    itsf::RankingClass::Doubles,
+                    itsf::RankingClass::Combined,
+                ];
+            }
+            let _lock = lock_database(config)?;
+            let db = load_database(config)?;
+            scraping::run_itsf_rankings_download(&db, years, categories, classes, download.options(config))
+                .await
+                .map_err(|err| err.to_string())
+        }
+        #[cfg(feature = "scraping")]
+        Command::Scrape(ScrapeCommand::Dtfb { download }) => {
+            let seasons = download.years(config.scraping.min_year)?;
+            let _lock = lock_database(config)?;
+            let db = load_database(config)?;
+            scraping::run_dtfb_rankings_download(db, seasons, download.options(config))
+                .await
+                .map_err(|err| err.to_string())
+        }
+        Command::Export(args) => export(args, config),
+        Command::Import { archive, mode } => {
+            let _lock = lock_database(config)?;
+            let db = load_database(config)?;
+            let source = data::ChangeSource::Import {
+                user: String::from(CLI_USER),
+            };
+            let report = db
+                .import_zip_archive(&archive, mode, &source)
+                .map_err(|err| err.to_string())?;
+            print_json(&report)
+        }
+        Command::Player(PlayerCommand::Show { itsf_id }) => {
+            let db = load_database(config)?;
+            match db.get_player(itsf_id).map_err(|err| err.to_string())? {
+                Some(player) => print_json(&player),
+                None => match db.get_redirect(itsf_id).map_err(|err| err.to_string())? {
+                    Some(target) => Err(format!("Player {} was merged into {}", itsf_id, target)),
+                    None => Err(format!("Player {} not found", itsf_id)),
+                },
+            }
+        }
+        Command::Player(PlayerCommand::Merge {
+            source_itsf_id,
+            target_itsf_id,
+        }) => {
+            let _lock = lock_database(config)?;
+            let db = load_database(config)?;
+            let merge = db
+                .merge_players(source_itsf_id, target_itsf_id, CLI_USER)
+                .map_err(|err| err.to_string())?;
+            print_json(&merge)
+        }
+        Command::Users(UsersCommand::Add { name, role }) => add_user(&name, role, config),
+    }
+}
+
+fn export(args: ExportArgs, config: &Config) -> Result<(), String> {
+    let db = load_database(config)?;
+    let writer: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(std::fs::File::create(path).map_err(|err| format!("{}: {}", path.display(), err))?),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let writer = std::io::BufWriter::new(writer);
+    let redaction = data::Redaction::default();
+
+    let result = match args.table {
+        Some(table) => {
+            let params = data::PlayerFilterParams {
+                country: args.country,
+                category: args.category,
+                name: args.name,
+            };
+            let filter = data::PlayerFilter::from_params(&params)?;
+            db.write_export(writer, table, args.format, &filter, &redaction)
+        }
+        None => db.write_zip_archive(writer, &redaction),
+    };
+    result.map_err(|err| err.to_string())
+}
+
+/// The users file is created if it doesn't exist yet, so that the first admin can be added with this.
+fn add_user(name: &str, role: Role, config: &Config) -> Result<(), String> {
+    let path = &config.auth.users_file;
+    if !std::path::Path::new(path).exists() {
+        std::fs::File::create(path).map_err(|err| format!("{}: {}", path, err))?;
+    }
+    let users = UserStore::open(path).map_err(|err| err.to_string())?;
+
+    eprint!("Password for {}: ", name);
+    let mut password = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut password)
+        .map_err(|err| err.to_string())?;
+    let password = password.trim_end_matches(['\r', '\n']);
+
+    users.add(name, password, role).map_err(|err| err.to_string())?;
+    println!("Added user {}", name);
+    Ok(())
+}
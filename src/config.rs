@@ -0,0 +1,486 @@
+//! Configuration of the server, read from the command line, a TOML file and the environment.
+
+use chrono::Datelike;
+use clap::Parser;
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::cli::Command;
+use crate::data::PrivateField;
+
+/// Command line options, which override the config file.
+#[derive(Debug, Default, Parser)]
+#[command(about = "Server of the ITSF player database")]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// TOML config file
+    #[arg(long, short, global = true)]
+    pub config: Option<String>,
+    #[arg(long, global = true)]
+    pub bind_address: Option<IpAddr>,
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+    #[arg(long, global = true)]
+    pub html_root: Option<String>,
+    /// SQLite DB file
+    #[arg(long, global = true)]
+    pub database: Option<String>,
+    /// Directory of the player images
+    #[arg(long, global = true)]
+    pub images: Option<String>,
+    #[arg(long, global = true)]
+    pub users_file: Option<String>,
+    #[arg(long, global = true)]
+    pub tokens_file: Option<String>,
+}
+
+// The sections of the config file. Every value is optional, and falls back to the environment or a default.
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServerSection {
+    bind_address: Option<IpAddr>,
+    port: Option<u16>,
+    html_root: Option<String>,
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+    http_redirect_port: Option<u16>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StorageSection {
+    database: Option<String>,
+    images: Option<String>,
+    max_import_mb: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ScrapingSection {
+    max_concurrent: Option<usize>,
+    min_year: Option<i32>,
+    max_rank: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AuthSection {
+    users_file: Option<String>,
+    tokens_file: Option<String>,
+    read_requires_auth: Option<bool>,
+    private_fields: Option<Vec<String>>,
+    rate_limit_per_ip: Option<u32>,
+    rate_limit_per_user: Option<u32>,
+    rate_limit_expensive: Option<u32>,
+    max_login_failures: Option<u32>,
+    trusted_proxies: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SchedulesSection {
+    itsf_hours: Option<u64>,
+    dtfb_hours: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    server: ServerSection,
+    storage: StorageSection,
+    scraping: ScrapingSection,
+    auth: AuthSection,
+    schedules: SchedulesSection,
+}
+
+/// PEM files with the certificate chain and the private key, which may also be the same file.
+#[derive(Debug, Clone)]
+pub struct TlsFiles {
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    pub html_root: String,
+    pub tls: Option<TlsFiles>,
+    /// Port of a plain HTTP listener that redirects to HTTPS
+    pub http_redirect_port: Option<u16>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StorageConfig {
+    pub database: String,
+    pub images: String,
+    /// Largest archive that `/import` accepts, in bytes
+    pub max_import_size: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScrapingConfig {
+    /// Number of players that are downloaded at the same time
+    pub max_concurrent: usize,
+    /// Oldest year whose rankings can be downloaded
+    pub min_year: i32,
+    /// Default number of players that are downloaded from each ranking
+    pub max_rank: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    pub users_file: String,
+    pub tokens_file: String,
+    pub read_requires_auth: bool,
+    pub private_fields: Vec<PrivateField>,
+    /// Requests per minute, 0 disables a limit
+    pub rate_limit_per_ip: u32,
+    pub rate_limit_per_user: u32,
+    pub rate_limit_expensive: u32,
+    pub max_login_failures: u32,
+    /// Reverse proxies whose `X-Forwarded-For` header is used to find the IP address of clients
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+/// Intervals of automatic downloads of the current year's rankings, 0 disables them
+#[derive(Debug, Clone)]
+pub struct SchedulesConfig {
+    pub itsf_hours: u64,
+    pub dtfb_hours: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub storage: StorageConfig,
+    pub scraping: ScrapingConfig,
+    pub auth: AuthConfig,
+    pub schedules: SchedulesConfig,
+}
+
+/// Collects all problems, so that they can be reported at once.
+#[derive(Default)]
+struct Problems(Vec<String>);
+
+impl Problems {
+    /// Returns the first value that is set: command line, config file, environment variable.
+    fn value<T: FromStr>(&mut self, cli: Option<T>, file: Option<T>, env: &str) -> Option<T> {
+        cli.or(file).or_else(|| {
+            let value = std::env::var(env).ok()?;
+            match value.parse() {
+                Ok(value) => Some(value),
+                Err(_) => {
+                    self.0.push(format!("invalid {}: '{}'", env, value));
+                    None
+                }
+            }
+        })
+    }
+
+    fn check(&mut self, ok: bool, problem: impl FnOnce() -> String) {
+        if !ok {
+            self.0.push(problem());
+        }
+    }
+}
+
+fn is_dir(path: &str) -> bool {
+    Path::new(path).is_dir()
+}
+
+fn is_file(path: &str) -> bool {
+    Path::new(path).is_file()
+}
+
+impl Config {
+    /// Reads the config file given on the command line, if any, and validates all values.
+    /// Values that are missing in the file are taken from the environment, e.g. a `.env` file.
+    pub fn load(args: &Args) -> Result<Self, Vec<String>> {
+        let file = match &args.config {
+            Some(path) => {
+                let content = std::fs::read_to_string(path).map_err(|err| vec![format!("{}: {}", path, err)])?;
+                toml::from_str(&content).map_err(|err| vec![format!("{}: {}", path, err)])?
+            }
+            None => ConfigFile::default(),
+        };
+        Self::from_file(args, file)
+    }
+
+    fn from_file(args: &Args, file: ConfigFile) -> Result<Self, Vec<String>> {
+        let mut problems = Problems::default();
+        let p = &mut problems;
+
+        // CERT_PEM is the combined certificate and key file of older versions
+        let tls_cert = p
+            .value(None, file.server.tls_cert, "TLS_CERT")
+            .or_else(|| std::env::var("CERT_PEM").ok());
+        let tls_key = p.value(None, file.server.tls_key, "TLS_KEY");
+        let server = ServerConfig {
+            bind_address: p
+                .value(args.bind_address, file.server.bind_address, "BIND_ADDRESS")
+                .unwrap_or(IpAddr::from([0, 0, 0, 0])),
+            port: p.value(args.port, file.server.port, "SERVER_PORT").unwrap_or(8080),
+            html_root: p
+                .value(args.html_root.clone(), file.server.html_root, "HTML_ROOT")
+                .unwrap_or_else(|| String::from("html/")),
+            tls: tls_cert.map(|cert_path| TlsFiles {
+                key_path: tls_key.clone().unwrap_or_else(|| cert_path.clone()),
+                cert_path,
+            }),
+            http_redirect_port: p.value(None, file.server.http_redirect_port, "HTTP_REDIRECT_PORT"),
+        };
+
+        let max_import_mb = p
+            .value(None, file.storage.max_import_mb, "MAX_IMPORT_MB")
+            .unwrap_or(1024);
+        let max_import_size = max_import_mb.checked_mul(1024 * 1024).unwrap_or_else(|| {
+            p.0.push(format!("storage.max_import_mb: {} is too large", max_import_mb));
+            0
+        });
+        let storage = StorageConfig {
+            database: p
+                .value(args.database.clone(), file.storage.database, "DATABASE_URL")
+                .unwrap_or_else(|| String::from("db.sqlite")),
+            images: p
+                .value(args.images.clone(), file.storage.images, "IMAGE_PATH")
+                .unwrap_or_else(|| String::from("images/")),
+            max_import_size,
+        };
+
+        let scraping = ScrapingConfig {
+            max_concurrent: p
+                .value(None, file.scraping.max_concurrent, "MAX_CONCURRENT_DOWNLOADS")
+                .unwrap_or(5),
+            min_year: p.value(None, file.scraping.min_year, "MIN_YEAR").unwrap_or(2010),
+            max_rank: p.value(None, file.scraping.max_rank, "MAX_RANK").unwrap_or(1000),
+        };
+
+        let private_fields = match file.auth.private_fields {
+            Some(fields) => fields,
+            None => std::env::var("PRIVATE_FIELDS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|field| field.trim().to_string())
+                .filter(|field| !field.is_empty())
+                .collect(),
+        };
+        let private_fields = private_fields
+            .iter()
+            .filter_map(|field| PrivateField::try_from_str(field).map_err(|err| p.0.push(err)).ok())
+            .collect();
+        let trusted_proxies = match file.auth.trusted_proxies {
+            Some(proxies) => proxies,
+            None => std::env::var("TRUSTED_PROXIES")
+                .unwrap_or_default()
+                .split(',')
+                .map(|proxy| proxy.trim().to_string())
+                .filter(|proxy| !proxy.is_empty())
+                .collect(),
+        };
+        let trusted_proxies = trusted_proxies
+            .iter()
+            .filter_map(|proxy| {
+                proxy
+                    .parse()
+                    .map_err(|_| p.0.push(format!("invalid TRUSTED_PROXIES: '{}'", proxy)))
+                    .ok()
+            })
+            .collect();
+        let auth = AuthConfig {
+            users_file: p
+                .value(args.users_file.clone(), file.auth.users_file, "USERS_FILE")
+                .unwrap_or_else(|| String::from("users.txt")),
+            tokens_file: p
+                .value(args.tokens_file.clone(), file.auth.tokens_file, "TOKENS_FILE")
+                .unwrap_or_else(|| String::from("tokens.json")),
+            read_requires_auth: p
+                .value(None, file.auth.read_requires_auth, "READ_REQUIRES_AUTH")
+                .unwrap_or(false),
+            private_fields,
+            rate_limit_per_ip: p
+                .value(None, file.auth.rate_limit_per_ip, "RATE_LIMIT_PER_IP")
+                .unwrap_or(300),
+            rate_limit_per_user: p
+                .value(None, file.auth.rate_limit_per_user, "RATE_LIMIT_PER_USER")
+                .unwrap_or(600),
+            rate_limit_expensive: p
+                .value(None, file.auth.rate_limit_expensive, "RATE_LIMIT_EXPENSIVE")
+                .unwrap_or(6),
+            max_login_failures: p
+                .value(None, file.auth.max_login_failures, "MAX_LOGIN_FAILURES")
+                .unwrap_or(10),
+            trusted_proxies,
+        };
+
+        let schedules = SchedulesConfig {
+            itsf_hours: p
+                .value(None, file.schedules.itsf_hours, "ITSF_SCHEDULE_HOURS")
+                .unwrap_or(0),
+            dtfb_hours: p
+                .value(None, file.schedules.dtfb_hours, "DTFB_SCHEDULE_HOURS")
+                .unwrap_or(0),
+        };
+
+        let config = Self {
+            server,
+            storage,
+            scraping,
+            auth,
+            schedules,
+        };
+        // the other commands don't need the server's files
+        let serving = matches!(args.command, None | Some(Command::Serve));
+        config.validate(&mut problems, tls_key.is_some(), serving);
+        if problems.0.is_empty() {
+            Ok(config)
+        } else {
+            Err(problems.0)
+        }
+    }
+
+    fn validate(&self, p: &mut Problems, has_tls_key: bool, serving: bool) {
+        if serving {
+            self.validate_server(p, has_tls_key);
+            p.check(is_file(&self.auth.users_file), || {
+                format!("auth.users_file: {} does not exist", self.auth.users_file)
+            });
+        }
+
+        let storage = &self.storage;
+        let database_dir = Path::new(&storage.database)
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        p.check(database_dir.is_dir(), || {
+            format!("storage.database: directory of {} does not exist", storage.database)
+        });
+        p.check(is_dir(&storage.images), || {
+            format!("storage.images: {} is not a directory", storage.images)
+        });
+
+        let scraping = &self.scraping;
+        p.check(scraping.max_concurrent >= 1, || {
+            String::from("scraping.max_concurrent must be at least 1")
+        });
+        let current_year = chrono::Utc::now().year();
+        p.check(scraping.min_year <= current_year, || {
+            format!("scraping.min_year must not be after {}", current_year)
+        });
+        p.check(scraping.max_rank >= 1, || {
+            String::from("scraping.max_rank must be at least 1")
+        });
+
+        let schedules = &self.schedules;
+        p.check(
+            cfg!(feature = "scraping") || (schedules.itsf_hours == 0 && schedules.dtfb_hours == 0),
+            || String::from("schedules: downloads need the `scraping` feature"),
+        );
+    }
+
+    fn validate_server(&self, p: &mut Problems, has_tls_key: bool) {
+        let server = &self.server;
+        p.check(is_dir(&server.html_root), || {
+            format!("server.html_root: {} is not a directory", server.html_root)
+        });
+        match &server.tls {
+            Some(tls) => {
+                p.check(cfg!(feature = "tls"), || {
+                    String::from("server.tls_cert: TLS needs the `tls` feature")
+                });
+                p.check(is_file(&tls.cert_path), || {
+                    format!("server.tls_cert: {} does not exist", tls.cert_path)
+                });
+                p.check(is_file(&tls.key_path), || {
+                    format!("server.tls_key: {} does not exist", tls.key_path)
+                });
+            }
+            None => {
+                p.check(!has_tls_key, || {
+                    String::from("server.tls_key is set without server.tls_cert")
+                });
+                p.check(server.http_redirect_port.is_none(), || {
+                    String::from("server.http_redirect_port needs TLS to be configured")
+                });
+            }
+        }
+        p.check(server.http_redirect_port != Some(server.port), || {
+            String::from("server.http_redirect_port must differ from server.port")
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_toml(args: &Args, content: &str) -> Result<Config, Vec<String>> {
+        Config::from_file(args, toml::from_str(content).unwrap())
+    }
+
+    /// Args with existing directories and files, so that only the problems of a test are reported.
+    fn valid_args(dir: &tempfile::TempDir) -> Args {
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        std::fs::write(path("users.txt"), "").unwrap();
+        Args {
+            html_root: Some(path("")),
+            database: Some(path("db.sqlite")),
+            images: Some(path("")),
+            users_file: Some(path("users.txt")),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn command_line_overrides_file_overrides_environment() {
+        let mut p = Problems::default();
+        std::env::set_var("ITSF_PLAYERDB_TEST_VALUE", "3");
+        assert_eq!(p.value(Some(1), Some(2), "ITSF_PLAYERDB_TEST_VALUE"), Some(1));
+        assert_eq!(p.value(None, Some(2), "ITSF_PLAYERDB_TEST_VALUE"), Some(2));
+        assert_eq!(p.value(None::<u16>, None, "ITSF_PLAYERDB_TEST_VALUE"), Some(3));
+        assert_eq!(p.value(None::<u16>, None, "ITSF_PLAYERDB_TEST_UNSET"), None);
+        assert!(p.0.is_empty());
+
+        std::env::set_var("ITSF_PLAYERDB_TEST_INVALID", "many");
+        assert_eq!(p.value(None::<u16>, None, "ITSF_PLAYERDB_TEST_INVALID"), None);
+        assert_eq!(p.0, vec!["invalid ITSF_PLAYERDB_TEST_INVALID: 'many'"]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut args = valid_args(&dir);
+        let file = "[server]\nport = 9000\n[scraping]\nmax_rank = 50\n";
+        let config = from_toml(&args, file).unwrap();
+        assert_eq!((config.server.port, config.scraping.max_rank), (9000, 50));
+        args.port = Some(9100);
+        assert_eq!(from_toml(&args, file).unwrap().server.port, 9100);
+    }
+
+    #[test]
+    fn reports_all_problems() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut args = valid_args(&dir);
+        args.html_root = Some(String::from("/nonexistent/html"));
+        let file = "[storage]\nmax_import_mb = 9223372036854775807\n[scraping]\nmax_concurrent = 0\n\
+                    [auth]\nprivate_fields = [\"shoe_size\"]\ntrusted_proxies = [\"proxy\"]\n";
+        let problems = from_toml(&args, file).err().unwrap();
+        assert_eq!(
+            problems,
+            vec![
+                "storage.max_import_mb: 9223372036854775807 is too large",
+                "invalid private field: 'shoe_size'",
+                "invalid TRUSTED_PROXIES: 'proxy'",
+                "server.html_root: /nonexistent/html is not a directory",
+                "scraping.max_concurrent must be at least 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_settings() {
+        assert!(toml::from_str::<ConfigFile>("[server]\nprot = 80\n").is_err());
+        assert!(toml::from_str::<ConfigFile>("[storage]\nmax_import_mb = 10\n").is_ok());
+    }
+}
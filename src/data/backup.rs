@@ -0,0 +1,117 @@
+use libsqlite3_sys as ffi;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_int;
+use std::path::Path;
+use std::time::Duration;
+
+/// How often a backup step is retried while another connection holds a write lock
+const MAX_BUSY_RETRIES: usize = 500;
+
+struct Connection(*mut ffi::sqlite3);
+
+impl Connection {
+    fn open(path: &str, flags: c_int) -> Result<Self, String> {
+        let c_path = CString::new(path).map_err(|_| format!("invalid DB path: {}", path))?;
+        let mut handle = std::ptr::null_mut();
+        let result = unsafe { ffi::sqlite3_open_v2(c_path.as_ptr(), &mut handle, flags, std::ptr::null()) };
+
+        // sqlite returns a handle even if opening failed, which still needs to be closed
+        let conn = Connection(handle);
+        if result != ffi::SQLITE_OK {
+            return Err(format!("Failed to open {}: {}", path, conn.error_message()));
+        }
+        Ok(conn)
+    }
+
+    fn error_message(&self) -> String {
+        if self.0.is_null() {
+            return String::from("out of memory");
+        }
+        unsafe { CStr::from_ptr(ffi::sqlite3_errmsg(self.0)) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::sqlite3_close(self.0);
+        }
+    }
+}
+
+/// Copies the DB at `source` into a new DB file at `target`, using SQLite's online backup API.
+/// The whole DB is copied in a single step, so the copy is consistent even if other connections
+/// are writing to the source DB at the same time.
+pub fn backup_database(source: &str, target: &Path) -> Result<(), String> {
+    backup_with_retries(source, target, MAX_BUSY_RETRIES)
+}
+
+fn backup_with_retries(source: &str, target: &Path, max_busy_retries: usize) -> Result<(), String> {
+    let target = target.to_str().ok_or(format!("invalid backup path: {:?}", target))?;
+
+    let source_conn = Connection::open(source, ffi::SQLITE_OPEN_READONLY | ffi::SQLITE_OPEN_URI)?;
+    let target_conn = Connection::open(target, ffi::SQLITE_OPEN_READWRITE | ffi::SQLITE_OPEN_CREATE)?;
+
+    let main = CString::new("main").unwrap();
+    let backup = unsafe { ffi::sqlite3_backup_init(target_conn.0, main.as_ptr(), source_conn.0, main.as_ptr()) };
+    if backup.is_null() {
+        return Err(format!("Failed to start DB backup: {}", target_conn.error_message()));
+    }
+
+    let mut retries = 0;
+    let step = loop {
+        match unsafe { ffi::sqlite3_backup_step(backup, -1) } {
+            ffi::SQLITE_BUSY | ffi::SQLITE_LOCKED if retries < max_busy_retries => {
+                retries += 1;
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            result => break result,
+        }
+    };
+
+    // sqlite3_backup_finish() reports BUSY and LOCKED as success, although the copy is incomplete then
+    let finish = unsafe { ffi::sqlite3_backup_finish(backup) };
+    match step {
+        ffi::SQLITE_DONE if finish == ffi::SQLITE_OK => Ok(()),
+        ffi::SQLITE_DONE => Err(format!("DB backup failed: {}", target_conn.error_message())),
+        ffi::SQLITE_BUSY | ffi::SQLITE_LOCKED => Err(String::from("DB backup failed: the DB stayed locked")),
+        _ => Err(format!("DB backup failed: {}", error_string(step))),
+    }
+}
+
+fn error_string(code: c_int) -> String {
+    unsafe { CStr::from_ptr(ffi::sqlite3_errstr(code)) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::{Connection as _, RunQueryDsl, SqliteConnection};
+
+    #[test]
+    fn fails_while_the_db_is_locked() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source.sqlite");
+        let source = source.to_str().unwrap();
+        let mut conn = SqliteConnection::establish(source).unwrap();
+        diesel::sql_query("CREATE TABLE players (id INTEGER PRIMARY KEY)")
+            .execute(&mut conn)
+            .unwrap();
+
+        diesel::sql_query("BEGIN EXCLUSIVE").execute(&mut conn).unwrap();
+        let locked = backup_with_retries(source, &dir.path().join("locked.sqlite"), 2);
+        assert_eq!(locked, Err(String::from("DB backup failed: the DB stayed locked")));
+
+        diesel::sql_query("COMMIT").execute(&mut conn).unwrap();
+        let target = dir.path().join("backup.sqlite");
+        backup_with_retries(source, &target, 2).unwrap();
+        let mut backup = SqliteConnection::establish(target.to_str().unwrap()).unwrap();
+        diesel::sql_query("SELECT id FROM players")
+            .execute(&mut backup)
+            .unwrap();
+    }
+}
@@ -1,10 +1,17 @@
+use diesel::connection::TransactionManager;
 use diesel::sqlite::SqliteConnection;
 use diesel::{prelude::*, Insertable, Queryable};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
+use crate::error::{Error, Result};
 use crate::schema::*;
 
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
+
+type TxManager = <SqliteConnection as Connection>::TransactionManager;
+
 #[derive(Queryable, Insertable, AsChangeset)]
 #[diesel(table_name = players)]
 struct DbPlayer {
@@ -12,33 +19,150 @@ struct DbPlayer {
     json_data: Vec<u8>,
 }
 
+#[derive(Queryable, Insertable, AsChangeset)]
+#[diesel(table_name = player_redirects)]
+struct DbRedirect {
+    old_itsf_id: i32,
+    new_itsf_id: i32,
+}
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = player_slugs)]
+struct DbSlug {
+    slug: String,
+    itsf_id: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = player_merges)]
+struct DbNewMerge<'a> {
+    timestamp: i64,
+    user: &'a str,
+    source_itsf_id: i32,
+    target_itsf_id: i32,
+    source_json: Vec<u8>,
+    target_json: Vec<u8>,
+    image_moved: bool,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = player_quarantine)]
+struct DbNewQuarantineEntry<'a> {
+    itsf_id: i32,
+    timestamp: i64,
+    json_data: Vec<u8>,
+    error: &'a str,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = player_history)]
+struct DbNewHistoryEntry<'a> {
+    itsf_id: i32,
+    timestamp: i64,
+    action: &'a str,
+    source: Vec<u8>,
+    changes: Vec<u8>,
+}
+
+#[derive(Queryable)]
+pub struct DbHistoryEntry {
+    pub id: i64,
+    pub itsf_id: i32,
+    pub timestamp: i64,
+    pub action: String,
+    pub source: Vec<u8>,
+    pub changes: Vec<u8>,
+}
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = player_merges)]
+pub struct DbMerge {
+    pub id: i32,
+    pub timestamp: i64,
+    pub user: String,
+    pub source_itsf_id: i32,
+    pub target_itsf_id: i32,
+    pub source_json: Vec<u8>,
+    pub target_json: Vec<u8>,
+    pub image_moved: bool,
+    pub reverted: bool,
+}
+
+fn run_migrations(conn: &mut SqliteConnection) -> Result<Vec<String>> {
+    let versions = conn
+        .run_pending_migrations(MIGRATIONS)
+        .map_err(|err| Error::Database(format!("Failed to run DB migrations: {}", err)))?;
+    Ok(versions.into_iter().map(|version| version.to_string()).collect())
+}
+
 pub struct DbConnection {
     conn: SqliteConnection,
 }
 
-fn expect_result<T>(result: Result<T, diesel::result::Error>) -> T {
-    match result {
-        Ok(value) => value,
-        Err(err) => panic!("SQL Error: {:?}", err),
+impl DbConnection {
+    pub fn open(path: &str) -> Result<Self> {
+        let mut conn = SqliteConnection::establish(path)?;
+        run_migrations(&mut conn)?;
+        Ok(Self { conn })
     }
-}
 
-impl DbConnection {
-    pub fn open(path: &str) -> Self {
-        let conn = SqliteConnection::establish(path).expect("Failed to open DB");
-        Self { conn }
+    /// Applies all pending migrations to the DB, without reading anything, and returns their versions.
+    pub fn migrate(path: &str) -> Result<Vec<String>> {
+        let mut conn = SqliteConnection::establish(path)?;
+        run_migrations(&mut conn)
     }
 
-    pub fn get_player_ids(&mut self) -> Vec<i32> {
-        use crate::schema::players::dsl;
+    /// The version of the newest migration that was applied to the DB.
+    pub fn schema_version(&mut self) -> Result<String> {
+        let versions = self
+            .conn
+            .applied_migrations()
+            .map_err(|err| Error::Database(format!("Failed to query DB migrations: {}", err)))?;
+        Ok(versions.into_iter().max().map(|v| v.to_string()).unwrap_or_default())
+    }
+
+    pub fn begin_transaction(&mut self) -> Result<()> {
+        Ok(TxManager::begin_transaction(&mut self.conn)?)
+    }
 
-        let ids = dsl::players.select(dsl::itsf_id).load(&mut self.conn);
+    pub fn commit_transaction(&mut self) -> Result<()> {
+        Ok(TxManager::commit_transaction(&mut self.conn)?)
+    }
 
-        expect_result(ids)
+    pub fn rollback_transaction(&mut self) -> Result<()> {
+        Ok(TxManager::rollback_transaction(&mut self.conn)?)
     }
 
-    pub fn write_player_json<T: Serialize>(&mut self, itsf_id: i32, data: &T) {
-        let json_data = serde_json::to_vec(&data).expect("JSON serialization failed");
+    /// Runs `f` inside a single SQL transaction, so that multi-table updates are applied atomically.
+    /// The transaction is rolled back if `f` fails.
+    pub fn transaction<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        self.begin_transaction()?;
+        match f(self) {
+            Ok(value) => {
+                self.commit_transaction()?;
+                Ok(value)
+            }
+            Err(err) => {
+                if let Err(rollback_err) = self.rollback_transaction() {
+                    log::error!("Failed to roll back transaction: {}", rollback_err);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    pub fn get_player_ids(&mut self) -> Result<Vec<i32>> {
+        use crate::schema::players::dsl;
+
+        let ids = dsl::players.select(dsl::itsf_id).load(&mut self.conn)?;
+        Ok(ids)
+    }
+
+    pub fn write_player_json<T: Serialize>(&mut self, itsf_id: i32, data: &T) -> Result<()> {
+        let json_data = serde_json::to_vec(&data)?;
         let player = DbPlayer { itsf_id, json_data };
 
         use crate::schema::players::dsl;
@@ -48,26 +172,347 @@ impl DbConnection {
             .on_conflict(dsl::itsf_id)
             .do_update()
             .set(&player)
-            .execute(&mut self.conn);
+            .execute(&mut self.conn)?;
 
-        let result = expect_result(result);
         if result != 1 {
-            panic!("invalid query result for player insert: {}", result);
+            return Err(Error::Database(format!(
+                "invalid query result for insert of player {}: {}",
+                itsf_id, result
+            )));
         }
+        Ok(())
     }
 
-    pub fn read_player_json<T: DeserializeOwned>(&mut self, itsf_id: i32) -> Result<T, String> {
+    pub fn read_player_json<T: DeserializeOwned>(&mut self, itsf_id: i32) -> Result<T> {
         use crate::schema::players::dsl;
 
         let player = dsl::players
             .filter(dsl::itsf_id.eq(itsf_id))
             .first::<DbPlayer>(&mut self.conn)
-            .optional();
+            .optional()?;
 
-        match expect_result(player) {
+        match player {
             Some(player) => serde_json::from_slice(&player.json_data)
-                .map_err(|err| format!("JSON Error when loading player {}: {}", itsf_id, err)),
-            None => Err(format!("No player data found for player {}", itsf_id)),
+                .map_err(|err| Error::Data(format!("JSON Error when loading player {}: {}", itsf_id, err))),
+            None => Err(Error::NotFound(format!("No player data found for player {}", itsf_id))),
+        }
+    }
+
+    pub fn delete_player(&mut self, itsf_id: i32) -> Result<()> {
+        use crate::schema::players::dsl;
+
+        diesel::delete(dsl::players.filter(dsl::itsf_id.eq(itsf_id))).execute(&mut self.conn)?;
+        Ok(())
+    }
+
+    /// Moves the data of a player that can't be loaded out of the `players` table, so that it can be
+    /// inspected and repaired manually, without being overwritten by newly scraped data.
+    pub fn quarantine_player(&mut self, itsf_id: i32, timestamp: i64, error: &str) -> Result<()> {
+        use crate::schema::players::dsl;
+
+        self.transaction(|db| {
+            let player = dsl::players
+                .filter(dsl::itsf_id.eq(itsf_id))
+                .first::<DbPlayer>(&mut db.conn)?;
+            let entry = DbNewQuarantineEntry {
+                itsf_id,
+                timestamp,
+                json_data: player.json_data,
+                error,
+            };
+            diesel::insert_into(player_quarantine::table)
+                .values(&entry)
+                .execute(&mut db.conn)?;
+            db.delete_player(itsf_id)
+        })
+    }
+
+    pub fn clear_quarantine(&mut self) -> Result<()> {
+        diesel::delete(player_quarantine::table).execute(&mut self.conn)?;
+        Ok(())
+    }
+
+    pub fn add_history_entry<S: Serialize, C: Serialize>(
+        &mut self,
+        itsf_id: i32,
+        timestamp: i64,
+        action: &str,
+        source: &S,
+        changes: &C,
+    ) -> Result<i64> {
+        let entry = DbNewHistoryEntry {
+            itsf_id,
+            timestamp,
+            action,
+            source: serde_json::to_vec(source)?,
+            changes: serde_json::to_vec(changes)?,
+        };
+
+        use crate::schema::player_history::dsl;
+
+        let id = diesel::insert_into(dsl::player_history)
+            .values(&entry)
+            .returning(dsl::id)
+            .get_result(&mut self.conn)?;
+        Ok(id)
+    }
+
+    pub fn get_history(&mut self, itsf_id: i32) -> Result<Vec<DbHistoryEntry>> {
+        use crate::schema::player_history::dsl;
+
+        let entries = dsl::player_history
+            .filter(dsl::itsf_id.eq(itsf_id))
+            .order(dsl::id.asc())
+            .load(&mut self.conn)?;
+        Ok(entries)
+    }
+
+    pub fn get_all_history(&mut self) -> Result<Vec<DbHistoryEntry>> {
+        use crate::schema::player_history::dsl;
+
+        let entries = dsl::player_history.order(dsl::id.asc()).load(&mut self.conn)?;
+        Ok(entries)
+    }
+
+    pub fn set_history_changes<C: Serialize>(&mut self, id: i64, changes: &C) -> Result<()> {
+        use crate::schema::player_history::dsl;
+
+        diesel::update(dsl::player_history.filter(dsl::id.eq(id)))
+            .set(dsl::changes.eq(serde_json::to_vec(changes)?))
+            .execute(&mut self.conn)?;
+        Ok(())
+    }
+
+    /// Overwrites who made a history entry, e.g. to hide the user in exported DBs.
+    pub fn set_history_source<S: Serialize>(&mut self, id: i64, source: &S) -> Result<()> {
+        use crate::schema::player_history::dsl;
+
+        diesel::update(dsl::player_history.filter(dsl::id.eq(id)))
+            .set(dsl::source.eq(serde_json::to_vec(source)?))
+            .execute(&mut self.conn)?;
+        Ok(())
+    }
+
+    /// Deletes the history entries of a player that are older than the entry `id`.
+    pub fn delete_history_before(&mut self, itsf_id: i32, id: i64) -> Result<()> {
+        use crate::schema::player_history::dsl;
+
+        diesel::delete(dsl::player_history.filter(dsl::itsf_id.eq(itsf_id).and(dsl::id.lt(id))))
+            .execute(&mut self.conn)?;
+        Ok(())
+    }
+
+    /// Returns `(id, itsf_id, action)` of all history entries newer than `since`, in ascending order.
+    pub fn get_history_since(&mut self, since: i64) -> Result<Vec<(i64, i32, String)>> {
+        use crate::schema::player_history::dsl;
+
+        let entries = dsl::player_history
+            .select((dsl::id, dsl::itsf_id, dsl::action))
+            .filter(dsl::id.gt(since))
+            .order(dsl::id.asc())
+            .load(&mut self.conn)?;
+        Ok(entries)
+    }
+
+    /// Returns `(id, timestamp)` of the newest history entry.
+    pub fn get_last_history_entry(&mut self) -> Result<Option<(i64, i64)>> {
+        use crate::schema::player_history::dsl;
+
+        let entry = dsl::player_history
+            .select((dsl::id, dsl::timestamp))
+            .order(dsl::id.desc())
+            .first(&mut self.conn)
+            .optional()?;
+        Ok(entry)
+    }
+
+    /// Returns `(itsf_id, id, timestamp)` of the newest history entry of every player.
+    pub fn get_last_history_entries(&mut self) -> Result<Vec<(i32, i64, i64)>> {
+        use crate::schema::player_history::dsl;
+
+        let entries = dsl::player_history
+            .group_by(dsl::itsf_id)
+            .select((
+                dsl::itsf_id,
+                diesel::dsl::max(dsl::id),
+                diesel::dsl::max(dsl::timestamp),
+            ))
+            .load::<(i32, Option<i64>, Option<i64>)>(&mut self.conn)?;
+
+        Ok(entries
+            .into_iter()
+            .map(|(itsf_id, id, timestamp)| (itsf_id, id.unwrap_or(0), timestamp.unwrap_or(0)))
+            .collect())
+    }
+
+    pub fn get_redirects(&mut self) -> Result<Vec<(i32, i32)>> {
+        use crate::schema::player_redirects::dsl;
+
+        let redirects = dsl::player_redirects.load::<DbRedirect>(&mut self.conn)?;
+
+        Ok(redirects
+            .into_iter()
+            .map(|redirect| (redirect.old_itsf_id, redirect.new_itsf_id))
+            .collect())
+    }
+
+    pub fn write_redirect(&mut self, old_itsf_id: i32, new_itsf_id: i32) -> Result<()> {
+        let redirect = DbRedirect {
+            old_itsf_id,
+            new_itsf_id,
+        };
+
+        use crate::schema::player_redirects::dsl;
+
+        diesel::insert_into(dsl::player_redirects)
+            .values(&redirect)
+            .on_conflict(dsl::old_itsf_id)
+            .do_update()
+            .set(&redirect)
+            .execute(&mut self.conn)?;
+        Ok(())
+    }
+
+    pub fn delete_redirect(&mut self, old_itsf_id: i32) -> Result<()> {
+        use crate::schema::player_redirects::dsl;
+
+        diesel::delete(dsl::player_redirects.filter(dsl::old_itsf_id.eq(old_itsf_id))).execute(&mut self.conn)?;
+        Ok(())
+    }
+
+    pub fn delete_redirects(&mut self) -> Result<()> {
+        use crate::schema::player_redirects::dsl;
+
+        diesel::delete(dsl::player_redirects).execute(&mut self.conn)?;
+        Ok(())
+    }
+
+    pub fn get_slugs(&mut self) -> Result<Vec<(String, i32)>> {
+        use crate::schema::player_slugs::dsl;
+
+        let slugs = dsl::player_slugs.load::<DbSlug>(&mut self.conn)?;
+
+        Ok(slugs.into_iter().map(|slug| (slug.slug, slug.itsf_id)).collect())
+    }
+
+    pub fn write_slug(&mut self, slug: &str, itsf_id: i32) -> Result<()> {
+        let slug = DbSlug {
+            slug: String::from(slug),
+            itsf_id,
+        };
+
+        use crate::schema::player_slugs::dsl;
+
+        diesel::insert_into(dsl::player_slugs)
+            .values(&slug)
+            .execute(&mut self.conn)?;
+        Ok(())
+    }
+
+    pub fn replace_slugs(&mut self, slugs: &[(String, i32)]) -> Result<()> {
+        use crate::schema::player_slugs::dsl;
+
+        diesel::delete(dsl::player_slugs).execute(&mut self.conn)?;
+        for (slug, itsf_id) in slugs {
+            self.write_slug(slug, *itsf_id)?;
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_merge<T: Serialize>(
+        &mut self,
+        timestamp: i64,
+        user: &str,
+        source: &T,
+        source_itsf_id: i32,
+        target: &T,
+        target_itsf_id: i32,
+        image_moved: bool,
+    ) -> Result<i32> {
+        let merge = DbNewMerge {
+            timestamp,
+            user,
+            source_itsf_id,
+            target_itsf_id,
+            source_json: serde_json::to_vec(source)?,
+            target_json: serde_json::to_vec(target)?,
+            image_moved,
+        };
+
+        use crate::schema::player_merges::dsl;
+
+        let id = diesel::insert_into(dsl::player_merges)
+            .values(&merge)
+            .returning(dsl::id)
+            .get_result(&mut self.conn)?;
+        Ok(id)
+    }
+
+    pub fn get_merges(&mut self) -> Result<Vec<DbMerge>> {
+        use crate::schema::player_merges::dsl;
+
+        let merges = dsl::player_merges.order(dsl::id.asc()).load(&mut self.conn)?;
+        Ok(merges)
+    }
+
+    /// Replaces all merges, keeping their IDs.
+    pub fn replace_merges(&mut self, merges: &[DbMerge]) -> Result<()> {
+        use crate::schema::player_merges::dsl;
+
+        diesel::delete(dsl::player_merges).execute(&mut self.conn)?;
+        for merge in merges {
+            diesel::insert_into(dsl::player_merges)
+                .values(merge)
+                .execute(&mut self.conn)?;
         }
+        Ok(())
+    }
+
+    pub fn get_merge(&mut self, id: i32) -> Result<Option<DbMerge>> {
+        use crate::schema::player_merges::dsl;
+
+        let merge = dsl::player_merges
+            .filter(dsl::id.eq(id))
+            .first(&mut self.conn)
+            .optional()?;
+        Ok(merge)
+    }
+
+    pub fn set_merge_reverted(&mut self, id: i32) -> Result<()> {
+        use crate::schema::player_merges::dsl;
+
+        diesel::update(dsl::player_merges.filter(dsl::id.eq(id)))
+            .set(dsl::reverted.eq(true))
+            .execute(&mut self.conn)?;
+        Ok(())
+    }
+
+    pub fn set_merge_json<T: Serialize>(&mut self, id: i32, source: &T, target: &T) -> Result<()> {
+        use crate::schema::player_merges::dsl;
+
+        diesel::update(dsl::player_merges.filter(dsl::id.eq(id)))
+            .set((
+                dsl::source_json.eq(serde_json::to_vec(source)?),
+                dsl::target_json.eq(serde_json::to_vec(target)?),
+            ))
+            .execute(&mut self.conn)?;
+        Ok(())
+    }
+
+    /// Overwrites who made a merge, e.g. to hide the user in exported DBs.
+    pub fn set_merge_user(&mut self, id: i32, user: &str) -> Result<()> {
+        use crate::schema::player_merges::dsl;
+
+        diesel::update(dsl::player_merges.filter(dsl::id.eq(id)))
+            .set(dsl::user.eq(user))
+            .execute(&mut self.conn)?;
+        Ok(())
+    }
+
+    /// Rebuilds the DB file, so that deleted or overwritten data doesn't linger in unused pages.
+    pub fn vacuum(&mut self) -> Result<()> {
+        diesel::sql_query("VACUUM").execute(&mut self.conn)?;
+        Ok(())
     }
 }
@@ -1,4 +1,4 @@
-#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
 #[repr(i8)]
 pub enum ChampionshipCategory {
     #[serde(rename = "men")]
@@ -11,7 +11,7 @@ pub enum ChampionshipCategory {
     Senior,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
 #[repr(i8)]
 pub enum ChampionshipClass {
     #[serde(rename = "singles")]
@@ -20,7 +20,7 @@ pub enum ChampionshipClass {
     Doubles,
 }
 
-#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
 pub struct NationalChampionshipResult {
     pub year: i32,
     pub place: i32,
@@ -34,7 +34,7 @@ impl NationalChampionshipResult {
     }
 }
 
-#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
 pub struct NationalRanking {
     pub year: i32,
     pub place: i32,
@@ -47,7 +47,7 @@ impl NationalRanking {
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
 pub struct NationalTeam {
     pub year: i32,
     pub name: String,
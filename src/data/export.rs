@@ -0,0 +1,312 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// Version of the archive layout, to be increased on incompatible changes
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Path of the DB inside the archive
+pub const ZIP_DATABASE_PATH: &str = "db.sqlite";
+/// Directory of the player images inside the archive
+pub const ZIP_IMAGE_DIRECTORY: &str = "images";
+/// Path of the manifest inside the archive
+pub const ZIP_MANIFEST_PATH: &str = "manifest.json";
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Describes the contents of an exported archive, stored as `manifest.json` inside of it.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+    pub format_version: u32,
+    /// Version of the newest DB migration that was applied to the exported DB
+    pub schema_version: String,
+    /// UNIX timestamp of the export
+    pub exported_at: i64,
+    /// Change sequence number of the exported DB, see `/changes`
+    pub change_seq: i64,
+    pub player_count: usize,
+    pub image_count: usize,
+    /// Every file in the archive, except for the manifest itself
+    pub files: Vec<ManifestEntry>,
+}
+
+/// Forwards all data that is read, while computing its size and SHA-256.
+struct HashingReader<R: Read> {
+    inner: R,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.hasher.update(&buf[..count]);
+        self.size += count as u64;
+        Ok(count)
+    }
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    fn into_entry(self, path: &str) -> ManifestEntry {
+        ManifestEntry {
+            path: String::from(path),
+            size: self.size,
+            sha256: format!("{:x}", self.hasher.finalize()),
+        }
+    }
+}
+
+pub struct ZipExporter<W: Write + Seek> {
+    zip: ZipWriter<W>,
+    entries: Vec<ManifestEntry>,
+    image_count: usize,
+}
+
+impl<W: Write + Seek> ZipExporter<W> {
+    pub fn new(zip: ZipWriter<W>) -> Self {
+        Self {
+            zip,
+            entries: Vec::new(),
+            image_count: 0,
+        }
+    }
+
+    fn add_file(&mut self, source: &Path, name: &str, compression: CompressionMethod) -> Result<(), String> {
+        let file = File::open(source).map_err(|err| format!("Failed to open {:?}: {}", source, err))?;
+
+        let options = SimpleFileOptions::default()
+            .compression_method(compression)
+            .large_file(true);
+        self.zip.start_file(name, options).map_err(|err| err.to_string())?;
+
+        let mut reader = HashingReader::new(file);
+        std::io::copy(&mut reader, &mut self.zip).map_err(|err| format!("Failed to write {}: {}", name, err))?;
+        self.entries.push(reader.into_entry(name));
+
+        Ok(())
+    }
+
+    pub fn add_database(&mut self, snapshot: &Path) -> Result<(), String> {
+        self.add_file(snapshot, ZIP_DATABASE_PATH, CompressionMethod::Deflated)
+    }
+
+    /// Adds all images of the image directory, stored under `images/<file name>`.
+    pub fn add_images(&mut self, image_directory: &str) -> Result<(), String> {
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        self.zip
+            .add_directory(ZIP_IMAGE_DIRECTORY, options)
+            .map_err(|err| err.to_string())?;
+
+        let dir = std::fs::read_dir(image_directory).map_err(|err| err.to_string())?;
+        let mut files: Vec<_> = dir
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().map(|t| t.is_file()).unwrap_or(false))
+            .map(|entry| entry.path())
+            .collect();
+        files.sort();
+
+        for file in files {
+            let file_name = file
+                .file_name()
+                .and_then(|name| name.to_str())
+                .ok_or(format!("invalid image file name: {:?}", file))?;
+            // JPEGs are already compressed
+            let name = format!("{}/{}", ZIP_IMAGE_DIRECTORY, file_name);
+            self.add_file(&file, &name, CompressionMethod::Stored)?;
+            self.image_count += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the manifest as the last entry of the archive. The manifest's file list and image count
+    /// are filled in from the files that were added before.
+    pub fn finish(mut self, mut manifest: Manifest) -> Result<W, String> {
+        manifest.files = std::mem::take(&mut self.entries);
+        manifest.image_count = self.image_count;
+
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        self.zip
+            .start_file(ZIP_MANIFEST_PATH, options)
+            .map_err(|err| err.to_string())?;
+        serde_json::to_writer_pretty(&mut self.zip, &manifest).map_err(|err| err.to_string())?;
+
+        self.zip.finish().map_err(|err| err.to_string())
+    }
+}
+
+fn is_image_path(path: &str) -> bool {
+    path.strip_prefix(ZIP_IMAGE_DIRECTORY)
+        .and_then(|path| path.strip_prefix('/'))
+        .map(|name| !name.is_empty() && !name.contains('/'))
+        .unwrap_or(false)
+}
+
+/// Checks that an archive is complete, by comparing its contents to its manifest.
+/// All problems that were found are reported, separated by newlines.
+pub fn verify_archive<R: Read + Seek>(reader: R) -> Result<Manifest, String> {
+    let mut zip = ZipArchive::new(reader).map_err(|err| format!("Not a valid zip file: {}", err))?;
+
+    let manifest: Manifest = {
+        let file = zip
+            .by_name(ZIP_MANIFEST_PATH)
+            .map_err(|_| format!("{} is missing", ZIP_MANIFEST_PATH))?;
+        serde_json::from_reader(file).map_err(|err| format!("Invalid {}: {}", ZIP_MANIFEST_PATH, err))?
+    };
+
+    let mut problems = Vec::new();
+
+    if manifest.format_version != FORMAT_VERSION {
+        problems.push(format!("Unsupported format version {}", manifest.format_version));
+    }
+
+    for entry in &manifest.files {
+        let file = match zip.by_name(&entry.path) {
+            Ok(file) => file,
+            Err(_) => {
+                problems.push(format!("{} is missing", entry.path));
+                continue;
+            }
+        };
+
+        let mut reader = HashingReader::new(file);
+        if let Err(err) = std::io::copy(&mut reader, &mut std::io::sink()) {
+            problems.push(format!("{} can't be read: {}", entry.path, err));
+            continue;
+        }
+
+        let actual = reader.into_entry(&entry.path);
+        if actual.size != entry.size {
+            problems.push(format!(
+                "{} has {} bytes instead of {}",
+                entry.path, actual.size, entry.size
+            ));
+        } else if actual.sha256 != entry.sha256 {
+            problems.push(format!("{} has a wrong checksum", entry.path));
+        }
+    }
+
+    let listed: HashSet<&str> = manifest.files.iter().map(|entry| entry.path.as_str()).collect();
+    if !listed.contains(ZIP_DATABASE_PATH) {
+        problems.push(format!("{} is not listed in the manifest", ZIP_DATABASE_PATH));
+    }
+    for name in zip.file_names() {
+        let is_directory = name.ends_with('/');
+        if !is_directory && name != ZIP_MANIFEST_PATH && !listed.contains(name) {
+            problems.push(format!("{} is not listed in the manifest", name));
+        }
+    }
+
+    let image_count = listed.iter().filter(|path| is_image_path(path)).count();
+    if image_count != manifest.image_count {
+        problems.push(format!(
+            "Archive contains {} images instead of {}",
+            image_count, manifest.image_count
+        ));
+    }
+
+    if problems.is_empty() {
+        Ok(manifest)
+    } else {
+        Err(problems.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn entry(path: &str, data: &[u8]) -> ManifestEntry {
+        let mut reader = HashingReader::new(data);
+        std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+        reader.into_entry(path)
+    }
+
+    /// Builds an archive with the given files, and a manifest that lists `listed`.
+    fn archive(files: &[(&str, &[u8])], listed: Vec<ManifestEntry>, image_count: usize) -> Cursor<Vec<u8>> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        let manifest = Manifest {
+            format_version: FORMAT_VERSION,
+            schema_version: String::from("1"),
+            exported_at: 0,
+            change_seq: 0,
+            player_count: 0,
+            image_count,
+            files: listed,
+        };
+        zip.start_file(ZIP_MANIFEST_PATH, SimpleFileOptions::default()).unwrap();
+        serde_json::to_writer(&mut zip, &manifest).unwrap();
+        let mut cursor = zip.finish().unwrap();
+        cursor.rewind().unwrap();
+        cursor
+    }
+
+    #[test]
+    fn accepts_complete_archives() {
+        let files: [(&str, &[u8]); 2] = [(ZIP_DATABASE_PATH, b"db"), ("images/1.jpg", b"jpg")];
+        let listed = files.iter().map(|(name, data)| entry(name, data)).collect();
+        let manifest = verify_archive(archive(&files, listed, 1)).unwrap();
+        assert_eq!(manifest.files.len(), 2);
+    }
+
+    #[test]
+    fn reports_all_problems() {
+        let files: [(&str, &[u8]); 3] = [
+            (ZIP_DATABASE_PATH, b"db"),
+            ("images/1.jpg", b"changed"),
+            ("images/2.jpg", b"jpg"),
+        ];
+        let listed = vec![
+            entry(ZIP_DATABASE_PATH, b"DB"),
+            entry("images/1.jpg", b"jpg"),
+            entry("images/3.jpg", b"jpg"),
+        ];
+        let problems = verify_archive(archive(&files, listed, 3)).unwrap_err();
+        let problems: Vec<&str> = problems.lines().collect();
+        assert_eq!(
+            problems,
+            vec![
+                "db.sqlite has a wrong checksum",
+                "images/1.jpg has 7 bytes instead of 3",
+                "images/3.jpg is missing",
+                "images/2.jpg is not listed in the manifest",
+                "Archive contains 2 images instead of 3",
+            ]
+        );
+    }
+
+    #[test]
+    fn requires_manifest_and_db() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file(ZIP_DATABASE_PATH, SimpleFileOptions::default()).unwrap();
+        let mut cursor = zip.finish().unwrap();
+        cursor.rewind().unwrap();
+        assert_eq!(verify_archive(cursor).unwrap_err(), "manifest.json is missing");
+
+        let problems = verify_archive(archive(&[], Vec::new(), 0)).unwrap_err();
+        assert_eq!(problems, "db.sqlite is not listed in the manifest");
+        assert!(verify_archive(Cursor::new(b"not a zip".to_vec())).is_err());
+    }
+}
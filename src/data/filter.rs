@@ -0,0 +1,119 @@
+use sha2::{Digest, Sha256};
+
+use super::{itsf, Player};
+
+/// Query parameters that select a subset of all players, as passed to `/listplayers` and the exports.
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PlayerFilterParams {
+    /// Country code, e.g. `GER`
+    pub country: Option<String>,
+    /// ITSF player category, e.g. `men` or `junior_female`
+    pub category: Option<String>,
+    /// Part of the first or last name
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize)]
+pub struct PlayerFilter {
+    country: Option<String>,
+    category: Option<itsf::PlayerCategory>,
+    name: Option<String>,
+}
+
+impl PlayerFilter {
+    pub fn from_params(params: &PlayerFilterParams) -> Result<Self, String> {
+        let category = match &params.category {
+            Some(category) => Some(itsf::PlayerCategory::try_from_str(
+                &category.to_uppercase().replace('_', " "),
+            )?),
+            None => None,
+        };
+        let non_empty = |value: &Option<String>| {
+            value
+                .as_ref()
+                .map(|v| v.trim())
+                .filter(|v| !v.is_empty())
+                .map(String::from)
+        };
+
+        Ok(Self {
+            country: non_empty(&params.country).map(|country| country.to_uppercase()),
+            category,
+            name: non_empty(&params.name).map(|name| name.to_lowercase()),
+        })
+    }
+
+    pub fn matches(&self, player: &Player) -> bool {
+        if let Some(country) = &self.country {
+            if player.country_code.as_deref() != Some(country.as_str()) {
+                return false;
+            }
+        }
+        if let Some(category) = self.category {
+            if player.category != category {
+                return false;
+            }
+        }
+        if let Some(name) = &self.name {
+            let full_name = format!("{} {}", player.first_name, player.last_name).to_lowercase();
+            if !full_name.contains(name.as_str()) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Identifies the filter in cache validators, so that differently filtered responses get different ETags.
+    pub fn cache_key(&self) -> String {
+        cache_key_of(self)
+    }
+}
+
+/// Hashes the JSON form of a parsed query, which is the same across builds and restarts unlike `std::hash`.
+/// Cached ETags thus stay valid when the server is updated.
+pub(super) fn cache_key_of<T: serde::Serialize>(value: &T) -> String {
+    let canonical = serde_json::to_string(value).unwrap_or_default();
+    format!("{:x}", Sha256::digest(canonical.as_bytes()))[..16].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(country: Option<&str>, category: Option<&str>, name: Option<&str>) -> PlayerFilter {
+        PlayerFilter::from_params(&PlayerFilterParams {
+            country: country.map(String::from),
+            category: category.map(String::from),
+            name: name.map(String::from),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn cache_keys_are_stable() {
+        // the key must not change between builds, or clients' cached ETags would never match again
+        assert_eq!(filter(None, None, None).cache_key(), "b5ab31ec5deb260b");
+        assert_eq!(
+            filter(Some(" ger"), None, Some("Anna ")).cache_key(),
+            filter(Some("GER"), None, Some("anna")).cache_key()
+        );
+        assert_ne!(
+            filter(Some("GER"), None, None).cache_key(),
+            filter(None, None, Some("ger")).cache_key()
+        );
+        assert_ne!(
+            filter(None, Some("men"), None).cache_key(),
+            filter(None, Some("women"), None).cache_key()
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_categories() {
+        let params = PlayerFilterParams {
+            category: Some(String::from("kids")),
+            ..Default::default()
+        };
+        assert!(PlayerFilter::from_params(&params).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use super::Player;
+
+/// Who or what caused a change of player data.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChangeSource {
+    /// A background scraping job, see `BackgroundOperationProgress`
+    Scrape {
+        job_id: i64,
+        job: String,
+    },
+    /// A manual change by an authorized user
+    Admin {
+        user: String,
+    },
+    /// A comment that was added by a user
+    Comment {
+        user: String,
+    },
+    Merge {
+        user: String,
+        merge_id: i32,
+    },
+    RevertMerge {
+        user: String,
+        merge_id: i32,
+    },
+    /// An import of a `db.zip` archive
+    Import {
+        user: String,
+    },
+}
+
+impl ChangeSource {
+    /// Removes the name of the user or token that made the change, see `PrivateField::ChangeAuthors`.
+    pub fn hide_user(&mut self) {
+        match self {
+            Self::Scrape { .. } => {}
+            Self::Admin { user }
+            | Self::Comment { user }
+            | Self::Merge { user, .. }
+            | Self::RevertMerge { user, .. }
+            | Self::Import { user } => user.clear(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeAction {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl ChangeAction {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Updated => "updated",
+            Self::Deleted => "deleted",
+        }
+    }
+
+    pub fn try_from_str(action: &str) -> Result<Self, String> {
+        match action {
+            "created" => Ok(Self::Created),
+            "updated" => Ok(Self::Updated),
+            "deleted" => Ok(Self::Deleted),
+            _ => Err(format!("invalid change action: '{}'", action)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FieldChange {
+    pub field: String,
+    pub before: Value,
+    pub after: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PlayerChange {
+    pub id: i64,
+    pub itsf_id: i32,
+    pub timestamp: i64,
+    pub action: ChangeAction,
+    pub source: ChangeSource,
+    pub changes: Vec<FieldChange>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DeletedPlayer {
+    pub itsf_id: i32,
+    /// Set if the player was deleted because it was merged into another one
+    pub merged_into: Option<i32>,
+}
+
+/// All players that changed after a given change sequence number.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChangeFeed {
+    /// The latest change sequence number, to be passed as `since` on the next query
+    pub seq: i64,
+    /// Set if `since` was ahead of the server, e.g. after the DB was restored from an archive. The feed then
+    /// contains all players, which replace everything the client has.
+    #[serde(default)]
+    pub reset: bool,
+    pub added: Vec<Player>,
+    pub updated: Vec<Player>,
+    pub deleted: Vec<DeletedPlayer>,
+}
+
+/// Compares the top-level fields of two serialized players. A missing player (i.e. one that is
+/// created or deleted) is passed as `Value::Null`, in which case all fields of the other one are listed.
+pub fn diff(before: &Value, after: &Value) -> Vec<FieldChange> {
+    let empty = serde_json::Map::new();
+    let before = before.as_object().unwrap_or(&empty);
+    let after = after.as_object().unwrap_or(&empty);
+
+    let mut fields: Vec<&String> = before.keys().chain(after.keys()).collect();
+    fields.sort();
+    fields.dedup();
+
+    fields
+        .into_iter()
+        .filter_map(|field| {
+            let old_value = before.get(field).unwrap_or(&Value::Null);
+            let new_value = after.get(field).unwrap_or(&Value::Null);
+            if old_value == new_value {
+                None
+            } else {
+                Some(FieldChange {
+                    field: field.clone(),
+                    before: old_value.clone(),
+                    after: new_value.clone(),
+                })
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn diff_lists_changed_fields() {
+        let before = json!({"first_name": "Anna", "birth_year": 0, "comments": []});
+        let after = json!({"first_name": "Anna", "birth_year": 1990, "comments": [{"text": "hi"}]});
+        let changes = diff(&before, &after);
+        let fields: Vec<_> = changes.iter().map(|change| change.field.as_str()).collect();
+        assert_eq!(fields, vec!["birth_year", "comments"]);
+        assert_eq!(changes[0].before, json!(0));
+        assert_eq!(changes[0].after, json!(1990));
+
+        assert_eq!(diff(&Value::Null, &after).len(), 3);
+        assert!(diff(&after, &after).is_empty());
+    }
+}
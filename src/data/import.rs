@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Seek;
+use std::path::Path;
+use zip::ZipArchive;
+
+use super::{db, export, Player};
+use crate::error::{Error, Result};
+
+/// How the contents of an archive are combined with the existing data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// The archive replaces all players, redirects and images
+    Replace,
+    /// Players are only taken from the archive if they were modified more recently there
+    Merge,
+}
+
+/// Lists the IDs of all players that were affected by an import.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ImportReport {
+    pub mode: ImportMode,
+    pub added: Vec<i32>,
+    pub updated: Vec<i32>,
+    pub skipped: Vec<i32>,
+    pub deleted: Vec<i32>,
+    pub images: usize,
+}
+
+impl ImportReport {
+    pub fn new(mode: ImportMode) -> Self {
+        Self {
+            mode,
+            added: Vec::new(),
+            updated: Vec::new(),
+            skipped: Vec::new(),
+            deleted: Vec::new(),
+            images: 0,
+        }
+    }
+}
+
+/// The verified contents of an exported archive, see `export::ZipExporter`.
+pub struct ArchiveContents {
+    zip: ZipArchive<File>,
+    pub players: Vec<Player>,
+    /// Timestamp of the last change of every player that has a history in the archive
+    pub modified: HashMap<i32, i64>,
+    pub redirects: Vec<(i32, i32)>,
+    pub slugs: Vec<(String, i32)>,
+    pub merges: Vec<db::DbMerge>,
+    /// Zip entry names of the player images
+    images: HashMap<i32, String>,
+}
+
+fn image_id(path: &str) -> Option<i32> {
+    path.strip_prefix(export::ZIP_IMAGE_DIRECTORY)?
+        .strip_prefix('/')?
+        .strip_suffix(".jpg")?
+        .parse()
+        .ok()
+}
+
+impl ArchiveContents {
+    /// Verifies the archive against its manifest, and reads all players from the contained DB.
+    /// The DB is migrated to the current schema if it was exported by an older version.
+    pub fn read(path: &Path) -> Result<Self> {
+        let path_name = path.to_string_lossy();
+        let mut file = File::open(path).map_err(|err| Error::io(&path_name, err))?;
+        let manifest = export::verify_archive(&mut file).map_err(Error::Invalid)?;
+        file.rewind().map_err(|err| Error::io(&path_name, err))?;
+        let mut zip = ZipArchive::new(file).map_err(|err| Error::Invalid(format!("Not a valid zip file: {}", err)))?;
+
+        let mut db_file = tempfile::NamedTempFile::new().map_err(|err| Error::io("imported DB", err))?;
+        {
+            let mut entry = zip
+                .by_name(export::ZIP_DATABASE_PATH)
+                .map_err(|err| Error::Invalid(format!("Failed to read {}: {}", export::ZIP_DATABASE_PATH, err)))?;
+            std::io::copy(&mut entry, &mut db_file).map_err(|err| Error::io(export::ZIP_DATABASE_PATH, err))?;
+        }
+
+        // invalid player data is an error of the archive, not of the server's DB
+        let mut archive_db = db::DbConnection::open(&db_file.path().to_string_lossy())?;
+        let mut players = Vec::new();
+        for player_id in archive_db.get_player_ids()? {
+            let player = archive_db
+                .read_player_json::<Player>(player_id)
+                .map_err(|err| Error::Invalid(err.to_string()))?;
+            players.push(player);
+        }
+        if players.len() != manifest.player_count {
+            return Err(Error::Invalid(format!(
+                "Archive contains {} players instead of {}",
+                players.len(),
+                manifest.player_count
+            )));
+        }
+        players.sort_by_key(|player| player.itsf_id);
+
+        let modified = archive_db
+            .get_last_history_entries()?
+            .into_iter()
+            .map(|(itsf_id, _, timestamp)| (itsf_id, timestamp))
+            .collect();
+        let redirects = archive_db.get_redirects()?;
+        let slugs = archive_db.get_slugs()?;
+        let merges = archive_db.get_merges()?;
+
+        let images = zip
+            .file_names()
+            .filter_map(|name| image_id(name).map(|id| (id, String::from(name))))
+            .collect();
+
+        Ok(Self {
+            zip,
+            players,
+            modified,
+            redirects,
+            slugs,
+            merges,
+            images,
+        })
+    }
+
+    pub fn has_image(&self, itsf_id: i32) -> bool {
+        self.images.contains_key(&itsf_id)
+    }
+
+    /// Writes the image of a player to `target`, if the archive contains one.
+    pub fn extract_image(&mut self, itsf_id: i32, target: &str) -> Result<bool> {
+        let Some(name) = self.images.get(&itsf_id) else {
+            return Ok(false);
+        };
+        let mut entry = self
+            .zip
+            .by_name(name)
+            .map_err(|err| Error::Invalid(format!("Failed to read {}: {}", name, err)))?;
+        let mut file = File::create(target).map_err(|err| Error::io(target, err))?;
+        std::io::copy(&mut entry, &mut file).map_err(|err| Error::io(target, err))?;
+        Ok(true)
+    }
+}
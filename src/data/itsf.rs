@@ -1,4 +1,4 @@
-#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
 #[repr(i8)]
 pub enum PlayerCategory {
     Men,
@@ -33,7 +33,7 @@ impl PlayerCategory {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
 #[repr(i8)]
 pub enum RankingCategory {
     #[serde(rename = "open")]
@@ -46,7 +46,7 @@ pub enum RankingCategory {
     Senior,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
 #[repr(i8)]
 pub enum RankingClass {
     #[serde(rename = "singles")]
@@ -57,7 +57,7 @@ pub enum RankingClass {
     Combined,
 }
 
-#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
 pub struct Ranking {
     pub year: i32,
     pub place: i32,
@@ -0,0 +1,49 @@
+use std::fs::{File, OpenOptions, TryLockError};
+
+use crate::error::{Error, Result};
+
+/// An exclusive lock on a DB, held by the server and by commands that change the DB. The server keeps all players
+/// in memory and would overwrite changes that other processes make, so they must not write while it runs.
+///
+/// The lock is a lock on the file `<database>.lock`, which the OS releases when the process ends.
+pub struct DatabaseLock {
+    _file: File,
+}
+
+impl DatabaseLock {
+    /// Fails with [`Error::Unavailable`] if another process holds the lock.
+    pub fn acquire(database: &str) -> Result<Self> {
+        let path = format!("{}.lock", database);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|err| Error::io(&path, err))?;
+        match file.try_lock() {
+            Ok(()) => Ok(Self { _file: file }),
+            Err(TryLockError::WouldBlock) => Err(Error::Unavailable(format!(
+                "{} is in use by another process, e.g. a running server",
+                database
+            ))),
+            Err(TryLockError::Error(err)) => Err(Error::io(&path, err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_one_holder_at_a_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = dir.path().join("db.sqlite");
+        let database = database.to_str().unwrap();
+
+        let lock = DatabaseLock::acquire(database).unwrap();
+        assert!(matches!(DatabaseLock::acquire(database), Err(Error::Unavailable(_))));
+        drop(lock);
+        let _lock = DatabaseLock::acquire(database).unwrap();
+    }
+}
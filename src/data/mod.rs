@@ -1,23 +1,56 @@
-use std::fs::File;
-use std::io::{Cursor, Read, Write};
+//! The player model and its storage.
+//!
+//! [`DatabaseRef`] keeps all players in memory and writes every change to an SQLite DB, where it is recorded
+//! in the player history. It can be cloned cheaply and shared between threads.
+
+use std::io::Write;
+use std::time::SystemTime;
 use std::{
     cell::RefCell,
-    collections::HashMap,
-    sync::{Arc, Mutex},
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard},
 };
-use zip::{CompressionMethod, ZipWriter};
+use zip::ZipWriter;
+
+use crate::error::{Error, Result};
 
+mod backup;
 mod db;
 pub mod dtfb;
+mod export;
+mod filter;
+mod history;
+mod import;
 pub mod itsf;
+mod lock;
+mod privacy;
+mod seeding;
+mod slug;
+mod table;
+#[cfg(test)]
+mod testing;
+mod view;
+
+pub use filter::{PlayerFilter, PlayerFilterParams};
+pub use history::{ChangeAction, ChangeFeed, ChangeSource, DeletedPlayer, FieldChange, PlayerChange};
+pub use import::{ImportMode, ImportReport};
+pub use lock::DatabaseLock;
+pub use privacy::{PrivateField, Redaction};
+pub use seeding::{Seed, SeedingParticipant, SeedingPlayer, SeedingRanking, SeedingRequest, TieBreak};
+pub use table::{ExportFormat, ExportTable};
+pub use view::{PlayerSection, PlayerView, PlayerViewParams};
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+/// Subdirectory of the image directory with the images of merged players, see `DatabaseRef::merge_players`
+const MERGED_IMAGE_DIRECTORY: &str = "merged";
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
 pub struct PlayerComment {
     pub timestamp: u32,
     pub text: String,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+/// A player with all known data from the ITSF and DTFB, identified by the ITSF license number.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
 pub struct Player {
     pub itsf_id: i32,
 
@@ -38,6 +71,114 @@ pub struct Player {
     pub comments: Vec<PlayerComment>,
 }
 
+impl Player {
+    /// Adds all data of `other` that this player doesn't have yet. On conflicting entries,
+    /// e.g. two placements in the same ranking, this player's data is kept.
+    fn absorb(&mut self, other: &Player) {
+        if self.birth_year == 0 {
+            self.birth_year = other.birth_year;
+        }
+        if self.country_code.is_none() {
+            self.country_code = other.country_code.clone();
+        }
+        if self.dtfb_id.is_none() {
+            self.dtfb_id = other.dtfb_id;
+        }
+
+        for ranking in &other.itsf_rankings {
+            if !self.itsf_rankings.iter().any(|r| r.matches(ranking)) {
+                self.itsf_rankings.push(*ranking);
+            }
+        }
+        for ranking in &other.dtfb_national_rankings {
+            if !self.dtfb_national_rankings.iter().any(|r| r.matches(ranking)) {
+                self.dtfb_national_rankings.push(*ranking);
+            }
+        }
+        for result in &other.dtfb_championship_results {
+            if !self.dtfb_championship_results.iter().any(|r| r.matches(result)) {
+                self.dtfb_championship_results.push(*result);
+            }
+        }
+        for team in &other.dtfb_league_teams {
+            if !self.dtfb_league_teams.iter().any(|t| t.year == team.year) {
+                self.dtfb_league_teams.push(team.clone());
+            }
+        }
+
+        self.comments.extend(other.comments.iter().cloned());
+        self.comments.sort_by_key(|c| c.timestamp);
+    }
+
+    /// Removes the data that `absorb` added from `other` to `original`, which this player is a later version of.
+    /// Everything else, e.g. rankings that were downloaded since, is kept.
+    fn unabsorb(&mut self, original: &Player, other: &Player) {
+        if original.birth_year == 0 && self.birth_year == other.birth_year {
+            self.birth_year = 0;
+        }
+        if original.country_code.is_none() && self.country_code == other.country_code {
+            self.country_code = None;
+        }
+        if original.dtfb_id.is_none() && self.dtfb_id == other.dtfb_id {
+            self.dtfb_id = None;
+        }
+
+        self.itsf_rankings.retain(|ranking| {
+            original.itsf_rankings.iter().any(|r| r.matches(ranking))
+                || !other.itsf_rankings.iter().any(|r| r.matches(ranking))
+        });
+        self.dtfb_national_rankings.retain(|ranking| {
+            original.dtfb_national_rankings.iter().any(|r| r.matches(ranking))
+                || !other.dtfb_national_rankings.iter().any(|r| r.matches(ranking))
+        });
+        self.dtfb_championship_results.retain(|result| {
+            original.dtfb_championship_results.iter().any(|r| r.matches(result))
+                || !other.dtfb_championship_results.iter().any(|r| r.matches(result))
+        });
+        self.dtfb_league_teams.retain(|team| {
+            original.dtfb_league_teams.iter().any(|t| t.year == team.year)
+                || !other.dtfb_league_teams.iter().any(|t| t.year == team.year)
+        });
+
+        let same = |a: &PlayerComment, b: &PlayerComment| a.timestamp == b.timestamp && a.text == b.text;
+        self.comments.retain(|comment| {
+            original.comments.iter().any(|c| same(c, comment)) || !other.comments.iter().any(|c| same(c, comment))
+        });
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct PlayerMerge {
+    pub id: i32,
+    pub timestamp: i64,
+    pub user: String,
+    pub source_itsf_id: i32,
+    pub target_itsf_id: i32,
+    pub reverted: bool,
+}
+
+impl From<db::DbMerge> for PlayerMerge {
+    fn from(merge: db::DbMerge) -> Self {
+        Self {
+            id: merge.id,
+            timestamp: merge.timestamp,
+            user: merge.user,
+            source_itsf_id: merge.source_itsf_id,
+            target_itsf_id: merge.target_itsf_id,
+            reverted: merge.reverted,
+        }
+    }
+}
+
+/// Identifies the state of a player, or of the whole DB, for caching purposes.
+#[derive(Debug, Clone, Copy)]
+pub struct Version {
+    /// Change sequence number of the last modification
+    pub seq: i64,
+    /// Time of the last modification, or of loading the DB, if it was modified before the history was recorded
+    pub timestamp: i64,
+}
+
 pub struct PlayerImage {
     pub itsf_id: i32,
     pub image_data: Vec<u8>,
@@ -46,77 +187,440 @@ pub struct PlayerImage {
 
 struct DatabaseInner {
     db: RefCell<db::DbConnection>,
+    loaded_at: i64,
     players: HashMap<i32, Player>,
+    redirects: HashMap<i32, i32>,
+    dtfb_ids: HashMap<i32, i32>,
+    slugs: HashMap<String, i32>,
+    player_slugs: HashMap<i32, String>,
+    version: Version,
+    player_versions: HashMap<i32, Version>,
+}
+
+impl DatabaseInner {
+    fn load(db: db::DbConnection, loaded_at: i64) -> Result<Self> {
+        let mut inner = DatabaseInner {
+            db: RefCell::new(db),
+            loaded_at,
+            players: HashMap::new(),
+            redirects: HashMap::new(),
+            dtfb_ids: HashMap::new(),
+            slugs: HashMap::new(),
+            player_slugs: HashMap::new(),
+            version: Version {
+                seq: 0,
+                timestamp: loaded_at,
+            },
+            player_versions: HashMap::new(),
+        };
+        inner.refresh()?;
+        Ok(inner)
+    }
+
+    /// Re-reads all players and indices from the DB, replacing the cached state.
+    /// Players whose data can't be read are moved to the quarantine table, instead of failing completely.
+    fn refresh(&mut self) -> Result<()> {
+        let mut db = self.db.borrow_mut();
+
+        let mut players = HashMap::new();
+        for player_id in db.get_player_ids()? {
+            match db.read_player_json(player_id) {
+                Ok(player) => {
+                    players.insert(player_id, player);
+                }
+                Err(Error::Data(err)) => {
+                    log::error!("Quarantining player {}: {}", player_id, err);
+                    db.quarantine_player(player_id, chrono::Utc::now().timestamp(), &err)?;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        log::error!("Loaded {} players", players.len());
+
+        self.players = players;
+        self.redirects = db.get_redirects()?.into_iter().collect();
+        self.version = match db.get_last_history_entry()? {
+            Some((seq, timestamp)) => Version { seq, timestamp },
+            None => Version {
+                seq: 0,
+                timestamp: self.loaded_at,
+            },
+        };
+        self.player_versions = db
+            .get_last_history_entries()?
+            .into_iter()
+            .map(|(itsf_id, seq, timestamp)| (itsf_id, Version { seq, timestamp }))
+            .collect();
+
+        self.dtfb_ids.clear();
+        let slugs = db.get_slugs()?;
+        drop(db);
+        self.set_slugs(slugs);
+
+        let mut player_ids: Vec<i32> = self.players.keys().copied().collect();
+        player_ids.sort();
+
+        self.db.borrow_mut().begin_transaction()?;
+        let result = player_ids
+            .into_iter()
+            .try_for_each(|player_id| self.index_player(player_id));
+        match result {
+            Ok(()) => self.db.borrow_mut().commit_transaction(),
+            Err(err) => {
+                self.db.borrow_mut().rollback_transaction()?;
+                Err(err)
+            }
+        }
+    }
+
+    fn set_slugs(&mut self, slugs: Vec<(String, i32)>) {
+        self.slugs.clear();
+        self.player_slugs.clear();
+        for (slug, itsf_id) in slugs {
+            self.player_slugs.entry(itsf_id).or_insert_with(|| slug.clone());
+            self.slugs.insert(slug, itsf_id);
+        }
+    }
+
+    /// Runs `f` inside a single SQL transaction. If it fails, the transaction is rolled back,
+    /// and the cached state is re-read from the DB, since `f` may have modified it already.
+    fn transaction<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        self.db.borrow_mut().begin_transaction()?;
+        let result = f(self).and_then(|value| {
+            self.db.borrow_mut().commit_transaction()?;
+            Ok(value)
+        });
+
+        if result.is_err() {
+            if let Err(err) = self.db.borrow_mut().rollback_transaction() {
+                log::error!("Failed to roll back transaction: {}", err);
+            }
+            if let Err(err) = self.refresh() {
+                log::error!("Failed to reload DB after rollback: {}", err);
+            }
+        }
+        result
+    }
+
+    /// Follows the redirects of merged players, and returns the ID that `itsf_id` was finally merged into.
+    fn canonical_id(&self, itsf_id: i32) -> i32 {
+        let mut id = itsf_id;
+        for _ in 0..=self.redirects.len() {
+            match self.redirects.get(&id) {
+                Some(new_id) => id = *new_id,
+                None => break,
+            }
+        }
+        id
+    }
+
+    /// Stores a new version of a player, and records its differences to the previous version in the history.
+    fn write_player(&mut self, player: Player, source: &ChangeSource) -> Result<()> {
+        let itsf_id = player.itsf_id;
+        let before = match self.players.get(&itsf_id) {
+            Some(player) => serde_json::to_value(player)?,
+            None => serde_json::Value::Null,
+        };
+        let after = serde_json::to_value(&player)?;
+
+        let changes = history::diff(&before, &after);
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let action = if before.is_null() {
+            ChangeAction::Created
+        } else {
+            ChangeAction::Updated
+        };
+
+        let timestamp = chrono::Utc::now().timestamp();
+        let seq = self.db.borrow_mut().transaction(|db| {
+            db.write_player_json(itsf_id, &player)?;
+            db.add_history_entry(itsf_id, timestamp, action.to_str(), source, &changes)
+        })?;
+        self.version = Version { seq, timestamp };
+        self.player_versions.insert(itsf_id, self.version);
+
+        self.unindex_dtfb_id(itsf_id);
+        self.players.insert(itsf_id, player);
+        self.index_player(itsf_id)
+    }
+
+    fn remove_player(&mut self, itsf_id: i32, source: &ChangeSource) -> Result<()> {
+        let Some(player) = self.players.get(&itsf_id) else {
+            return Ok(());
+        };
+        let before = serde_json::to_value(player)?;
+        let changes = history::diff(&before, &serde_json::Value::Null);
+
+        let timestamp = chrono::Utc::now().timestamp();
+        let seq = self.db.borrow_mut().transaction(|db| {
+            db.delete_player(itsf_id)?;
+            db.add_history_entry(itsf_id, timestamp, ChangeAction::Deleted.to_str(), source, &changes)
+        })?;
+        self.version = Version { seq, timestamp };
+        self.player_versions.remove(&itsf_id);
+        self.unindex_dtfb_id(itsf_id);
+        self.players.remove(&itsf_id);
+        Ok(())
+    }
+
+    /// Removes the player's DTFB ID from the index, unless it belongs to another player by now.
+    fn unindex_dtfb_id(&mut self, itsf_id: i32) {
+        let dtfb_id = self.players.get(&itsf_id).and_then(|player| player.dtfb_id);
+        if let Some(dtfb_id) = dtfb_id {
+            if self.dtfb_ids.get(&dtfb_id) == Some(&itsf_id) {
+                self.dtfb_ids.remove(&dtfb_id);
+            }
+        }
+    }
+
+    /// Updates the secondary lookup indices for a player that was added or modified.
+    fn index_player(&mut self, itsf_id: i32) -> Result<()> {
+        let Some(player) = self.players.get(&itsf_id) else {
+            return Ok(());
+        };
+
+        if let Some(dtfb_id) = player.dtfb_id {
+            self.dtfb_ids.insert(dtfb_id, itsf_id);
+        }
+
+        // slugs are only assigned once, so they stay valid even if the player's name changes later on
+        if !self.player_slugs.contains_key(&itsf_id) {
+            let base = slug::base_slug(player);
+            let mut slug = base.clone();
+            let mut counter = 1;
+            while self.slugs.contains_key(&slug) {
+                counter += 1;
+                slug = format!("{}-{}", base, counter);
+            }
+
+            self.db.borrow_mut().write_slug(&slug, itsf_id)?;
+            self.slugs.insert(slug.clone(), itsf_id);
+            self.player_slugs.insert(itsf_id, slug);
+        }
+        Ok(())
+    }
 }
 
+/// Applies all pending migrations to the DB at `path`, and returns the versions of the applied ones.
+pub fn migrate_database(path: &str) -> Result<Vec<String>> {
+    db::DbConnection::migrate(path)
+}
+
+/// Shared handle of the player DB. All players are cached in memory, so reads don't need to query the DB.
 #[derive(Clone)]
 pub struct DatabaseRef {
     database_path: String,
     image_directory: String,
+    loaded_at: i64,
     inner: Arc<Mutex<DatabaseInner>>,
 }
 
-fn add_zip_file(
-    writer: &mut ZipWriter<Cursor<&mut Vec<u8>>>,
-    compression: CompressionMethod,
-    path: &str,
-) -> Result<(), ()> {
-    let mut f = File::open(path).map_err(|_| ())?;
-    let mut data = Vec::new();
-    f.read_to_end(&mut data).map_err(|_| ())?;
-
-    let options = zip::write::FileOptions::default().compression_method(compression);
-    // writer.start_file(path.split("/").last().unwrap(), options).map_err(|_| ())?;
-    writer.start_file(path, options).map_err(|_| ())?;
-    writer.write(&data).map_err(|_| ())?;
-
-    Ok(())
-}
-
 impl DatabaseRef {
-    pub fn load(path: &str, image_directory: &str) -> Self {
-        let mut db = db::DbConnection::open(path);
-        let mut players = HashMap::new();
+    /// Opens the SQLite DB at `path`, applies pending migrations and reads all players.
+    /// Player images are stored as files in `image_directory`.
+    pub fn load(path: &str, image_directory: &str) -> Result<Self> {
+        let loaded_at = chrono::Utc::now().timestamp();
+        let inner = DatabaseInner::load(db::DbConnection::open(path)?, loaded_at)?;
 
-        for player_id in db.get_player_ids() {
-            let player = db.read_player_json(player_id).expect("failed to read player");
-            players.insert(player_id, player);
+        let path_info = std::fs::metadata(image_directory).map_err(|err| Error::io(image_directory, err))?;
+        if !path_info.is_dir() {
+            return Err(Error::Io(format!("Not a directory: {}", image_directory)));
         }
-        log::error!("Loaded {} players", players.len());
-
-        let inner = DatabaseInner {
-            db: RefCell::new(db),
-            players,
-        };
 
-        let path_info = std::fs::metadata(image_directory).unwrap_or_else(|_| panic!("Can't open {}", image_directory));
-        assert!(path_info.is_dir(), "Not a directory: {}", image_directory);
-
-        Self {
+        Ok(Self {
             inner: Arc::new(Mutex::new(inner)),
             image_directory: String::from(image_directory),
             database_path: String::from(path),
+            loaded_at,
+        })
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, DatabaseInner>> {
+        self.inner
+            .lock()
+            .map_err(|_| Error::Unavailable(String::from("DB lock was poisoned")))
+    }
+
+    pub fn get_player(&self, itsf_id: i32) -> Result<Option<Player>> {
+        let inner = self.lock()?;
+        Ok(inner.players.get(&itsf_id).cloned())
+    }
+
+    /// Returns the players with the given IDs, in the same order, all from the same version of the DB. IDs of
+    /// merged players return the player that they were merged into.
+    pub fn get_players(&self, itsf_ids: &[i32]) -> Result<(Version, Vec<Option<Player>>)> {
+        let inner = self.lock()?;
+        let players = itsf_ids
+            .iter()
+            .map(|itsf_id| inner.players.get(&inner.canonical_id(*itsf_id)).cloned())
+            .collect();
+        Ok((inner.version, players))
+    }
+
+    /// Orders the participants of a tournament by their stored rankings, see [`SeedingRequest`].
+    pub fn seed(&self, request: &SeedingRequest) -> Result<Vec<Seed>> {
+        let inner = self.lock()?;
+        let inner = &*inner;
+        seeding::seed(request, |player| {
+            let itsf_id = match player {
+                SeedingPlayer::ItsfLic(itsf_id) => itsf_id,
+                SeedingPlayer::DtfbId(dtfb_id) => *inner.dtfb_ids.get(&dtfb_id)?,
+            };
+            inner.players.get(&inner.canonical_id(itsf_id))
+        })
+    }
+
+    /// Returns the ITSF ID of the player with the given DTFB ID.
+    pub fn find_player_by_dtfb_id(&self, dtfb_id: i32) -> Result<Option<i32>> {
+        let inner = self.lock()?;
+        Ok(inner.dtfb_ids.get(&dtfb_id).map(|id| inner.canonical_id(*id)))
+    }
+
+    /// Returns the ITSF ID of the player with the given slug.
+    pub fn find_player_by_slug(&self, slug: &str) -> Result<Option<i32>> {
+        let inner = self.lock()?;
+        Ok(inner.slugs.get(slug).map(|id| inner.canonical_id(*id)))
+    }
+
+    pub fn get_player_slug(&self, itsf_id: i32) -> Result<Option<String>> {
+        let inner = self.lock()?;
+        Ok(inner.player_slugs.get(&itsf_id).cloned())
+    }
+
+    /// The version of the whole DB, which changes whenever any player is modified.
+    pub fn get_version(&self) -> Result<Version> {
+        let inner = self.lock()?;
+        Ok(inner.version)
+    }
+
+    pub fn get_player_version(&self, itsf_id: i32) -> Result<Version> {
+        let inner = self.lock()?;
+        Ok(inner.player_versions.get(&itsf_id).copied().unwrap_or(Version {
+            seq: 0,
+            timestamp: self.loaded_at,
+        }))
+    }
+
+    /// Returns the IDs of all players that match `filter`, in ascending order.
+    pub fn find_players(&self, filter: &PlayerFilter) -> Result<Vec<i32>> {
+        let inner = self.lock()?;
+        let mut ids: Vec<i32> = inner
+            .players
+            .values()
+            .filter(|player| filter.matches(player))
+            .map(|player| player.itsf_id)
+            .collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// Returns the ID of the player that `itsf_id` was merged into, if it is the ID of a merged player.
+    pub fn get_redirect(&self, itsf_id: i32) -> Result<Option<i32>> {
+        let inner = self.lock()?;
+        if inner.redirects.contains_key(&itsf_id) {
+            Ok(Some(inner.canonical_id(itsf_id)))
+        } else {
+            Ok(None)
         }
     }
 
-    pub fn get_player(&self, itsf_id: i32) -> Option<Player> {
-        let inner = self.inner.lock().unwrap();
-        inner.players.get(&itsf_id).cloned()
+    pub fn add_player(&self, player: Player, source: &ChangeSource) -> Result<()> {
+        let mut inner = self.lock()?;
+        if inner.redirects.contains_key(&player.itsf_id) {
+            log::error!(
+                "Not adding player {}, it was merged into {}",
+                player.itsf_id,
+                inner.canonical_id(player.itsf_id)
+            );
+            return Ok(());
+        }
+        inner.write_player(player, source)
     }
 
-    pub fn get_player_ids(&self) -> Vec<i32> {
-        let inner = self.inner.lock().unwrap();
-        inner.players.keys().copied().collect()
+    /// Collects all players that were added, updated or deleted after the change sequence number `since`.
+    /// Passing 0 returns all players, since the DB may contain players that predate the change history.
+    pub fn get_changes_since(&self, since: i64) -> Result<ChangeFeed> {
+        let inner = self.lock()?;
+        if since <= 0 || since > inner.version.seq {
+            return Ok(ChangeFeed {
+                seq: inner.version.seq,
+                reset: since > 0,
+                added: inner.players.values().cloned().collect(),
+                updated: Vec::new(),
+                deleted: Vec::new(),
+            });
+        }
+
+        let entries = inner.db.borrow_mut().get_history_since(since)?;
+
+        // the first change within the range tells whether the player already existed before
+        let mut seen = HashSet::new();
+        let first_actions: Vec<(i32, String)> = entries
+            .into_iter()
+            .filter(|(_, itsf_id, _)| seen.insert(*itsf_id))
+            .map(|(_, itsf_id, action)| (itsf_id, action))
+            .collect();
+
+        let mut feed = ChangeFeed {
+            seq: inner.version.seq,
+            reset: false,
+            added: Vec::new(),
+            updated: Vec::new(),
+            deleted: Vec::new(),
+        };
+
+        for (itsf_id, first_action) in first_actions {
+            let was_created = first_action == ChangeAction::Created.to_str();
+            match inner.players.get(&itsf_id) {
+                Some(player) if was_created => feed.added.push(player.clone()),
+                Some(player) => feed.updated.push(player.clone()),
+                None if was_created => {}
+                None => feed.deleted.push(DeletedPlayer {
+                    itsf_id,
+                    merged_into: inner
+                        .redirects
+                        .contains_key(&itsf_id)
+                        .then(|| inner.canonical_id(itsf_id)),
+                }),
+            }
+        }
+
+        Ok(feed)
     }
 
-    pub fn add_player(&self, player: Player) {
-        let mut inner = self.inner.lock().unwrap();
-        inner.db.borrow_mut().write_player_json(player.itsf_id, &player);
-        inner.players.insert(player.itsf_id, player);
+    pub fn get_player_history(&self, itsf_id: i32) -> Result<Vec<PlayerChange>> {
+        let inner = self.lock()?;
+        let entries = inner.db.borrow_mut().get_history(itsf_id)?;
+
+        Ok(entries
+            .into_iter()
+            .filter_map(|entry| {
+                let change = ChangeAction::try_from_str(&entry.action).and_then(|action| {
+                    let source = serde_json::from_slice(&entry.source).map_err(|err| err.to_string())?;
+                    let changes = serde_json::from_slice(&entry.changes).map_err(|err| err.to_string())?;
+                    Ok(PlayerChange {
+                        id: entry.id,
+                        itsf_id: entry.itsf_id,
+                        timestamp: entry.timestamp,
+                        action,
+                        source,
+                        changes,
+                    })
+                });
+                change
+                    .map_err(|err| log::error!("invalid history entry {}: {}", entry.id, err))
+                    .ok()
+            })
+            .collect())
     }
 
     pub fn get_player_image(&self, itsf_id: i32) -> Option<PlayerImage> {
-        let path = format!("{}/{}.jpg", self.image_directory, itsf_id);
+        let path = self.image_path(itsf_id);
         std::fs::read(path).ok().map(|image_data| PlayerImage {
             itsf_id,
             image_data,
@@ -124,85 +628,812 @@ impl DatabaseRef {
         })
     }
 
-    pub fn set_player_image(&self, player_image: PlayerImage) {
-        let path = format!("{}/{}.jpg", self.image_directory, player_image.itsf_id);
-        std::fs::write(&path, player_image.image_data).unwrap_or_else(|_| panic!("Failed to write {}", path));
+    pub fn get_player_image_metadata(&self, itsf_id: i32) -> Option<std::fs::Metadata> {
+        std::fs::metadata(self.image_path(itsf_id)).ok()
+    }
+
+    /// Returns the number of images and the modification time of the newest one.
+    pub fn get_images_modified(&self) -> (usize, Option<SystemTime>) {
+        let mut count = 0;
+        let mut newest = None;
+        if let Ok(dir) = std::fs::read_dir(&self.image_directory) {
+            let files = dir
+                .filter_map(|entry| entry.ok()?.metadata().ok())
+                .filter(|metadata| metadata.is_file());
+            for modified in files.filter_map(|metadata| metadata.modified().ok()) {
+                count += 1;
+                newest = newest.max(Some(modified));
+            }
+        }
+        (count, newest)
+    }
+
+    fn image_path(&self, itsf_id: i32) -> String {
+        format!("{}/{}.jpg", self.image_directory, itsf_id)
     }
 
-    fn modify_player<F>(&self, itsf_id: i32, f: F)
+    /// Where the image of a merged player is kept if the player it was merged into has its own image.
+    fn merged_image_path(&self, merge_id: i32) -> String {
+        format!("{}/{}/{}.jpg", self.image_directory, MERGED_IMAGE_DIRECTORY, merge_id)
+    }
+
+    pub fn set_player_image(&self, player_image: PlayerImage) -> Result<()> {
+        if player_image.image_format != "jpg" {
+            return Err(Error::Invalid(format!(
+                "Unsupported image format: {}",
+                player_image.image_format
+            )));
+        }
+        let path = self.image_path(player_image.itsf_id);
+        std::fs::write(&path, player_image.image_data).map_err(|err| Error::io(&path, err))
+    }
+
+    fn modify_player<F>(&self, itsf_id: i32, source: &ChangeSource, f: F) -> Result<()>
     where
         F: FnOnce(&mut Player),
     {
-        let mut inner = self.inner.lock().unwrap();
+        let mut inner = self.lock()?;
+        let itsf_id = inner.canonical_id(itsf_id);
 
-        if let Some(player) = inner.players.get_mut(&itsf_id) {
-            f(player);
-        }
-
-        if let Some(player) = inner.players.get(&itsf_id) {
-            inner.db.borrow_mut().write_player_json(itsf_id, &player);
+        match inner.players.get(&itsf_id) {
+            Some(player) => {
+                let mut player = player.clone();
+                f(&mut player);
+                inner.write_player(player, source)
+            }
+            None => Ok(()),
         }
     }
 
-    pub fn add_player_itsf_ranking(&self, itsf_id: i32, ranking: itsf::Ranking) {
-        self.modify_player(itsf_id, |player| {
-            player.itsf_rankings.retain(|r| !ranking.matches(r));
-            player.itsf_rankings.push(ranking);
-        });
+    pub fn add_player_itsf_ranking(&self, itsf_id: i32, ranking: itsf::Ranking, source: &ChangeSource) -> Result<()> {
+        self.modify_player(itsf_id, source, |player| {
+            match player.itsf_rankings.iter_mut().find(|r| ranking.matches(r)) {
+                Some(existing) => *existing = ranking,
+                None => player.itsf_rankings.push(ranking),
+            }
+        })
     }
 
-    pub fn set_player_dtfb_id(&self, itsf_id: i32, dtfb_id: i32) {
-        self.modify_player(itsf_id, |player| {
+    pub fn set_player_dtfb_id(&self, itsf_id: i32, dtfb_id: i32, source: &ChangeSource) -> Result<()> {
+        self.modify_player(itsf_id, source, |player| {
             player.dtfb_id = Some(dtfb_id);
-        });
+        })
     }
 
-    pub fn add_player_dtfb_championship_result(&self, itsf_id: i32, result: dtfb::NationalChampionshipResult) {
-        self.modify_player(itsf_id, |player| {
-            player.dtfb_championship_results.retain(|r| !result.matches(r));
-            player.dtfb_championship_results.push(result);
-        });
+    pub fn add_player_dtfb_championship_result(
+        &self,
+        itsf_id: i32,
+        result: dtfb::NationalChampionshipResult,
+        source: &ChangeSource,
+    ) -> Result<()> {
+        self.modify_player(itsf_id, source, |player| {
+            match player.dtfb_championship_results.iter_mut().find(|r| result.matches(r)) {
+                Some(existing) => *existing = result,
+                None => player.dtfb_championship_results.push(result),
+            }
+        })
     }
 
-    pub fn add_player_dtfb_ranking(&self, itsf_id: i32, ranking: dtfb::NationalRanking) {
-        self.modify_player(itsf_id, |player| {
-            player.dtfb_national_rankings.retain(|r| !ranking.matches(r));
-            player.dtfb_national_rankings.push(ranking);
-        });
+    pub fn add_player_dtfb_ranking(
+        &self,
+        itsf_id: i32,
+        ranking: dtfb::NationalRanking,
+        source: &ChangeSource,
+    ) -> Result<()> {
+        self.modify_player(itsf_id, source, |player| {
+            match player.dtfb_national_rankings.iter_mut().find(|r| ranking.matches(r)) {
+                Some(existing) => *existing = ranking,
+                None => player.dtfb_national_rankings.push(ranking),
+            }
+        })
     }
 
-    pub fn add_player_dtfb_team(&self, itsf_id: i32, year: i32, name: String) {
-        self.modify_player(itsf_id, |player| {
-            player.dtfb_league_teams.retain(|t| t.year != year);
-            player.dtfb_league_teams.push(dtfb::NationalTeam { year, name });
-        });
+    pub fn add_player_dtfb_team(&self, itsf_id: i32, year: i32, name: String, source: &ChangeSource) -> Result<()> {
+        self.modify_player(itsf_id, source, |player| {
+            let team = dtfb::NationalTeam { year, name };
+            match player.dtfb_league_teams.iter_mut().find(|t| t.year == year) {
+                Some(existing) => *existing = team,
+                None => player.dtfb_league_teams.push(team),
+            }
+        })
     }
 
-    pub fn add_player_comment(&self, itsf_id: i32, text: String) {
-        self.modify_player(itsf_id, |player| {
+    pub fn add_player_comment(&self, itsf_id: i32, text: String, source: &ChangeSource) -> Result<()> {
+        self.modify_player(itsf_id, source, |player| {
             let timestamp = chrono::Utc::now().naive_local().timestamp() as u32;
             player.comments.push(PlayerComment { timestamp, text });
-            player.comments.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+            player.comments.sort_by_key(|c| c.timestamp);
+        })
+    }
+
+    /// Merges all data of the `source_id` player into the `target_id` player, and deletes the source player.
+    /// Requests for the source ID are redirected to the target afterwards.
+    pub fn merge_players(&self, source_id: i32, target_id: i32, user: &str) -> Result<PlayerMerge> {
+        if source_id == target_id {
+            return Err(Error::Invalid(String::from("Can't merge a player into itself")));
+        }
+
+        let mut inner = self.lock()?;
+        let inner = &mut *inner;
+
+        let source = inner
+            .players
+            .get(&source_id)
+            .ok_or(Error::NotFound(format!("No such player: {}", source_id)))?
+            .clone();
+        let target = inner
+            .players
+            .get(&target_id)
+            .ok_or(Error::NotFound(format!("No such player: {}", target_id)))?
+            .clone();
+
+        let mut merged = target.clone();
+        merged.absorb(&source);
+
+        let source_image = self.image_path(source_id);
+        let target_image = self.image_path(target_id);
+        let move_image = std::path::Path::new(&source_image).exists() && !std::path::Path::new(&target_image).exists();
+
+        let timestamp = chrono::Utc::now().timestamp();
+        let merge_id = inner.transaction(|inner| {
+            let merge_id = inner
+                .db
+                .borrow_mut()
+                .add_merge(timestamp, user, &source, source_id, &target, target_id, move_image)?;
+            let change_source = ChangeSource::Merge {
+                user: String::from(user),
+                merge_id,
+            };
+            inner.write_player(merged, &change_source)?;
+            inner.remove_player(source_id, &change_source)?;
+            inner.db.borrow_mut().write_redirect(source_id, target_id)?;
+            Ok(merge_id)
+        })?;
+
+        inner.redirects.insert(source_id, target_id);
+
+        if move_image {
+            std::fs::rename(&source_image, &target_image).map_err(|err| Error::io(&source_image, err))?;
+        } else if std::path::Path::new(&source_image).exists() {
+            // the target's image is kept, and the source's one is put aside until the merge is reverted
+            let merged_image = self.merged_image_path(merge_id);
+            std::fs::create_dir_all(format!("{}/{}", self.image_directory, MERGED_IMAGE_DIRECTORY))
+                .map_err(|err| Error::io(&merged_image, err))?;
+            std::fs::rename(&source_image, &merged_image).map_err(|err| Error::io(&source_image, err))?;
+        }
+
+        log::info!("{} merged player {} into {}", user, source_id, target_id);
+
+        Ok(PlayerMerge {
+            id: merge_id,
+            timestamp,
+            user: String::from(user),
+            source_itsf_id: source_id,
+            target_itsf_id: target_id,
+            reverted: false,
+        })
+    }
+
+    /// Undoes a merge, restoring the source player to the state it was in before the merge, and removing the data
+    /// that the merge added to the target player. Data that was added to the target player since then is kept.
+    pub fn revert_merge(&self, merge_id: i32, user: &str) -> Result<PlayerMerge> {
+        let mut inner = self.lock()?;
+        let inner = &mut *inner;
+        let mut db = inner.db.borrow_mut();
+
+        let merge = db
+            .get_merge(merge_id)?
+            .ok_or(Error::NotFound(format!("No such merge: {}", merge_id)))?;
+        if merge.reverted {
+            return Err(Error::Invalid(format!("Merge {} was already reverted", merge_id)));
+        }
+
+        let involved = [merge.source_itsf_id, merge.target_itsf_id];
+        let later_merge = db.get_merges()?.into_iter().find(|other| {
+            other.id > merge.id
+                && !other.reverted
+                && (involved.contains(&other.source_itsf_id) || involved.contains(&other.target_itsf_id))
         });
+        if let Some(later_merge) = later_merge {
+            return Err(Error::Invalid(format!(
+                "Merge {} has to be reverted first",
+                later_merge.id
+            )));
+        }
+        drop(db);
+
+        let current_target = inner
+            .players
+            .get(&merge.target_itsf_id)
+            .ok_or(Error::NotFound(format!("No such player: {}", merge.target_itsf_id)))?;
+        if inner.players.contains_key(&merge.source_itsf_id) {
+            return Err(Error::Invalid(format!(
+                "Player {} already exists",
+                merge.source_itsf_id
+            )));
+        }
+
+        let source: Player = serde_json::from_slice(&merge.source_json)
+            .map_err(|err| Error::Data(format!("JSON Error when loading merge {}: {}", merge_id, err)))?;
+        let original_target: Player = serde_json::from_slice(&merge.target_json)
+            .map_err(|err| Error::Data(format!("JSON Error when loading merge {}: {}", merge_id, err)))?;
+
+        let mut target = current_target.clone();
+        target.unabsorb(&original_target, &source);
+
+        inner.transaction(|inner| {
+            let mut db = inner.db.borrow_mut();
+            db.delete_redirect(merge.source_itsf_id)?;
+            db.set_merge_reverted(merge_id)?;
+            drop(db);
+            let change_source = ChangeSource::RevertMerge {
+                user: String::from(user),
+                merge_id,
+            };
+            inner.write_player(source, &change_source)?;
+            inner.write_player(target, &change_source)
+        })?;
+
+        inner.redirects.remove(&merge.source_itsf_id);
+
+        let source_image = self.image_path(merge.source_itsf_id);
+        let merged_image = self.merged_image_path(merge_id);
+        if merge.image_moved {
+            let target_image = self.image_path(merge.target_itsf_id);
+            std::fs::rename(&target_image, &source_image).map_err(|err| Error::io(&target_image, err))?;
+        } else if std::path::Path::new(&merged_image).exists() {
+            std::fs::rename(&merged_image, &source_image).map_err(|err| Error::io(&merged_image, err))?;
+        }
+
+        log::info!(
+            "reverted merge of player {} into {}",
+            merge.source_itsf_id,
+            merge.target_itsf_id
+        );
+
+        let mut merge = PlayerMerge::from(merge);
+        merge.reverted = true;
+        Ok(merge)
+    }
+
+    pub fn get_merges(&self) -> Result<Vec<PlayerMerge>> {
+        let inner = self.lock()?;
+        let merges = inner.db.borrow_mut().get_merges()?;
+        Ok(merges.into_iter().map(PlayerMerge::from).collect())
+    }
+
+    /// Writes a zip archive with a consistent snapshot of the DB and all player images to `writer`.
+    /// The archive is written incrementally, so it is never held in memory as a whole.
+    /// Fields hidden by `redaction` are removed from the snapshot, and images are left out if they are hidden.
+    pub fn write_zip_archive<W: Write>(&self, writer: W, redaction: &Redaction) -> Result<()> {
+        let snapshot = tempfile::NamedTempFile::new().map_err(|err| Error::io("DB snapshot", err))?;
+        backup::backup_database(&self.database_path, snapshot.path()).map_err(Error::Database)?;
+
+        // the manifest describes the snapshot, not the live DB, which may have changed in the meantime
+        let mut snapshot_db = db::DbConnection::open(&snapshot.path().to_string_lossy())?;
+        if !redaction.is_empty() {
+            privacy::redact_database(&mut snapshot_db, redaction)?;
+        }
+        let manifest = export::Manifest {
+            format_version: export::FORMAT_VERSION,
+            schema_version: snapshot_db.schema_version()?,
+            exported_at: chrono::Utc::now().timestamp(),
+            change_seq: snapshot_db.get_last_history_entry()?.map(|(seq, _)| seq).unwrap_or(0),
+            player_count: snapshot_db.get_player_ids()?.len(),
+            image_count: 0,
+            files: Vec::new(),
+        };
+        drop(snapshot_db);
+
+        let mut exporter = export::ZipExporter::new(ZipWriter::new_stream(writer));
+        exporter.add_database(snapshot.path()).map_err(Error::Io)?;
+        if !redaction.hides(PrivateField::Image) {
+            exporter.add_images(&self.image_directory).map_err(Error::Io)?;
+        }
+        exporter.finish(manifest).map_err(Error::Io)?;
+
+        Ok(())
     }
 
-    pub fn create_zip_file(&self) -> Result<Vec<u8>, ()> {
-        let mut buffer = Vec::new();
-        {
-            let mut zip = ZipWriter::new(Cursor::new(&mut buffer));
-            add_zip_file(&mut zip, CompressionMethod::Deflated, &self.database_path)?;
+    /// Writes the rows of `table` for all players that match `filter`. Players are looked up one by one,
+    /// so that the DB isn't locked while waiting for a slow writer.
+    pub fn write_export<W: Write>(
+        &self,
+        writer: W,
+        table: ExportTable,
+        format: ExportFormat,
+        filter: &PlayerFilter,
+        redaction: &Redaction,
+    ) -> Result<()> {
+        let mut rows = table::RowWriter::new(format, writer);
+        for itsf_id in self.find_players(filter)? {
+            let Some(mut player) = self.get_player(itsf_id)? else {
+                continue;
+            };
+            redaction.apply(&mut player);
+            let slug = self.get_player_slug(itsf_id)?.unwrap_or_default();
+            rows.write_player(table, &player, &slug).map_err(Error::Io)?;
+        }
+        rows.finish().map_err(Error::Io)
+    }
 
-            let options = zip::write::FileOptions::default().compression_method(CompressionMethod::Stored);
-            zip.add_directory("images", options).map_err(|_| ())?;
+    /// Imports an archive that was created by `write_zip_archive`. Changes are recorded in the player history
+    /// like any other change, and the cached state is re-read from the DB afterwards.
+    pub fn import_zip_archive(
+        &self,
+        path: &std::path::Path,
+        mode: ImportMode,
+        source: &ChangeSource,
+    ) -> Result<ImportReport> {
+        let mut archive = import::ArchiveContents::read(path)?;
+        let mut report = ImportReport::new(mode);
+        // images are only extracted after the transaction, so that a rollback doesn't leave them behind
+        let mut images = Vec::new();
 
-            let dir = std::fs::read_dir(&self.image_directory).map_err(|_| ())?;
-            for file in dir {
-                let file = file.map_err(|_| ())?.path();
-                let file = file.to_str().ok_or(())?;
-                add_zip_file(&mut zip, CompressionMethod::Deflated, file)?;
+        let mut inner = self.lock()?;
+        inner.transaction(|inner| {
+            let archive_ids: HashSet<i32> = archive.players.iter().map(|player| player.itsf_id).collect();
+            if mode == ImportMode::Replace {
+                // the slugs of the archive's players are taken before they are written, so that they are kept
+                inner.db.borrow_mut().replace_slugs(&archive.slugs)?;
+                inner.set_slugs(archive.slugs.clone());
+            }
+            for player in std::mem::take(&mut archive.players) {
+                let itsf_id = player.itsf_id;
+                let take_player = match (mode, inner.players.get(&itsf_id)) {
+                    (ImportMode::Replace, _) => true,
+                    (ImportMode::Merge, None) => !inner.redirects.contains_key(&itsf_id),
+                    (ImportMode::Merge, Some(_)) => {
+                        let local = inner.player_versions.get(&itsf_id).map(|v| v.timestamp).unwrap_or(0);
+                        let imported = archive.modified.get(&itsf_id).copied().unwrap_or(0);
+                        imported > local
+                    }
+                };
+
+                let is_new = !inner.players.contains_key(&itsf_id);
+                let seq = inner.version.seq;
+                if take_player {
+                    inner.write_player(player, source)?;
+                }
+                if inner.version.seq == seq {
+                    report.skipped.push(itsf_id);
+                } else if is_new {
+                    report.added.push(itsf_id);
+                } else {
+                    report.updated.push(itsf_id);
+                }
+
+                let take_image = match mode {
+                    ImportMode::Replace => true,
+                    ImportMode::Merge => take_player && !std::path::Path::new(&self.image_path(itsf_id)).exists(),
+                };
+                if take_image && archive.has_image(itsf_id) {
+                    images.push(itsf_id);
+                }
+            }
+
+            match mode {
+                ImportMode::Replace => {
+                    let removed: Vec<i32> = inner
+                        .players
+                        .keys()
+                        .copied()
+                        .filter(|id| !archive_ids.contains(id))
+                        .collect();
+                    for itsf_id in removed {
+                        inner.remove_player(itsf_id, source)?;
+                        // only the deletion is kept, so that `/changes` still reports it
+                        inner
+                            .db
+                            .borrow_mut()
+                            .delete_history_before(itsf_id, inner.version.seq)?;
+                        report.deleted.push(itsf_id);
+                    }
+
+                    // merges and redirects of the archive refer to its players, not to the replaced ones
+                    let mut db = inner.db.borrow_mut();
+                    db.replace_merges(&archive.merges)?;
+                    db.delete_redirects()?;
+                    for (old_itsf_id, new_itsf_id) in &archive.redirects {
+                        db.write_redirect(*old_itsf_id, *new_itsf_id)?;
+                    }
+                }
+                ImportMode::Merge => {
+                    let mut db = inner.db.borrow_mut();
+                    for (old_itsf_id, new_itsf_id) in &archive.redirects {
+                        if !inner.players.contains_key(old_itsf_id) && !inner.redirects.contains_key(old_itsf_id) {
+                            db.write_redirect(*old_itsf_id, *new_itsf_id)?;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        })?;
+
+        // redirects were only written to the DB, and are picked up from there
+        inner.refresh()?;
+        drop(inner);
+
+        for itsf_id in images {
+            if archive.extract_image(itsf_id, &self.image_path(itsf_id))? {
+                report.images += 1;
+            }
+        }
+
+        if mode == ImportMode::Replace {
+            let stale_images: Vec<i32> = std::fs::read_dir(&self.image_directory)
+                .map_err(|err| Error::io(&self.image_directory, err))?
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| {
+                    let name = entry.file_name();
+                    let itsf_id = name.to_str()?.strip_suffix(".jpg")?.parse::<i32>().ok()?;
+                    Some(itsf_id).filter(|id| !archive.has_image(*id))
+                })
+                .collect();
+            for itsf_id in stale_images {
+                let path = self.image_path(itsf_id);
+                std::fs::remove_file(&path).map_err(|err| Error::io(&path, err))?;
+            }
+
+            // images that were put aside by merges aren't exported, and belong to merges that were replaced
+            let merged_images = std::path::Path::new(&self.image_directory).join(MERGED_IMAGE_DIRECTORY);
+            if merged_images.exists() {
+                std::fs::remove_dir_all(&merged_images)
+                    .map_err(|err| Error::io(&merged_images.to_string_lossy(), err))?;
             }
         }
 
-        Ok(buffer)
+        report.skipped.sort();
+        report.added.sort();
+        report.updated.sort();
+        report.deleted.sort();
+
+        log::info!(
+            "imported archive: {} added, {} updated, {} skipped, {} deleted",
+            report.added.len(),
+            report.updated.len(),
+            report.skipped.len(),
+            report.deleted.len()
+        );
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{admin, player, ranking, TestDb};
+    use super::{export, ChangeAction, ChangeSource, ImportMode, PrivateField, Redaction};
+    use std::io::Cursor;
+
+    #[test]
+    fn merge_redirects_and_absorbs() {
+        let test = TestDb::new();
+        let mut source = player(1, "Anna", "Schmidt");
+        source.birth_year = 1990;
+        source.itsf_rankings.push(ranking(2020, 4));
+        test.add(source);
+        test.add(player(2, "Anna", "Schmidt"));
+
+        let merge = test.db.merge_players(1, 2, "admin").unwrap();
+        assert_eq!(merge.source_itsf_id, 1);
+        assert!(test.db.get_player(1).unwrap().is_none());
+        assert_eq!(test.db.get_redirect(1).unwrap(), Some(2));
+        let target = test.db.get_player(2).unwrap().unwrap();
+        assert_eq!(target.birth_year, 1990);
+        assert_eq!(target.itsf_rankings.len(), 1);
+
+        assert!(test.db.merge_players(2, 2, "admin").is_err());
+        assert!(test.db.merge_players(3, 2, "admin").is_err());
+    }
+
+    #[test]
+    fn revert_keeps_data_added_since_the_merge() {
+        let test = TestDb::new();
+        let mut source = player(1, "Anna", "Schmidt");
+        source.itsf_rankings.push(ranking(2020, 4));
+        test.add(source);
+        let mut target = player(2, "Anna", "Schmidt");
+        target.itsf_rankings.push(ranking(2021, 7));
+        test.add(target);
+
+        let merge = test.db.merge_players(1, 2, "admin").unwrap();
+        test.db.add_player_itsf_ranking(2, ranking(2022, 2), &admin()).unwrap();
+        test.db.add_player_comment(2, String::from("later"), &admin()).unwrap();
+
+        assert!(test.db.revert_merge(merge.id, "admin").unwrap().reverted);
+        let years = |id| -> Vec<i32> {
+            let mut years: Vec<_> = test
+                .db
+                .get_player(id)
+                .unwrap()
+                .unwrap()
+                .itsf_rankings
+                .iter()
+                .map(|r| r.year)
+                .collect();
+            years.sort();
+            years
+        };
+        assert_eq!(years(1), vec![2020]);
+        assert_eq!(years(2), vec![2021, 2022]);
+        assert_eq!(test.db.get_player(2).unwrap().unwrap().comments.len(), 1);
+        assert_eq!(test.db.get_redirect(1).unwrap(), None);
+        assert!(test.db.revert_merge(merge.id, "admin").is_err());
+    }
+
+    #[test]
+    fn history_records_changes_with_their_source() {
+        let test = TestDb::new();
+        test.add(player(1, "Anna", "Schmidt"));
+        test.db.add_player_itsf_ranking(1, ranking(2020, 4), &admin()).unwrap();
+        // writing the same data again is not a change
+        test.add(test.db.get_player(1).unwrap().unwrap());
+        test.db.add_player_comment(1, String::from("hi"), &admin()).unwrap();
+
+        let history = test.db.get_player_history(1).unwrap();
+        let actions: Vec<_> = history.iter().map(|change| change.action).collect();
+        assert_eq!(
+            actions,
+            vec![ChangeAction::Created, ChangeAction::Updated, ChangeAction::Updated]
+        );
+        assert!(history.windows(2).all(|pair| pair[0].id < pair[1].id));
+        assert_eq!(history[1].changes.len(), 1);
+        assert_eq!(history[1].changes[0].field, "itsf_rankings");
+        assert!(matches!(&history[2].source, ChangeSource::Admin { user } if user == "admin"));
+        assert_eq!(test.db.get_player_version(1).unwrap().seq, history[2].id);
+    }
+
+    #[test]
+    fn change_feed() {
+        let test = TestDb::new();
+        test.add(player(1, "Anna", "Schmidt"));
+        test.add(player(2, "Anna", "Schmidt"));
+        let feed = test.db.get_changes_since(0).unwrap();
+        assert_eq!((feed.added.len(), feed.reset), (2, false));
+
+        test.db.add_player_comment(1, String::from("hi"), &admin()).unwrap();
+        test.add(player(3, "Tom", "Meier"));
+        test.db.merge_players(2, 1, "admin").unwrap();
+        let changes = test.db.get_changes_since(feed.seq).unwrap();
+        assert!(changes.seq > feed.seq);
+        assert_eq!(changes.added.iter().map(|p| p.itsf_id).collect::<Vec<_>>(), vec![3]);
+        assert_eq!(changes.updated.iter().map(|p| p.itsf_id).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(changes.deleted.len(), 1);
+        assert_eq!(changes.deleted[0].merged_into, Some(1));
+
+        let unchanged = test.db.get_changes_since(changes.seq).unwrap();
+        assert!(unchanged.added.is_empty() && unchanged.updated.is_empty() && !unchanged.reset);
+
+        // a client that is ahead of the server has to start over
+        let ahead = test.db.get_changes_since(changes.seq + 10).unwrap();
+        assert_eq!(ahead.seq, changes.seq);
+        assert!(ahead.reset);
+        assert_eq!(ahead.added.len(), 2);
+    }
+
+    #[test]
+    fn dtfb_lookups_follow_changes() {
+        let test = TestDb::new();
+        test.add(player(1, "Anna", "Schmidt"));
+        test.db.set_player_dtfb_id(1, 10, &admin()).unwrap();
+        assert_eq!(test.db.find_player_by_dtfb_id(10).unwrap(), Some(1));
+        test.db.set_player_dtfb_id(1, 11, &admin()).unwrap();
+        assert_eq!(test.db.find_player_by_dtfb_id(10).unwrap(), None);
+        assert_eq!(test.db.find_player_by_dtfb_id(11).unwrap(), Some(1));
+
+        let mut source = player(2, "Anna", "Schmidt");
+        source.dtfb_id = Some(20);
+        test.add(source);
+        test.db.merge_players(2, 1, "admin").unwrap();
+        assert_eq!(test.db.find_player_by_dtfb_id(20).unwrap(), None);
+        assert_eq!(test.db.find_player_by_dtfb_id(11).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn slugs_are_unique_and_stable() {
+        let test = TestDb::new();
+        test.add(player(1, "Anna", "Schmidt"));
+        test.add(player(2, "Anna", "Schmidt"));
+        assert_eq!(test.db.find_player_by_slug("schmidt-anna").unwrap(), Some(1));
+        assert_eq!(test.db.find_player_by_slug("schmidt-anna-2").unwrap(), Some(2));
+
+        let mut renamed = player(1, "Anna", "Meier");
+        renamed.birth_year = 1990;
+        test.add(renamed);
+        assert_eq!(test.db.get_player_slug(1).unwrap().as_deref(), Some("schmidt-anna"));
+    }
+
+    #[test]
+    fn merge_puts_the_source_image_aside() {
+        let test = TestDb::new();
+        test.add(player(1, "Anna", "Schmidt"));
+        test.add(player(2, "Anna", "Schmidt"));
+        test.set_image(1, b"source");
+        test.set_image(2, b"target");
+
+        let merge = test.db.merge_players(1, 2, "admin").unwrap();
+        assert!(test.db.get_player_image(1).is_none());
+        assert_eq!(test.db.get_player_image(2).unwrap().image_data, b"target");
+        assert_eq!(test.db.get_images_modified().0, 1);
+
+        test.db.revert_merge(merge.id, "admin").unwrap();
+        assert_eq!(test.db.get_player_image(1).unwrap().image_data, b"source");
+        assert_eq!(test.db.get_player_image(2).unwrap().image_data, b"target");
+    }
+
+    #[test]
+    fn merge_moves_the_only_image() {
+        let test = TestDb::new();
+        test.add(player(1, "Anna", "Schmidt"));
+        test.add(player(2, "Anna", "Schmidt"));
+        test.set_image(1, b"source");
+
+        let merge = test.db.merge_players(1, 2, "admin").unwrap();
+        assert_eq!(test.db.get_player_image(2).unwrap().image_data, b"source");
+        test.db.revert_merge(merge.id, "admin").unwrap();
+        assert_eq!(test.db.get_player_image(1).unwrap().image_data, b"source");
+        assert!(test.db.get_player_image(2).is_none());
+    }
+
+    #[test]
+    fn archive_is_a_verified_snapshot() {
+        let test = TestDb::new();
+        test.add(player(1, "Anna", "Schmidt"));
+        test.add(player(2, "Tom", "Meier"));
+        test.set_image(1, b"image");
+
+        let mut archive = Vec::new();
+        test.db.write_zip_archive(&mut archive, &Redaction::default()).unwrap();
+        let manifest = export::verify_archive(Cursor::new(archive)).unwrap();
+        assert_eq!((manifest.player_count, manifest.image_count), (2, 1));
+        assert_eq!(manifest.change_seq, test.db.get_changes_since(0).unwrap().seq);
+
+        let mut archive = Vec::new();
+        let redaction = Redaction::new([PrivateField::Image]);
+        test.db.write_zip_archive(&mut archive, &redaction).unwrap();
+        let manifest = export::verify_archive(Cursor::new(archive)).unwrap();
+        assert_eq!((manifest.player_count, manifest.image_count), (2, 0));
+    }
+
+    fn export(test: &TestDb) -> tempfile::NamedTempFile {
+        let mut archive = tempfile::NamedTempFile::new().unwrap();
+        test.db.write_zip_archive(&mut archive, &Redaction::default()).unwrap();
+        archive
+    }
+
+    #[test]
+    fn replacing_import_drops_everything_of_removed_players() {
+        let exported = TestDb::new();
+        exported.add(player(1, "Anna", "Schmidt"));
+        exported.add(player(2, "Tom", "Meier"));
+        exported.add(player(3, "Tom", "Meier"));
+        exported.set_image(1, b"anna");
+        let exported_merge = exported.db.merge_players(3, 2, "admin").unwrap();
+        let archive = export(&exported);
+
+        let test = TestDb::new();
+        test.add(player(1, "Anna", "Schmidt-Neu"));
+        test.add(player(4, "Lisa", "Wolf"));
+        test.add(player(5, "Lisa", "Wolf"));
+        test.set_image(4, b"lisa");
+        test.set_image(5, b"lisa 2");
+        test.db.merge_players(5, 4, "admin").unwrap();
+
+        let report = test
+            .db
+            .import_zip_archive(archive.path(), ImportMode::Replace, &admin())
+            .unwrap();
+        assert_eq!(
+            (report.added, report.updated, report.deleted),
+            (vec![2], vec![1], vec![4])
+        );
+        assert_eq!(report.images, 1);
+
+        assert_eq!(test.db.get_player(1).unwrap().unwrap().last_name, "Schmidt");
+        assert!(test.db.get_player(4).unwrap().is_none());
+        assert!(test.db.get_player_image(4).is_none());
+        assert_eq!(test.db.get_player_image(1).unwrap().image_data, b"anna");
+
+        // merges, redirects and slugs are those of the archive
+        let merges = test.db.get_merges().unwrap();
+        assert_eq!(merges.len(), 1);
+        assert_eq!((merges[0].id, merges[0].source_itsf_id), (exported_merge.id, 3));
+        let (_, redirected) = test.db.get_players(&[3, 5]).unwrap();
+        assert_eq!(redirected[0].as_ref().map(|p| p.itsf_id), Some(2));
+        assert!(redirected[1].is_none());
+        assert_eq!(test.db.find_player_by_slug("wolf-lisa").unwrap(), None);
+        assert_eq!(
+            test.db.get_player_slug(2).unwrap(),
+            exported.db.get_player_slug(2).unwrap()
+        );
+
+        // only the deletion of a removed player is kept, for clients of `/changes`
+        let history = test.db.get_player_history(4).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].action, ChangeAction::Deleted);
+        assert!(test
+            .db
+            .get_changes_since(1)
+            .unwrap()
+            .deleted
+            .iter()
+            .any(|p| p.itsf_id == 4));
+
+        test.db.revert_merge(exported_merge.id, "admin").unwrap();
+        assert_eq!(test.db.get_player(3).unwrap().unwrap().itsf_id, 3);
+    }
+
+    #[test]
+    fn merging_import_only_adds() {
+        let exported = TestDb::new();
+        exported.add(player(1, "Anna", "Schmidt"));
+        exported.add(player(2, "Tom", "Meier"));
+        let archive = export(&exported);
+
+        let test = TestDb::new();
+        test.add(player(1, "Anna", "Schmidt-Neu"));
+        test.add(player(3, "Lisa", "Wolf"));
+
+        let report = test
+            .db
+            .import_zip_archive(archive.path(), ImportMode::Merge, &admin())
+            .unwrap();
+        assert_eq!((report.added, report.skipped), (vec![2], vec![1]));
+        assert!(report.deleted.is_empty());
+        assert_eq!(test.db.get_player(1).unwrap().unwrap().last_name, "Schmidt-Neu");
+        assert!(test.db.get_player(3).unwrap().is_some());
+        assert!(matches!(
+            test.db.get_player_history(2).unwrap()[0].source,
+            ChangeSource::Admin { .. }
+        ));
+    }
+
+    #[test]
+    fn merging_import_keeps_the_newest_record() {
+        let exported = TestDb::new();
+        exported.add(player(1, "Anna", "Schmidt-Neu"));
+        exported.add(player(2, "Tom", "Meier-Neu"));
+        exported.set_image(1, b"new anna");
+        exported.set_image(2, b"new tom");
+        let archive = export(&exported);
+
+        let test = TestDb::new();
+        test.add(player(1, "Anna", "Schmidt"));
+        test.add(player(2, "Tom", "Meier"));
+        // Anna was last changed before the export, Tom after it
+        let now = chrono::Utc::now().timestamp();
+        test.set_modified(1, now - 3600);
+        test.set_modified(2, now + 3600);
+
+        let report = test
+            .db
+            .import_zip_archive(archive.path(), ImportMode::Merge, &admin())
+            .unwrap();
+        assert_eq!((report.updated, report.skipped), (vec![1], vec![2]));
+        assert_eq!(report.images, 1);
+        assert_eq!(test.db.get_player(1).unwrap().unwrap().last_name, "Schmidt-Neu");
+        assert_eq!(test.db.get_player(2).unwrap().unwrap().last_name, "Meier");
+        assert_eq!(test.db.get_player_image(1).unwrap().image_data, b"new anna");
+        assert!(test.db.get_player_image(2).is_none());
+    }
+
+    #[test]
+    fn rejects_damaged_archives() {
+        let exported = TestDb::new();
+        exported.add(player(1, "Anna", "Schmidt"));
+        let archive = export(&exported);
+        let mut data = std::fs::read(archive.path()).unwrap();
+        let middle = data.len() / 2;
+        data[middle] ^= 0xff;
+        std::fs::write(archive.path(), data).unwrap();
+
+        let test = TestDb::new();
+        test.add(player(2, "Tom", "Meier"));
+        assert!(test
+            .db
+            .import_zip_archive(archive.path(), ImportMode::Replace, &admin())
+            .is_err());
+        assert!(test.db.get_player(2).unwrap().is_some());
     }
 }
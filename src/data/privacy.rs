@@ -0,0 +1,225 @@
+use std::collections::BTreeSet;
+
+use super::{db, history, ChangeAction, ChangeSource, Player, PlayerChange};
+use crate::error::Result;
+
+/// Player data that can be hidden from callers without the `read` permission.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrivateField {
+    BirthYear,
+    Comments,
+    Image,
+    /// Users and tokens that made changes. They are always hidden from callers without the `read` permission,
+    /// and can't be listed in `PRIVATE_FIELDS`.
+    ChangeAuthors,
+}
+
+impl PrivateField {
+    pub fn try_from_str(field: &str) -> std::result::Result<Self, String> {
+        match field {
+            "birth_year" => Ok(Self::BirthYear),
+            "comments" => Ok(Self::Comments),
+            "image" => Ok(Self::Image),
+            _ => Err(format!("invalid private field: '{}'", field)),
+        }
+    }
+
+    /// Name of the field in the stored player JSON, and thus in history entries
+    fn json_field(self) -> Option<&'static str> {
+        match self {
+            Self::BirthYear => Some("birth_year"),
+            Self::Comments => Some("comments"),
+            Self::Image | Self::ChangeAuthors => None,
+        }
+    }
+}
+
+/// The fields that are removed from player data before it is handed out to a caller.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Redaction {
+    hidden: BTreeSet<PrivateField>,
+}
+
+impl Redaction {
+    pub fn new(hidden: impl IntoIterator<Item = PrivateField>) -> Self {
+        Self {
+            hidden: hidden.into_iter().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hidden.is_empty()
+    }
+
+    pub fn hides(&self, field: PrivateField) -> bool {
+        self.hidden.contains(&field)
+    }
+
+    /// Hidden fields are reset to the values that mean "unknown", so that redacted players keep their shape.
+    pub fn apply(&self, player: &mut Player) {
+        if self.hides(PrivateField::BirthYear) {
+            player.birth_year = 0;
+        }
+        if self.hides(PrivateField::Comments) {
+            player.comments.clear();
+        }
+    }
+
+    /// Removes the changes of hidden fields. Updates that only changed hidden fields are dropped completely.
+    pub fn apply_to_changes(&self, changes: &mut Vec<history::FieldChange>) {
+        let hidden: Vec<&str> = self.hidden.iter().filter_map(|field| field.json_field()).collect();
+        changes.retain(|change| !hidden.contains(&change.field.as_str()));
+    }
+
+    pub fn apply_to_history(&self, history: &mut Vec<PlayerChange>) {
+        for change in history.iter_mut() {
+            self.apply_to_changes(&mut change.changes);
+            if self.hides(PrivateField::ChangeAuthors) {
+                change.source.hide_user();
+            }
+        }
+        history.retain(|change| change.action != ChangeAction::Updated || !change.changes.is_empty());
+    }
+
+    /// Identifies the redaction in cache validators, as for `PlayerFilter::cache_key()`.
+    pub fn cache_key(&self) -> String {
+        let fields: Vec<&str> = self
+            .hidden
+            .iter()
+            .map(|field| match field {
+                PrivateField::BirthYear => "b",
+                PrivateField::Comments => "c",
+                PrivateField::Image => "i",
+                PrivateField::ChangeAuthors => "a",
+            })
+            .collect();
+        if fields.is_empty() {
+            String::from("all")
+        } else {
+            fields.join("")
+        }
+    }
+}
+
+/// Removes all hidden fields from a DB snapshot that is about to be exported. Quarantined players are
+/// dropped completely, since their data may contain anything.
+pub(super) fn redact_database(db: &mut db::DbConnection, redaction: &Redaction) -> Result<()> {
+    db.transaction(|db| {
+        for itsf_id in db.get_player_ids()? {
+            let mut player: Player = db.read_player_json(itsf_id)?;
+            redaction.apply(&mut player);
+            db.write_player_json(itsf_id, &player)?;
+        }
+
+        let hide_authors = redaction.hides(PrivateField::ChangeAuthors);
+        for entry in db.get_all_history()? {
+            let mut changes: Vec<history::FieldChange> = serde_json::from_slice(&entry.changes)?;
+            let count = changes.len();
+            redaction.apply_to_changes(&mut changes);
+            if changes.len() != count {
+                db.set_history_changes(entry.id, &changes)?;
+            }
+            if hide_authors {
+                let mut source: ChangeSource = serde_json::from_slice(&entry.source)?;
+                source.hide_user();
+                db.set_history_source(entry.id, &source)?;
+            }
+        }
+
+        for merge in db.get_merges()? {
+            let mut source: Player = serde_json::from_slice(&merge.source_json)?;
+            let mut target: Player = serde_json::from_slice(&merge.target_json)?;
+            redaction.apply(&mut source);
+            redaction.apply(&mut target);
+            db.set_merge_json(merge.id, &source, &target)?;
+            if hide_authors {
+                db.set_merge_user(merge.id, "")?;
+            }
+        }
+
+        db.clear_quarantine()
+    })?;
+    db.vacuum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::testing::{admin, player};
+
+    fn change(action: ChangeAction, fields: &[&str]) -> PlayerChange {
+        PlayerChange {
+            id: 1,
+            itsf_id: 1,
+            timestamp: 0,
+            action,
+            source: admin(),
+            changes: fields
+                .iter()
+                .map(|field| history::FieldChange {
+                    field: String::from(*field),
+                    before: serde_json::Value::Null,
+                    after: serde_json::Value::Null,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn history_hides_fields_and_authors() {
+        let mut history = vec![
+            change(ChangeAction::Created, &["birth_year", "last_name"]),
+            change(ChangeAction::Updated, &["birth_year"]),
+            change(ChangeAction::Updated, &["comments", "country_code"]),
+        ];
+        let redaction = Redaction::new([PrivateField::BirthYear, PrivateField::ChangeAuthors]);
+        redaction.apply_to_history(&mut history);
+
+        let fields: Vec<Vec<&str>> = history
+            .iter()
+            .map(|change| change.changes.iter().map(|c| c.field.as_str()).collect())
+            .collect();
+        assert_eq!(fields, vec![vec!["last_name"], vec!["comments", "country_code"]]);
+        assert!(history
+            .iter()
+            .all(|change| matches!(&change.source, ChangeSource::Admin { user } if user.is_empty())));
+
+        let mut visible = vec![change(ChangeAction::Updated, &["birth_year"])];
+        Redaction::default().apply_to_history(&mut visible);
+        assert!(matches!(&visible[0].source, ChangeSource::Admin { user } if user == "admin"));
+    }
+
+    #[test]
+    fn exported_db_hides_authors() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = db::DbConnection::open(dir.path().join("db.sqlite").to_str().unwrap()).unwrap();
+        let mut anna = player(1, "Anna", "Schmidt");
+        anna.birth_year = 1990;
+        db.write_player_json(1, &anna).unwrap();
+        db.add_history_entry(
+            1,
+            0,
+            ChangeAction::Created.to_str(),
+            &admin(),
+            &Vec::<history::FieldChange>::new(),
+        )
+        .unwrap();
+        db.add_merge(0, "admin", &anna, 2, &anna, 1, false).unwrap();
+
+        let redaction = Redaction::new([PrivateField::BirthYear, PrivateField::ChangeAuthors]);
+        redact_database(&mut db, &redaction).unwrap();
+
+        assert_eq!(db.read_player_json::<Player>(1).unwrap().birth_year, 0);
+        let source: ChangeSource = serde_json::from_slice(&db.get_all_history().unwrap()[0].source).unwrap();
+        assert!(matches!(source, ChangeSource::Admin { user } if user.is_empty()));
+        assert_eq!(db.get_merges().unwrap()[0].user, "");
+    }
+
+    #[test]
+    fn cache_keys_name_the_hidden_fields() {
+        assert_eq!(Redaction::default().cache_key(), "all");
+        let redaction = Redaction::new([PrivateField::ChangeAuthors, PrivateField::BirthYear]);
+        assert_eq!(redaction.cache_key(), "ba");
+    }
+}
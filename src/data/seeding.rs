@@ -0,0 +1,497 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+
+use super::{dtfb, itsf, Player};
+use crate::error::{Error, Result};
+
+/// A player of a seeding request, identified by ITSF license or DTFB ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SeedingPlayer {
+    ItsfLic(i32),
+    DtfbId(i32),
+}
+
+impl fmt::Display for SeedingPlayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ItsfLic(id) => write!(f, "ITSF license {}", id),
+            Self::DtfbId(id) => write!(f, "DTFB ID {}", id),
+        }
+    }
+}
+
+/// A single player, or a doubles pair.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct SeedingParticipant {
+    pub players: Vec<SeedingPlayer>,
+}
+
+/// A stored ranking that participants are seeded by.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum SeedingRanking {
+    Itsf {
+        category: itsf::RankingCategory,
+        class: itsf::RankingClass,
+        year: i32,
+    },
+    Dtfb {
+        category: dtfb::ChampionshipCategory,
+        year: i32,
+    },
+}
+
+impl SeedingRanking {
+    fn place(&self, player: &Player) -> Option<i32> {
+        match *self {
+            Self::Itsf { category, class, year } => player
+                .itsf_rankings
+                .iter()
+                .find(|r| r.year == year && r.category == category && r.class == class)
+                .map(|r| r.place),
+            Self::Dtfb { category, year } => player
+                .dtfb_national_rankings
+                .iter()
+                .find(|r| r.year == year && r.category == category)
+                .map(|r| r.place),
+        }
+    }
+}
+
+/// Returns the name that a value is serialized as, e.g. `open` for `RankingCategory::Open`.
+fn serialized_name<T: serde::Serialize>(value: T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+impl fmt::Display for SeedingRanking {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Itsf { category, class, year } => write!(
+                f,
+                "ITSF {} {} {}",
+                serialized_name(category),
+                serialized_name(class),
+                year
+            ),
+            Self::Dtfb { category, year } => write!(f, "DTFB {} {}", serialized_name(category), year),
+        }
+    }
+}
+
+/// How participants with the same place, or the same sum of places, are ordered.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TieBreak {
+    /// By the following rankings of the request, then by the order of the participants
+    #[default]
+    NextRanking,
+    /// Pairs by the better place of their players, then by the order of the participants
+    BestPlayer,
+    /// By the order of the participants
+    InputOrder,
+}
+
+/// Body of `/seeding`.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct SeedingRequest {
+    pub participants: Vec<SeedingParticipant>,
+    /// The reference ranking, followed by its fallbacks. Each participant is seeded by the first ranking that
+    /// all of its players are ranked in, and participants that need a later fallback are seeded after the others.
+    /// Pairs of which only one player is ranked follow, by that player's place in the first ranking they are
+    /// ranked in, and participants without any ranked player come last.
+    pub rankings: Vec<SeedingRanking>,
+    #[serde(default)]
+    pub tie_break: TieBreak,
+}
+
+/// A position of the seed list.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct Seed {
+    /// Starting at 1
+    pub seed: usize,
+    /// Index of the participant in the request
+    pub participant: usize,
+    pub itsf_lics: Vec<i32>,
+    /// Index of the ranking that the participant was seeded by, if any
+    pub ranking: Option<usize>,
+    /// Places of the players in that ranking
+    pub places: Vec<i32>,
+    pub reason: String,
+}
+
+struct Entry {
+    participant: usize,
+    itsf_lics: Vec<i32>,
+    /// Places of all players in each of the rankings, `None` if one of them is not ranked in it
+    places: Vec<Option<Vec<i32>>>,
+    /// The first ranking that all players are ranked in, or the number of rankings
+    tier: usize,
+    /// Of pairs that aren't ranked together in any ranking: the only player who is ranked
+    partial: Option<PartialRank>,
+}
+
+/// The place of the ranked player of a pair whose partner isn't ranked.
+struct PartialRank {
+    ranking: usize,
+    itsf_lic: i32,
+    place: i32,
+}
+
+impl Entry {
+    fn sum(&self, ranking: usize) -> Option<i32> {
+        self.places.get(ranking)?.as_ref().map(|places| places.iter().sum())
+    }
+
+    fn best(&self, ranking: usize) -> Option<i32> {
+        self.places.get(ranking)?.as_ref()?.iter().copied().min()
+    }
+
+    /// Tier, then the ranking of a partially ranked pair, then the sum of places or the place of its ranked player
+    fn primary_key(&self) -> (usize, usize, i32) {
+        match &self.partial {
+            Some(partial) => (self.tier, partial.ranking, partial.place),
+            None => (self.tier, self.tier, self.sum(self.tier).unwrap_or(i32::MAX)),
+        }
+    }
+
+    fn describe_places(&self, ranking: usize) -> String {
+        match &self.places[ranking] {
+            None => String::from("not ranked"),
+            Some(places) if places.len() == 1 => format!("place {}", places[0]),
+            Some(places) => {
+                let places: Vec<String> = places.iter().map(i32::to_string).collect();
+                format!(
+                    "places {} = {}",
+                    places.join(" + "),
+                    self.sum(ranking).unwrap_or_default()
+                )
+            }
+        }
+    }
+}
+
+/// What decided the order of two participants with the same primary key.
+enum Decider {
+    Ranking(usize),
+    BestPlayer,
+    InputOrder,
+}
+
+/// Orders `None` after all places.
+fn compare_places(a: Option<i32>, b: Option<i32>) -> Ordering {
+    a.unwrap_or(i32::MAX).cmp(&b.unwrap_or(i32::MAX))
+}
+
+fn tie_break(policy: TieBreak, rankings: usize, a: &Entry, b: &Entry) -> (Ordering, Decider) {
+    match policy {
+        TieBreak::NextRanking => {
+            for ranking in a.tier + 1..rankings {
+                let order = compare_places(a.sum(ranking), b.sum(ranking));
+                if order != Ordering::Equal {
+                    return (order, Decider::Ranking(ranking));
+                }
+            }
+        }
+        TieBreak::BestPlayer => {
+            let order = compare_places(a.best(a.tier), b.best(b.tier));
+            if order != Ordering::Equal {
+                return (order, Decider::BestPlayer);
+            }
+        }
+        TieBreak::InputOrder => {}
+    }
+    (a.participant.cmp(&b.participant), Decider::InputOrder)
+}
+
+/// Orders the participants of `request` by their places in its rankings. `lookup` returns the stored player
+/// of a participant's player, if there is one.
+pub(super) fn seed<'a>(
+    request: &SeedingRequest,
+    lookup: impl Fn(SeedingPlayer) -> Option<&'a Player>,
+) -> Result<Vec<Seed>> {
+    let rankings = &request.rankings;
+    if rankings.is_empty() {
+        return Err(Error::Invalid(String::from("At least one ranking is required")));
+    }
+
+    let mut entries = Vec::new();
+    let mut unknown = Vec::new();
+    let mut seen = HashSet::new();
+    for (index, participant) in request.participants.iter().enumerate() {
+        if !(1..=2).contains(&participant.players.len()) {
+            return Err(Error::Invalid(format!(
+                "Participant {} has to be one player or a pair",
+                index
+            )));
+        }
+        let mut players = Vec::new();
+        for player in &participant.players {
+            match lookup(*player) {
+                Some(found) if !seen.insert(found.itsf_id) => {
+                    return Err(Error::Invalid(format!("{} is listed twice", player)));
+                }
+                Some(found) => players.push(found),
+                None => unknown.push(player.to_string()),
+            }
+        }
+
+        let places: Vec<Option<Vec<i32>>> = rankings
+            .iter()
+            .map(|ranking| players.iter().map(|player| ranking.place(player)).collect())
+            .collect();
+        let tier = places.iter().position(Option::is_some).unwrap_or(rankings.len());
+        let partial = if tier == rankings.len() {
+            rankings.iter().enumerate().find_map(|(ranking, spec)| {
+                let (place, itsf_lic) = players
+                    .iter()
+                    .filter_map(|player| Some((spec.place(player)?, player.itsf_id)))
+                    .min()?;
+                Some(PartialRank {
+                    ranking,
+                    itsf_lic,
+                    place,
+                })
+            })
+        } else {
+            None
+        };
+        entries.push(Entry {
+            participant: index,
+            itsf_lics: players.iter().map(|player| player.itsf_id).collect(),
+            places,
+            tier,
+            partial,
+        });
+    }
+    if !unknown.is_empty() {
+        return Err(Error::Invalid(format!("Unknown players: {}", unknown.join(", "))));
+    }
+
+    let policy = request.tie_break;
+    entries.sort_by(|a, b| {
+        a.primary_key()
+            .cmp(&b.primary_key())
+            .then_with(|| tie_break(policy, rankings.len(), a, b).0)
+    });
+
+    let seeds = entries
+        .iter()
+        .enumerate()
+        .map(|(position, entry)| {
+            let mut reason = match (rankings.get(entry.tier), &entry.partial) {
+                (Some(ranking), _) => format!("{} in {}", entry.describe_places(entry.tier), ranking),
+                (None, Some(partial)) => format!(
+                    "only ITSF license {} is ranked: place {} in {}; seeded after the participants that are \
+                     ranked in full",
+                    partial.itsf_lic, partial.place, rankings[partial.ranking]
+                ),
+                (None, None) => String::from("not ranked in any of the rankings"),
+            };
+            if entry.tier > 0 && entry.tier < rankings.len() {
+                let skipped: Vec<String> = rankings[..entry.tier].iter().map(|r| r.to_string()).collect();
+                reason += &format!(" (not ranked in {})", skipped.join(", "));
+            }
+
+            // a tie is explained by what decided the order with the previous or the next participant
+            let tied = [position.checked_sub(1), Some(position + 1)]
+                .into_iter()
+                .flatten()
+                .filter_map(|other| entries.get(other).map(|e| (other, e)))
+                .find(|(_, other)| other.primary_key() == entry.primary_key());
+            if let Some((other_position, other)) = tied {
+                let decided = match tie_break(policy, rankings.len(), entry, other).1 {
+                    Decider::Ranking(ranking) => format!(
+                        "{}: {} vs. {}",
+                        rankings[ranking],
+                        entry.describe_places(ranking),
+                        other.describe_places(ranking)
+                    ),
+                    Decider::BestPlayer => format!(
+                        "the best player: place {} vs. place {}",
+                        entry.best(entry.tier).unwrap_or_default(),
+                        other.best(other.tier).unwrap_or_default()
+                    ),
+                    Decider::InputOrder => String::from("the order of the participants"),
+                };
+                reason += &format!("; tie with seed {} broken by {}", other_position + 1, decided);
+            }
+
+            Seed {
+                seed: position + 1,
+                participant: entry.participant,
+                itsf_lics: entry.itsf_lics.clone(),
+                ranking: match &entry.partial {
+                    Some(partial) => Some(partial.ranking),
+                    None => (entry.tier < rankings.len()).then_some(entry.tier),
+                },
+                places: match &entry.partial {
+                    Some(partial) => vec![partial.place],
+                    None => entry.places.get(entry.tier).cloned().flatten().unwrap_or_default(),
+                },
+                reason,
+            }
+        })
+        .collect();
+    Ok(seeds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::testing::{player, ranking};
+
+    /// Players with places in the open singles rankings of 2024 and 2023, 0 if they aren't ranked.
+    fn players(places: &[(i32, i32, i32)]) -> Vec<Player> {
+        places
+            .iter()
+            .map(|&(itsf_id, place_2024, place_2023)| {
+                let mut player = player(itsf_id, "First", "Last");
+                for (year, place) in [(2024, place_2024), (2023, place_2023)] {
+                    if place > 0 {
+                        player.itsf_rankings.push(ranking(year, place));
+                    }
+                }
+                player
+            })
+            .collect()
+    }
+
+    fn request(participants: &[&[i32]], tie_break: TieBreak) -> SeedingRequest {
+        let open_singles = |year| SeedingRanking::Itsf {
+            category: itsf::RankingCategory::Open,
+            class: itsf::RankingClass::Singles,
+            year,
+        };
+        SeedingRequest {
+            participants: participants
+                .iter()
+                .map(|players| SeedingParticipant {
+                    players: players.iter().map(|id| SeedingPlayer::ItsfLic(*id)).collect(),
+                })
+                .collect(),
+            rankings: vec![open_singles(2024), open_singles(2023)],
+            tie_break,
+        }
+    }
+
+    fn seed_all(players: &[Player], request: &SeedingRequest) -> Result<Vec<Seed>> {
+        seed(request, |player| match player {
+            SeedingPlayer::ItsfLic(itsf_id) => players.iter().find(|p| p.itsf_id == itsf_id),
+            SeedingPlayer::DtfbId(_) => None,
+        })
+    }
+
+    fn order(seeds: &[Seed]) -> Vec<usize> {
+        seeds.iter().map(|seed| seed.participant).collect()
+    }
+
+    #[test]
+    fn seeds_by_the_first_ranking_with_all_players() {
+        let players = players(&[(1, 5, 0), (2, 1, 0), (4, 0, 3), (5, 0, 0)]);
+        let seeds = seed_all(&players, &request(&[&[1], &[4], &[5], &[2]], TieBreak::NextRanking)).unwrap();
+
+        assert_eq!(order(&seeds), vec![3, 0, 1, 2]);
+        assert_eq!(seeds[0].seed, 1);
+        assert_eq!(seeds[0].reason, "place 1 in ITSF open singles 2024");
+        assert_eq!((seeds[2].ranking, seeds[2].places.clone()), (Some(1), vec![3]));
+        assert_eq!(
+            seeds[2].reason,
+            "place 3 in ITSF open singles 2023 (not ranked in ITSF open singles 2024)"
+        );
+        assert_eq!((seeds[3].ranking, seeds[3].places.clone()), (None, vec![]));
+        assert_eq!(seeds[3].reason, "not ranked in any of the rankings");
+    }
+
+    #[test]
+    fn pairs_with_one_ranked_player_follow_the_ranked_pairs() {
+        let players = players(&[
+            (1, 5, 0),
+            (6, 2, 0),
+            (2, 1, 0),
+            (3, 10, 0),
+            (11, 0, 2),
+            (4, 0, 3),
+            (7, 0, 0),
+            (8, 0, 1),
+            (9, 20, 0),
+            (10, 0, 0),
+            (5, 0, 0),
+            (12, 0, 0),
+        ]);
+        let participants: [&[i32]; 6] = [&[5, 12], &[7, 8], &[9, 10], &[11, 4], &[2, 3], &[1, 6]];
+        let seeds = seed_all(&players, &request(&participants, TieBreak::NextRanking)).unwrap();
+
+        assert_eq!(order(&seeds), vec![5, 4, 3, 2, 1, 0]);
+        assert_eq!(seeds[0].reason, "places 5 + 2 = 7 in ITSF open singles 2024");
+        assert_eq!(seeds[0].places, vec![5, 2]);
+        assert_eq!((seeds[3].ranking, seeds[3].places.clone()), (Some(0), vec![20]));
+        assert_eq!(
+            seeds[3].reason,
+            "only ITSF license 9 is ranked: place 20 in ITSF open singles 2024; seeded after the participants \
+             that are ranked in full"
+        );
+        assert_eq!((seeds[4].ranking, seeds[4].places.clone()), (Some(1), vec![1]));
+        assert!(seeds[4]
+            .reason
+            .starts_with("only ITSF license 8 is ranked: place 1 in ITSF open singles 2023"));
+        assert_eq!(seeds[5].ranking, None);
+    }
+
+    #[test]
+    fn breaks_ties_by_the_policy() {
+        let singles = players(&[(1, 3, 9), (2, 3, 4), (3, 3, 0)]);
+        let seeds = seed_all(&singles, &request(&[&[1], &[2], &[3]], TieBreak::NextRanking)).unwrap();
+        assert_eq!(order(&seeds), vec![1, 0, 2]);
+        assert_eq!(
+            seeds[0].reason,
+            "place 3 in ITSF open singles 2024; tie with seed 2 broken by ITSF open singles 2023: \
+             place 4 vs. place 9"
+        );
+        let seeds = seed_all(&singles, &request(&[&[1], &[2], &[3]], TieBreak::InputOrder)).unwrap();
+        assert_eq!(order(&seeds), vec![0, 1, 2]);
+        assert!(seeds[2]
+            .reason
+            .ends_with("tie with seed 2 broken by the order of the participants"));
+
+        let pairs = players(&[(1, 4, 0), (2, 6, 0), (3, 8, 0), (4, 2, 0)]);
+        let seeds = seed_all(&pairs, &request(&[&[1, 2], &[3, 4]], TieBreak::BestPlayer)).unwrap();
+        assert_eq!(order(&seeds), vec![1, 0]);
+        assert!(seeds[0]
+            .reason
+            .ends_with("tie with seed 2 broken by the best player: place 2 vs. place 4"));
+    }
+
+    #[test]
+    fn rejects_invalid_requests() {
+        let players = players(&[(1, 1, 0), (2, 2, 0), (3, 3, 0)]);
+        let error = |request: SeedingRequest| match seed_all(&players, &request) {
+            Err(Error::Invalid(message)) => message,
+            other => panic!("unexpected result: {:?}", other.map(|seeds| order(&seeds))),
+        };
+
+        let mut no_rankings = request(&[&[1]], TieBreak::NextRanking);
+        no_rankings.rankings.clear();
+        assert_eq!(error(no_rankings), "At least one ranking is required");
+        assert_eq!(
+            error(request(&[&[1], &[1, 2, 3]], TieBreak::NextRanking)),
+            "Participant 1 has to be one player or a pair"
+        );
+        assert_eq!(
+            error(request(&[&[], &[1]], TieBreak::NextRanking)),
+            "Participant 0 has to be one player or a pair"
+        );
+        assert_eq!(
+            error(request(&[&[1, 2], &[2]], TieBreak::NextRanking)),
+            "ITSF license 2 is listed twice"
+        );
+        assert_eq!(
+            error(request(&[&[1, 4], &[5]], TieBreak::NextRanking)),
+            "Unknown players: ITSF license 4, ITSF license 5"
+        );
+    }
+}
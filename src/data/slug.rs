@@ -0,0 +1,55 @@
+use super::Player;
+
+/// Creates the human-readable part of a player slug, e.g. `mueller-thomas-ger`.
+pub fn base_slug(player: &Player) -> String {
+    let mut parts = vec![player.last_name.as_str(), player.first_name.as_str()];
+    if let Some(country_code) = &player.country_code {
+        parts.push(country_code);
+    }
+
+    let mut slug = String::new();
+    for part in parts {
+        // German umlauts get their usual transliteration, everything else is reduced to plain ASCII
+        let part = part
+            .replace('ä', "ae")
+            .replace('ö', "oe")
+            .replace('ü', "ue")
+            .replace('Ä', "Ae")
+            .replace('Ö', "Oe")
+            .replace('Ü', "Ue")
+            .replace('ß', "ss");
+
+        for ch in deunicode::deunicode(&part).chars() {
+            if ch.is_ascii_alphanumeric() {
+                slug.push(ch.to_ascii_lowercase());
+            } else if !slug.is_empty() && !slug.ends_with('-') {
+                slug.push('-');
+            }
+        }
+        if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        String::from("player")
+    } else {
+        String::from(slug)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::player;
+    use super::base_slug;
+
+    #[test]
+    fn transliterates_names() {
+        let mut thomas = player(1, "Thomas", "Müller");
+        thomas.country_code = Some(String::from("GER"));
+        assert_eq!(base_slug(&thomas), "mueller-thomas-ger");
+        assert_eq!(base_slug(&player(2, "José", "Núñez-Pérez")), "nunez-perez-jose");
+        assert_eq!(base_slug(&player(3, "", "")), "player");
+    }
+}
@@ -0,0 +1,245 @@
+use serde::Serialize;
+use std::io::Write;
+
+use super::{dtfb, itsf, Player};
+
+/// The tables that player data can be exported as, one row per player, per ranking or per league team.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportTable {
+    Players,
+    ItsfRankings,
+    DtfbResults,
+    DtfbLeagueTeams,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Csv,
+    /// One JSON object per line
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PlayerRow<'a> {
+    itsf_id: i32,
+    slug: &'a str,
+    first_name: &'a str,
+    last_name: &'a str,
+    birth_year: i32,
+    country_code: Option<&'a str>,
+    category: &'static str,
+    dtfb_id: Option<i32>,
+}
+
+#[derive(Serialize)]
+struct ItsfRankingRow<'a> {
+    itsf_id: i32,
+    first_name: &'a str,
+    last_name: &'a str,
+    year: i32,
+    category: itsf::RankingCategory,
+    class: itsf::RankingClass,
+    place: i32,
+}
+
+/// A DTFB national championship result or national ranking, distinguished by `kind`.
+/// Rankings don't have a class.
+#[derive(Serialize)]
+struct DtfbResultRow<'a> {
+    itsf_id: i32,
+    dtfb_id: Option<i32>,
+    first_name: &'a str,
+    last_name: &'a str,
+    kind: &'static str,
+    year: i32,
+    category: dtfb::ChampionshipCategory,
+    class: Option<dtfb::ChampionshipClass>,
+    place: i32,
+}
+
+#[derive(Serialize)]
+struct DtfbLeagueTeamRow<'a> {
+    itsf_id: i32,
+    dtfb_id: Option<i32>,
+    first_name: &'a str,
+    last_name: &'a str,
+    year: i32,
+    team: &'a str,
+}
+
+/// Writes serializable rows either as CSV with a header line, or as NDJSON.
+pub enum RowWriter<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    Ndjson(W),
+}
+
+impl<W: Write> RowWriter<W> {
+    pub fn new(format: ExportFormat, writer: W) -> Self {
+        match format {
+            ExportFormat::Csv => Self::Csv(Box::new(csv::Writer::from_writer(writer))),
+            ExportFormat::Ndjson => Self::Ndjson(writer),
+        }
+    }
+
+    fn write<R: Serialize>(&mut self, row: &R) -> Result<(), String> {
+        match self {
+            Self::Csv(writer) => writer.serialize(row).map_err(|err| err.to_string()),
+            Self::Ndjson(writer) => {
+                serde_json::to_writer(&mut *writer, row).map_err(|err| err.to_string())?;
+                writer.write_all(b"\n").map_err(|err| err.to_string())
+            }
+        }
+    }
+
+    /// Writes all rows of `table` that belong to a single player.
+    pub fn write_player(&mut self, table: ExportTable, player: &Player, slug: &str) -> Result<(), String> {
+        match table {
+            ExportTable::Players => self.write(&PlayerRow {
+                itsf_id: player.itsf_id,
+                slug,
+                first_name: &player.first_name,
+                last_name: &player.last_name,
+                birth_year: player.birth_year,
+                country_code: player.country_code.as_deref(),
+                category: player.category.to_str(),
+                dtfb_id: player.dtfb_id,
+            }),
+            ExportTable::ItsfRankings => {
+                for ranking in &player.itsf_rankings {
+                    self.write(&ItsfRankingRow {
+                        itsf_id: player.itsf_id,
+                        first_name: &player.first_name,
+                        last_name: &player.last_name,
+                        year: ranking.year,
+                        category: ranking.category,
+                        class: ranking.class,
+                        place: ranking.place,
+                    })?;
+                }
+                Ok(())
+            }
+            ExportTable::DtfbResults => {
+                let row = |kind, year, category, class, place| DtfbResultRow {
+                    itsf_id: player.itsf_id,
+                    dtfb_id: player.dtfb_id,
+                    first_name: &player.first_name,
+                    last_name: &player.last_name,
+                    kind,
+                    year,
+                    category,
+                    class,
+                    place,
+                };
+                for result in &player.dtfb_championship_results {
+                    self.write(&row(
+                        "championship",
+                        result.year,
+                        result.category,
+                        Some(result.class),
+                        result.place,
+                    ))?;
+                }
+                for ranking in &player.dtfb_national_rankings {
+                    self.write(&row("ranking", ranking.year, ranking.category, None, ranking.place))?;
+                }
+                Ok(())
+            }
+            ExportTable::DtfbLeagueTeams => {
+                for team in &player.dtfb_league_teams {
+                    self.write(&DtfbLeagueTeamRow {
+                        itsf_id: player.itsf_id,
+                        dtfb_id: player.dtfb_id,
+                        first_name: &player.first_name,
+                        last_name: &player.last_name,
+                        year: team.year,
+                        team: &team.name,
+                    })?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn finish(self) -> Result<(), String> {
+        match self {
+            Self::Csv(mut writer) => writer.flush().map_err(|err| err.to_string()),
+            Self::Ndjson(mut writer) => writer.flush().map_err(|err| err.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::testing::{player, ranking};
+
+    fn export(table: ExportTable, format: ExportFormat) -> String {
+        let mut anna = player(1, "Anna", "Schmidt, Jr.");
+        anna.dtfb_id = Some(77);
+        anna.itsf_rankings.push(ranking(2020, 4));
+        anna.dtfb_league_teams.push(dtfb::NationalTeam {
+            year: 2021,
+            name: String::from("Kickers \"Nord\""),
+        });
+        anna.dtfb_national_rankings.push(dtfb::NationalRanking {
+            year: 2021,
+            place: 3,
+            category: dtfb::ChampionshipCategory::Women,
+        });
+
+        let mut output = Vec::new();
+        let mut rows = RowWriter::new(format, &mut output);
+        rows.write_player(table, &anna, "schmidt-jr-anna").unwrap();
+        rows.write_player(table, &player(2, "Tom", "Meier"), "meier-tom")
+            .unwrap();
+        rows.finish().unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn csv_rows() {
+        assert_eq!(
+            export(ExportTable::Players, ExportFormat::Csv),
+            "itsf_id,slug,first_name,last_name,birth_year,country_code,category,dtfb_id\n\
+             1,schmidt-jr-anna,Anna,\"Schmidt, Jr.\",0,,MEN,77\n\
+             2,meier-tom,Tom,Meier,0,,MEN,\n"
+        );
+        assert_eq!(
+            export(ExportTable::DtfbLeagueTeams, ExportFormat::Csv),
+            "itsf_id,dtfb_id,first_name,last_name,year,team\n\
+             1,77,Anna,\"Schmidt, Jr.\",2021,\"Kickers \"\"Nord\"\"\"\n"
+        );
+        assert_eq!(
+            export(ExportTable::DtfbResults, ExportFormat::Csv),
+            "itsf_id,dtfb_id,first_name,last_name,kind,year,category,class,place\n\
+             1,77,Anna,\"Schmidt, Jr.\",ranking,2021,women,,3\n"
+        );
+    }
+
+    #[test]
+    fn ndjson_rows() {
+        let lines: Vec<serde_json::Value> = export(ExportTable::ItsfRankings, ExportFormat::Ndjson)
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["place"], 4);
+        assert_eq!(lines[0]["class"], "singles");
+
+        let teams = export(ExportTable::DtfbLeagueTeams, ExportFormat::Ndjson);
+        let team: serde_json::Value = serde_json::from_str(teams.trim_end()).unwrap();
+        assert_eq!(team["team"], "Kickers \"Nord\"");
+        assert_eq!(team["dtfb_id"], 77);
+    }
+}
@@ -0,0 +1,70 @@
+//! Helpers for the tests of the data layer.
+
+use super::{itsf, ChangeSource, DatabaseRef, Player, PlayerImage};
+
+/// A DB in a temporary directory, which is removed when it is dropped.
+pub(crate) struct TestDb {
+    pub db: DatabaseRef,
+    _dir: tempfile::TempDir,
+}
+
+impl TestDb {
+    pub fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let images = dir.path().join("images");
+        std::fs::create_dir(&images).unwrap();
+        let db = DatabaseRef::load(dir.path().join("db.sqlite").to_str().unwrap(), images.to_str().unwrap()).unwrap();
+        Self { db, _dir: dir }
+    }
+
+    pub fn add(&self, player: Player) {
+        self.db.add_player(player, &admin()).unwrap();
+    }
+
+    /// Pretends that the player was last changed at `timestamp`.
+    pub fn set_modified(&self, itsf_id: i32, timestamp: i64) {
+        let mut inner = self.db.lock().unwrap();
+        inner.player_versions.get_mut(&itsf_id).unwrap().timestamp = timestamp;
+    }
+
+    pub fn set_image(&self, itsf_id: i32, data: &[u8]) {
+        let image = PlayerImage {
+            itsf_id,
+            image_data: data.to_vec(),
+            image_format: String::from("jpg"),
+        };
+        self.db.set_player_image(image).unwrap();
+    }
+}
+
+pub(crate) fn admin() -> ChangeSource {
+    ChangeSource::Admin {
+        user: String::from("admin"),
+    }
+}
+
+pub(crate) fn player(itsf_id: i32, first_name: &str, last_name: &str) -> Player {
+    Player {
+        itsf_id,
+        first_name: String::from(first_name),
+        last_name: String::from(last_name),
+        birth_year: 0,
+        country_code: None,
+        category: itsf::PlayerCategory::Men,
+        itsf_rankings: Vec::new(),
+        dtfb_id: None,
+        dtfb_national_rankings: Vec::new(),
+        dtfb_championship_results: Vec::new(),
+        dtfb_league_teams: Vec::new(),
+        comments: Vec::new(),
+    }
+}
+
+pub(crate) fn ranking(year: i32, place: i32) -> itsf::Ranking {
+    itsf::Ranking {
+        year,
+        place,
+        category: itsf::RankingCategory::Open,
+        class: itsf::RankingClass::Singles,
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::BTreeSet;
+
+use super::filter::cache_key_of;
+
+/// Optional parts of a player as returned by `/player/{itsf_lic}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlayerSection {
+    /// ITSF rankings of the combined class, which are left out by default
+    CombinedRankings,
+    /// All comments with their timestamps, not only the text of the latest one
+    Comments,
+    /// DTFB rankings, championship placements and league teams, included by default
+    Dtfb,
+    /// Size and modification time of the image
+    Image,
+    /// ITSF license, DTFB ID and ITSF category
+    Ids,
+}
+
+impl PlayerSection {
+    const DEFAULT: [Self; 1] = [Self::Dtfb];
+
+    pub fn try_from_str(section: &str) -> Result<Self, String> {
+        match section {
+            "combined_rankings" => Ok(Self::CombinedRankings),
+            "comments" => Ok(Self::Comments),
+            "dtfb" => Ok(Self::Dtfb),
+            "image" => Ok(Self::Image),
+            "ids" => Ok(Self::Ids),
+            _ => Err(format!("invalid section: '{}'", section)),
+        }
+    }
+}
+
+/// Query parameters that shape a player as returned by `/player/{itsf_lic}`. Without any, only the DTFB section
+/// is included, and everything is sorted newest first.
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PlayerViewParams {
+    /// Comma-separated sections to add: `combined_rankings`, `comments`, `dtfb`, `image`, `ids`
+    pub include: Option<String>,
+    /// Comma-separated sections to leave out, e.g. `dtfb`
+    pub exclude: Option<String>,
+    /// Only rankings, placements and teams of this year or later
+    pub from_year: Option<i32>,
+    /// Only rankings, placements and teams of this year or earlier
+    pub to_year: Option<i32>,
+    /// `newest_first` (default) or `oldest_first`
+    pub sort: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct PlayerView {
+    sections: BTreeSet<PlayerSection>,
+    from_year: Option<i32>,
+    to_year: Option<i32>,
+    oldest_first: bool,
+}
+
+impl Default for PlayerView {
+    fn default() -> Self {
+        Self {
+            sections: PlayerSection::DEFAULT.into_iter().collect(),
+            from_year: None,
+            to_year: None,
+            oldest_first: false,
+        }
+    }
+}
+
+impl PlayerView {
+    pub fn from_params(params: &PlayerViewParams) -> Result<Self, String> {
+        let sections = |list: &Option<String>| -> Result<Vec<PlayerSection>, String> {
+            list.iter()
+                .flat_map(|list| list.split(','))
+                .map(str::trim)
+                .filter(|section| !section.is_empty())
+                .map(PlayerSection::try_from_str)
+                .collect()
+        };
+
+        let mut view = Self::default();
+        view.sections.extend(sections(&params.include)?);
+        for section in sections(&params.exclude)? {
+            view.sections.remove(&section);
+        }
+        view.from_year = params.from_year;
+        view.to_year = params.to_year;
+        if let (Some(from), Some(to)) = (view.from_year, view.to_year) {
+            if from > to {
+                return Err(format!("from_year {} is after to_year {}", from, to));
+            }
+        }
+        view.oldest_first = match params.sort.as_deref().map(str::trim) {
+            None | Some("") | Some("newest_first") => false,
+            Some("oldest_first") => true,
+            Some(sort) => return Err(format!("invalid sort: '{}'", sort)),
+        };
+        Ok(view)
+    }
+
+    pub fn includes(&self, section: PlayerSection) -> bool {
+        self.sections.contains(&section)
+    }
+
+    /// Removes the entries outside of the year range, and sorts the rest by year.
+    pub fn select_years<T>(&self, entries: &mut Vec<T>, year: impl Fn(&T) -> i32) {
+        entries.retain(|entry| {
+            let year = year(entry);
+            self.from_year.is_none_or(|from| year >= from) && self.to_year.is_none_or(|to| year <= to)
+        });
+        if self.oldest_first {
+            entries.sort_by_key(|entry| year(entry));
+        } else {
+            entries.sort_by_key(|entry| std::cmp::Reverse(year(entry)));
+        }
+    }
+
+    /// Identifies the view in cache validators, so that differently shaped responses get different ETags.
+    pub fn cache_key(&self) -> String {
+        cache_key_of(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view(include: &str, exclude: &str, sort: Option<&str>) -> Result<PlayerView, String> {
+        PlayerView::from_params(&PlayerViewParams {
+            include: Some(String::from(include)),
+            exclude: Some(String::from(exclude)),
+            sort: sort.map(String::from),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn sections() {
+        let default = view("", "", None).unwrap();
+        assert_eq!(default, PlayerView::default());
+        assert!(default.includes(PlayerSection::Dtfb) && !default.includes(PlayerSection::Ids));
+
+        let shaped = view("ids, image", "dtfb", None).unwrap();
+        assert!(shaped.includes(PlayerSection::Ids) && shaped.includes(PlayerSection::Image));
+        assert!(!shaped.includes(PlayerSection::Dtfb));
+
+        assert!(view("everything", "", None).is_err());
+        assert!(view("", "", Some("random")).is_err());
+    }
+
+    #[test]
+    fn selects_and_sorts_years() {
+        let params = PlayerViewParams {
+            from_year: Some(2019),
+            to_year: Some(2021),
+            sort: Some(String::from("oldest_first")),
+            ..Default::default()
+        };
+        let mut years = vec![2022, 2019, 2021, 2018, 2020];
+        PlayerView::from_params(&params)
+            .unwrap()
+            .select_years(&mut years, |year| *year);
+        assert_eq!(years, vec![2019, 2020, 2021]);
+
+        let mut years = vec![2019, 2021, 2020];
+        PlayerView::default().select_years(&mut years, |year| *year);
+        assert_eq!(years, vec![2021, 2020, 2019]);
+
+        let reversed = PlayerViewParams {
+            from_year: Some(2021),
+            to_year: Some(2019),
+            ..Default::default()
+        };
+        assert!(PlayerView::from_params(&reversed).is_err());
+    }
+
+    #[test]
+    fn cache_keys_tell_views_apart() {
+        let default = PlayerView::default().cache_key();
+        assert_eq!(view("dtfb", "", Some("newest_first")).unwrap().cache_key(), default);
+        assert_ne!(view("ids", "", None).unwrap().cache_key(), default);
+        assert_ne!(view("", "", Some("oldest_first")).unwrap().cache_key(), default);
+    }
+}
@@ -0,0 +1,116 @@
+//! Errors of the data layer, which are also reported to HTTP clients.
+
+#[cfg(feature = "server")]
+use actix_web::http::header::RETRY_AFTER;
+#[cfg(feature = "server")]
+use actix_web::http::StatusCode;
+#[cfg(feature = "server")]
+use actix_web::{HttpResponse, ResponseError};
+use std::fmt;
+
+#[cfg(feature = "server")]
+use crate::json;
+
+/// Errors of the data layer and of request limits. They are reported to clients as JSON, with a status code matching the cause.
+#[derive(Debug)]
+pub enum Error {
+    /// A DB query failed
+    Database(String),
+    /// The DB is busy or can't be used right now, so the request may succeed if it is retried later
+    Unavailable(String),
+    /// Stored data couldn't be serialized or deserialized
+    Data(String),
+    /// Reading or writing a file failed
+    Io(String),
+    /// The request doesn't fit the current data, e.g. merging a player into itself
+    Invalid(String),
+    NotFound(String),
+    /// The client sent too many requests, and may retry after this many seconds
+    RateLimited(u64),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn io(path: &str, err: std::io::Error) -> Self {
+        Self::Io(format!("{}: {}", path, err))
+    }
+}
+
+#[cfg(feature = "server")]
+impl Error {
+    /// The message for clients. Server errors only name their status, since their details may contain paths or
+    /// queries; they are logged with the request ID instead.
+    pub fn public_message(&self) -> String {
+        let status = self.status_code();
+        if status.is_server_error() {
+            String::from(status.canonical_reason().unwrap_or("Internal Server Error"))
+        } else {
+            self.to_string()
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Database(msg) => write!(f, "DB error: {}", msg),
+            Self::Unavailable(msg) => write!(f, "DB unavailable: {}", msg),
+            Self::Data(msg) => write!(f, "Invalid data: {}", msg),
+            Self::Io(msg) => write!(f, "I/O error: {}", msg),
+            Self::Invalid(msg) | Self::NotFound(msg) => f.write_str(msg),
+            Self::RateLimited(seconds) => write!(f, "Too many requests, retry in {} seconds", seconds),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<diesel::result::Error> for Error {
+    fn from(err: diesel::result::Error) -> Self {
+        use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+        match &err {
+            // SQLite reports SQLITE_BUSY and SQLITE_LOCKED only through the message
+            DieselError::DatabaseError(DatabaseErrorKind::Unknown, info)
+                if info.message().contains("locked") || info.message().contains("busy") =>
+            {
+                Self::Unavailable(err.to_string())
+            }
+            _ => Self::Database(err.to_string()),
+        }
+    }
+}
+
+impl From<diesel::ConnectionError> for Error {
+    fn from(err: diesel::ConnectionError) -> Self {
+        Self::Unavailable(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Data(err.to_string())
+    }
+}
+
+#[cfg(feature = "server")]
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Database(_) | Self::Data(_) | Self::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Invalid(_) => StatusCode::BAD_REQUEST,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let Self::RateLimited(seconds) = self {
+            response.insert_header((RETRY_AFTER, seconds.to_string()));
+        }
+        response.json(json::err(self.public_message()))
+    }
+}
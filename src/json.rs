@@ -1,12 +1,4 @@
-#[derive(serde::Serialize)]
-struct JsonOk<T: serde::Serialize> {
-    data: T,
-}
-
-#[derive(serde::Serialize)]
-struct JsonErr<T: serde::Serialize> {
-    error: T,
-}
+use crate::api::{JsonErr, JsonOk};
 
 pub fn ok<T: serde::Serialize>(data: T) -> impl serde::Serialize {
     JsonOk { data }
@@ -0,0 +1,46 @@
+//! The ITSF player database: a model of table soccer players with their ITSF and DTFB results,
+//! stored in SQLite, and the scrapers that collect them.
+//!
+//! - [`data`] contains the player model and [`data::DatabaseRef`], which stores players and their history
+//! - [`scraping`] downloads rankings and player profiles from the ITSF and DTFB websites (feature `scraping`)
+//! - [`api`] contains the request and response bodies of the HTTP API
+//! - [`background`] tracks the progress of long-running jobs, e.g. downloads
+//! - [`users`] and [`tokens`] store the users of the server and their API tokens (feature `auth`)
+//! - [`server`] is the web server (feature `server`), which is configured with [`config`] and run by the `server`
+//!   binary, and [`cli`] contains its maintenance commands (feature `cli`)
+//!
+//! Without default features, only the model and the storage are built, without the web server, the command line,
+//! the password hashes of the users, the HTTP client or TLS.
+
+pub mod api;
+pub mod background;
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(feature = "cli")]
+pub mod config;
+pub mod data;
+pub mod error;
+#[cfg(feature = "scraping")]
+pub mod scraping;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "auth")]
+pub mod tokens;
+#[cfg(feature = "auth")]
+pub mod users;
+
+#[cfg(feature = "server")]
+mod auth;
+#[cfg(feature = "server")]
+mod caching;
+#[cfg(feature = "server")]
+mod json;
+#[cfg(feature = "server")]
+mod ratelimit;
+mod schema;
+#[cfg(feature = "server")]
+mod sessions;
+#[cfg(feature = "server")]
+mod streaming;
+#[cfg(feature = "tls")]
+mod tls;
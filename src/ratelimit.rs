@@ -0,0 +1,321 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::X_FORWARDED_FOR;
+use actix_web::HttpRequest;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::api::API_PREFIX;
+use crate::config::AuthConfig;
+use crate::error::{Error, Result};
+
+/// Buckets that haven't been used for this long are dropped
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Expensive endpoints, which get a stricter limit in addition to the general one
+const EXPENSIVE_PATHS: [&str; 3] = ["/db.zip", "/export/", "/import"];
+
+/// Time until failed logins are forgotten, and how long a client is locked out after too many of them
+const LOGIN_FAILURE_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket limiter: every key may do `limit` requests per minute, in bursts of up to `limit` requests.
+pub struct RateLimiter {
+    /// Requests per minute, or 0 for no limit
+    limit: u32,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32) -> Self {
+        Self {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn check(&self, key: &str) -> Result<()> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, now: Instant) -> Result<()> {
+        if self.limit == 0 {
+            return Ok(());
+        }
+        let rate = self.limit as f64 / 60.0;
+
+        let mut buckets = self
+            .buckets
+            .lock()
+            .map_err(|_| Error::Unavailable(String::from("rate limit lock was poisoned")))?;
+        if buckets.len() > 10_000 {
+            buckets.retain(|_, bucket| now.duration_since(bucket.updated) < IDLE_TIMEOUT);
+        }
+
+        let bucket = buckets.entry(String::from(key)).or_insert(Bucket {
+            tokens: self.limit as f64,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(self.limit as f64);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let retry_after = ((1.0 - bucket.tokens) / rate).ceil() as u64;
+            Err(Error::RateLimited(retry_after.max(1)))
+        }
+    }
+}
+
+struct Failures {
+    count: u32,
+    first: Instant,
+}
+
+/// Locks out clients that repeatedly send wrong credentials, so that passwords can't be guessed.
+/// Clients are identified by IP address, so that an attacker can't lock out a user everywhere.
+pub struct LoginGuard {
+    /// Failed attempts within `LOGIN_FAILURE_WINDOW` that lead to a lockout, or 0 for no limit
+    max_failures: u32,
+    failures: Mutex<HashMap<String, Failures>>,
+}
+
+impl LoginGuard {
+    pub fn new(max_failures: u32) -> Self {
+        Self {
+            max_failures,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, HashMap<String, Failures>>> {
+        self.failures
+            .lock()
+            .map_err(|_| Error::Unavailable(String::from("login guard lock was poisoned")))
+    }
+
+    /// Fails if the client is locked out. Checked before the credentials, so that they can't be tried anyway.
+    pub fn check(&self, client: &str) -> Result<()> {
+        self.check_at(client, Instant::now())
+    }
+
+    fn check_at(&self, client: &str, now: Instant) -> Result<()> {
+        if self.max_failures == 0 {
+            return Ok(());
+        }
+        let mut failures = self.lock()?;
+        failures.retain(|_, failures| now.duration_since(failures.first) < LOGIN_FAILURE_WINDOW);
+
+        match failures.get(client) {
+            Some(failures) if failures.count >= self.max_failures => {
+                let retry_after = LOGIN_FAILURE_WINDOW.saturating_sub(now.duration_since(failures.first));
+                Err(Error::RateLimited(retry_after.as_secs().max(1)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn record_failure(&self, client: &str) {
+        self.record_failure_at(client, Instant::now())
+    }
+
+    fn record_failure_at(&self, client: &str, now: Instant) {
+        if self.max_failures == 0 {
+            return;
+        }
+        let Ok(mut failures) = self.lock() else {
+            return;
+        };
+        let entry = failures
+            .entry(String::from(client))
+            .or_insert(Failures { count: 0, first: now });
+        entry.count += 1;
+        if entry.count == self.max_failures {
+            log::warn!("Locking out {} after {} failed logins", client, entry.count);
+        }
+    }
+}
+
+/// Returns the IP address of the client. `X-Forwarded-For` is only used if the request came from one of the
+/// trusted proxies, since clients could fake it. The client is the last address in it that isn't a trusted proxy.
+pub fn client_ip(req: &HttpRequest, trusted_proxies: &[IpAddr]) -> String {
+    let Some(peer) = req.peer_addr().map(|addr| addr.ip()) else {
+        return String::from("unknown");
+    };
+    if !trusted_proxies.contains(&peer) {
+        return peer.to_string();
+    }
+
+    let forwarded: Vec<&str> = req
+        .headers()
+        .get_all(X_FORWARDED_FOR)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect();
+    let mut client = peer;
+    for ip in forwarded.into_iter().rev() {
+        let Ok(ip) = ip.trim().parse() else {
+            break;
+        };
+        client = ip;
+        if !trusted_proxies.contains(&ip) {
+            break;
+        }
+    }
+    client.to_string()
+}
+
+pub struct RateLimits {
+    per_ip: RateLimiter,
+    per_user: RateLimiter,
+    expensive: RateLimiter,
+    pub logins: LoginGuard,
+    trusted_proxies: Vec<IpAddr>,
+}
+
+impl RateLimits {
+    pub fn new(config: &AuthConfig) -> Self {
+        Self {
+            per_ip: RateLimiter::new(config.rate_limit_per_ip),
+            per_user: RateLimiter::new(config.rate_limit_per_user),
+            expensive: RateLimiter::new(config.rate_limit_expensive),
+            logins: LoginGuard::new(config.max_login_failures),
+            trusted_proxies: config.trusted_proxies.clone(),
+        }
+    }
+
+    pub fn client_ip(&self, req: &HttpRequest) -> String {
+        client_ip(req, &self.trusted_proxies)
+    }
+
+    /// Applies the per-IP limits to every request, before it is handled.
+    pub fn check_request(&self, req: &ServiceRequest) -> Result<()> {
+        let ip = self.client_ip(req.request());
+        self.per_ip.check(&ip)?;
+        let path = req.path().strip_prefix(API_PREFIX).unwrap_or(req.path());
+        if EXPENSIVE_PATHS.iter().any(|expensive| path.starts_with(expensive)) {
+            self.expensive.check(&ip)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the limit of an authenticated user or token, independent of the IP addresses it uses.
+    pub fn check_user(&self, name: &str) -> Result<()> {
+        self.per_user.check(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn retry_after(result: Result<()>) -> Option<u64> {
+        match result {
+            Ok(()) => None,
+            Err(Error::RateLimited(seconds)) => Some(seconds),
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn buckets_refill_over_time() {
+        let limiter = RateLimiter::new(6);
+        let start = Instant::now();
+        for _ in 0..6 {
+            assert_eq!(retry_after(limiter.check_at("a", start)), None);
+        }
+        assert_eq!(retry_after(limiter.check_at("a", start)), Some(10));
+        assert_eq!(retry_after(limiter.check_at("b", start)), None);
+
+        assert_eq!(
+            retry_after(limiter.check_at("a", start + Duration::from_secs(4))),
+            Some(6)
+        );
+        assert_eq!(
+            retry_after(limiter.check_at("a", start + Duration::from_secs(10))),
+            None
+        );
+        assert_eq!(
+            retry_after(limiter.check_at("a", start + Duration::from_secs(10))),
+            Some(10)
+        );
+
+        // the bucket doesn't hold more than the limit
+        let later = start + Duration::from_secs(3600);
+        for _ in 0..6 {
+            assert_eq!(retry_after(limiter.check_at("b", later)), None);
+        }
+        assert!(retry_after(limiter.check_at("b", later)).is_some());
+
+        let unlimited = RateLimiter::new(0);
+        for _ in 0..100 {
+            assert_eq!(retry_after(unlimited.check_at("a", start)), None);
+        }
+    }
+
+    #[test]
+    fn locks_out_clients_for_the_failure_window() {
+        let guard = LoginGuard::new(3);
+        let start = Instant::now();
+        for _ in 0..2 {
+            guard.record_failure_at("a", start);
+        }
+        assert_eq!(retry_after(guard.check_at("a", start)), None);
+
+        guard.record_failure_at("a", start);
+        assert_eq!(retry_after(guard.check_at("a", start)), Some(15 * 60));
+        assert_eq!(retry_after(guard.check_at("b", start)), None);
+        let minute = start + Duration::from_secs(60);
+        assert_eq!(retry_after(guard.check_at("a", minute)), Some(14 * 60));
+
+        assert_eq!(retry_after(guard.check_at("a", start + LOGIN_FAILURE_WINDOW)), None);
+
+        let unlimited = LoginGuard::new(0);
+        for _ in 0..10 {
+            unlimited.record_failure_at("a", start);
+        }
+        assert_eq!(retry_after(unlimited.check_at("a", start)), None);
+    }
+
+    #[test]
+    fn trusts_forwarded_for_only_from_proxies() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let request = |peer: &str, forwarded_for: &[&str]| {
+            let mut req = TestRequest::default().peer_addr(format!("{}:1234", peer).parse().unwrap());
+            for value in forwarded_for {
+                req = req.append_header((X_FORWARDED_FOR, *value));
+            }
+            req.to_http_request()
+        };
+
+        assert_eq!(client_ip(&request("1.2.3.4", &["5.6.7.8"]), &[]), "1.2.3.4");
+        assert_eq!(client_ip(&request("1.2.3.4", &["5.6.7.8"]), &[proxy]), "1.2.3.4");
+        assert_eq!(client_ip(&request("10.0.0.1", &["5.6.7.8"]), &[proxy]), "5.6.7.8");
+        assert_eq!(
+            client_ip(&request("10.0.0.1", &["9.9.9.9, 5.6.7.8"]), &[proxy]),
+            "5.6.7.8"
+        );
+        assert_eq!(
+            client_ip(&request("10.0.0.1", &["9.9.9.9", "5.6.7.8, 10.0.0.1"]), &[proxy]),
+            "5.6.7.8"
+        );
+        assert_eq!(
+            client_ip(&request("10.0.0.1", &["5.6.7.8, nonsense"]), &[proxy]),
+            "10.0.0.1"
+        );
+        assert_eq!(client_ip(&request("10.0.0.1", &[]), &[proxy]), "10.0.0.1");
+        assert_eq!(
+            client_ip(&TestRequest::default().to_http_request(), &[proxy]),
+            "unknown"
+        );
+    }
+}
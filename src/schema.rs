@@ -1,8 +1,59 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    player_history (id) {
+        id -> BigInt,
+        itsf_id -> Integer,
+        timestamp -> BigInt,
+        action -> Text,
+        source -> Binary,
+        changes -> Binary,
+    }
+}
+
+diesel::table! {
+    player_merges (id) {
+        id -> Integer,
+        timestamp -> BigInt,
+        user -> Text,
+        source_itsf_id -> Integer,
+        target_itsf_id -> Integer,
+        source_json -> Binary,
+        target_json -> Binary,
+        image_moved -> Bool,
+        reverted -> Bool,
+    }
+}
+
+diesel::table! {
+    player_quarantine (id) {
+        id -> Integer,
+        itsf_id -> Integer,
+        timestamp -> BigInt,
+        json_data -> Binary,
+        error -> Text,
+    }
+}
+
+diesel::table! {
+    player_redirects (old_itsf_id) {
+        old_itsf_id -> Integer,
+        new_itsf_id -> Integer,
+    }
+}
+
+diesel::table! {
+    player_slugs (slug) {
+        slug -> Text,
+        itsf_id -> Integer,
+    }
+}
+
 diesel::table! {
     players (itsf_id) {
         itsf_id -> Integer,
         json_data -> Binary,
     }
 }
+
+diesel::allow_tables_to_appear_in_same_query!(player_merges, player_redirects, players,);
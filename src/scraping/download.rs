@@ -1,6 +1,8 @@
 use reqwest::Client;
 use scraper::Html;
 
+use super::ScrapingError;
+
 async fn get(url: &str, headers: &[(&str, &str)]) -> Result<String, reqwest::Error> {
     let client = Client::builder()
         .cookie_store(true)
@@ -15,11 +17,11 @@ async fn get(url: &str, headers: &[(&str, &str)]) -> Result<String, reqwest::Err
     request.send().await?.text().await
 }
 
-pub async fn download(url: &str, headers: &[(&str, &str)]) -> Result<String, String> {
-    get(url, headers).await.map_err(|err| err.to_string())
+pub async fn download(url: &str, headers: &[(&str, &str)]) -> Result<String, ScrapingError> {
+    get(url, headers).await.map_err(|err| ScrapingError::download(url, err))
 }
 
-pub async fn download_html(url: &str) -> Result<Html, String> {
+pub async fn download_html(url: &str) -> Result<Html, ScrapingError> {
     let body = download(url, &[]).await?;
     Ok(Html::parse_document(&body))
 }
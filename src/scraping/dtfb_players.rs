@@ -2,9 +2,9 @@ use scraper::{Html, Selector};
 
 use crate::data::dtfb::*;
 
-use super::download;
+use super::{download, ScrapingError};
 
-pub async fn collect_dtfb_ids_from_rankings(ranking_id: i32, max_rank: usize) -> Result<Vec<i32>, String> {
+pub async fn collect_dtfb_ids_from_rankings(ranking_id: i32, max_rank: usize) -> Result<Vec<i32>, ScrapingError> {
     let url = format!(
         "https://dtfb.de/wettbewerbe/turnierserie/rangliste?task=rangliste&id={}",
         ranking_id
@@ -28,7 +28,7 @@ pub async fn collect_dtfb_ids_from_rankings(ranking_id: i32, max_rank: usize) ->
     Ok(ret)
 }
 
-pub async fn collect_dtfb_rankings_for_season(season: i32) -> Result<Vec<i32>, String> {
+pub async fn collect_dtfb_rankings_for_season(season: i32) -> Result<Vec<i32>, ScrapingError> {
     let url = "https://dtfb.de/wettbewerbe/turnierserie/rangliste";
     let cookies = format!("sportsmanager_filter_saison_id={}", season);
     let html = download::download(url, &[("Cookie", &cookies)]).await?;
@@ -83,13 +83,8 @@ fn array<'a>(json: &'a serde_json::Value, name: &str) -> Result<&'a Vec<serde_js
 }
 
 impl DtfbPlayerInfo {
-    async fn try_download(dtfb_id: i32) -> Result<Self, String> {
-        let url = format!(
-            "https://dtfb.de/component/sportsmanager?task=spieler_details&id={}&format=json",
-            dtfb_id
-        );
-        let json = download::download(&url, &[]).await?;
-        let json: serde_json::Value = serde_json::from_str(&json).map_err(|err| err.to_string())?;
+    fn parse(dtfb_id: i32, json: &str) -> Result<Self, String> {
+        let json: serde_json::Value = serde_json::from_str(json).map_err(|err| err.to_string())?;
 
         let data = value(&json, "data")?;
         let spieler = value(data, "spieler")?;
@@ -180,9 +175,12 @@ impl DtfbPlayerInfo {
         })
     }
 
-    pub async fn download(dtfb_id: i32) -> Result<Self, String> {
-        Self::try_download(dtfb_id)
-            .await
-            .map_err(|err| format!("DTFB={}: {}", dtfb_id, err))
+    pub async fn download(dtfb_id: i32) -> Result<Self, ScrapingError> {
+        let url = format!(
+            "https://dtfb.de/component/sportsmanager?task=spieler_details&id={}&format=json",
+            dtfb_id
+        );
+        let json = download::download(&url, &[]).await?;
+        Self::parse(dtfb_id, &json).map_err(|message| ScrapingError::parse(&url, message))
     }
 }
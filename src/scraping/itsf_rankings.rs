@@ -1,4 +1,4 @@
-use super::download;
+use super::{download, ScrapingError};
 use crate::data::itsf::*;
 use scraper::{ElementRef, Selector};
 
@@ -34,7 +34,7 @@ pub async fn download(
     category: RankingCategory,
     class: RankingClass,
     count: usize,
-) -> Result<Vec<(i32, i32)>, String> {
+) -> Result<Vec<(i32, i32)>, ScrapingError> {
     let category = match category {
         RankingCategory::Open => "o",
         RankingCategory::Women => "w",
@@ -1,5 +1,11 @@
+//! Downloads rankings from the ITSF and DTFB websites, and the profiles of all ranked players.
+//!
+//! Downloads either run in the background with `start_*`, whose progress is tracked by a
+//! [`BackgroundOperationProgress`], or in the foreground with `run_*`.
+
 use std::{
     collections::HashSet,
+    fmt,
     sync::{Arc, Weak},
 };
 
@@ -15,24 +21,79 @@ mod dtfb_players;
 mod itsf_rankings;
 mod players;
 
+/// Errors of a download job.
+#[derive(Debug)]
+pub enum ScrapingError {
+    /// A page couldn't be downloaded
+    Download { url: String, message: String },
+    /// A page doesn't have the expected content, e.g. because the website changed
+    Parse { url: String, message: String },
+    /// Storing the downloaded data failed
+    Data(crate::error::Error),
+}
+
+impl ScrapingError {
+    fn download(url: &str, err: reqwest::Error) -> Self {
+        Self::Download {
+            url: String::from(url),
+            message: err.to_string(),
+        }
+    }
+
+    fn parse(url: &str, message: impl Into<String>) -> Self {
+        Self::Parse {
+            url: String::from(url),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ScrapingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Download { url, message } => write!(f, "Failed to download {}: {}", url, message),
+            Self::Parse { url, message } => write!(f, "Unexpected content of {}: {}", url, message),
+            Self::Data(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ScrapingError {}
+
+impl From<crate::error::Error> for ScrapingError {
+    fn from(err: crate::error::Error) -> Self {
+        Self::Data(err)
+    }
+}
+
+/// Limits of a download job.
+#[derive(Debug, Clone, Copy)]
+pub struct DownloadOptions {
+    /// Only players up to this place of each ranking are downloaded
+    pub max_rank: usize,
+    /// Downloads players again, even if they are known already
+    pub force: bool,
+    /// Number of players that are downloaded at the same time, to hide the latency of the ITSF and DTFB servers
+    pub max_concurrent: usize,
+}
+
 async fn download_itsf_players(
     db: &DatabaseRef,
     player_itsf_ids: &[i32],
     progress: Arc<BackgroundOperationProgress>,
-    force: bool,
-) -> Result<(), String> {
+    options: DownloadOptions,
+) -> Result<(), ScrapingError> {
     let mut missing_players: Vec<i32>;
 
-    if force {
+    if options.force {
         missing_players = player_itsf_ids.to_vec();
     } else {
-        missing_players = player_itsf_ids
-            .iter()
-            .filter_map(|itsf_lic| match db.get_player(*itsf_lic) {
-                None => Some(*itsf_lic),
-                Some(_) => None,
-            })
-            .collect();
+        missing_players = Vec::new();
+        for itsf_lic in player_itsf_ids {
+            if db.get_player(*itsf_lic)?.is_none() && db.get_redirect(*itsf_lic)?.is_none() {
+                missing_players.push(*itsf_lic);
+            }
+        }
     }
     if !missing_players.is_empty() {
         progress.set_progress(1, missing_players.len() + 1);
@@ -42,11 +103,10 @@ async fn download_itsf_players(
         ));
 
         // query players in sets of N, to hide ITSF server latency
-        const MAX_CONCURRENT: usize = 5;
         while !missing_players.is_empty() {
             let mut player_futures = Vec::new();
             let mut image_futures = Vec::new();
-            let count = missing_players.len().min(MAX_CONCURRENT);
+            let count = missing_players.len().min(options.max_concurrent);
             for _ in 0..count {
                 let itsf_id = missing_players.pop().unwrap();
                 player_futures.push(players::download_player_info(itsf_id));
@@ -60,7 +120,7 @@ async fn download_itsf_players(
                             "[ITSF] .. downloaded player info for ID={}: {} {} ({:?}, {:?})",
                             player.itsf_id, player.first_name, player.last_name, player.category, player.country_code
                         ));
-                        db.add_player(player);
+                        db.add_player(player, &progress.change_source())?;
                     }
                     Err(err) => {
                         progress.log(format!("[ITSF] Failed to download player: {}", err));
@@ -70,7 +130,7 @@ async fn download_itsf_players(
 
             for image in join_all(image_futures).await {
                 if let Some(image) = image? {
-                    db.set_player_image(image);
+                    db.set_player_image(image)?;
                 }
             }
         }
@@ -87,9 +147,10 @@ async fn do_itsf_rankings_downloads(
     categories: Vec<itsf::RankingCategory>,
     classes: Vec<itsf::RankingClass>,
     progress: Arc<BackgroundOperationProgress>,
-    max_rank: usize,
-    force: bool,
-) -> Result<(), String> {
+    options: DownloadOptions,
+) -> Result<(), ScrapingError> {
+    let source = progress.change_source();
+
     for year in years {
         for category in categories.iter().cloned() {
             for class in classes.iter().cloned() {
@@ -97,10 +158,10 @@ async fn do_itsf_rankings_downloads(
                     "[ITSF] Scraping ITSF rankings for {}, {:?}, {:?}",
                     year, category, class
                 ));
-                let rankings = itsf_rankings::download(year, category, class, max_rank).await?;
+                let rankings = itsf_rankings::download(year, category, class, options.max_rank).await?;
 
                 let itsf_player_ids: Vec<i32> = rankings.iter().map(|entry| entry.1).collect();
-                download_itsf_players(db, &itsf_player_ids, progress.clone(), force).await?;
+                download_itsf_players(db, &itsf_player_ids, progress.clone(), options).await?;
 
                 for placement in rankings {
                     db.add_player_itsf_ranking(
@@ -111,7 +172,8 @@ async fn do_itsf_rankings_downloads(
                             class,
                             place: placement.0,
                         },
-                    );
+                        &source,
+                    )?;
                 }
             }
         }
@@ -119,17 +181,17 @@ async fn do_itsf_rankings_downloads(
     Ok(())
 }
 
+/// Starts downloading the ITSF rankings in the background, and returns a handle to follow its progress.
 pub fn start_itsf_rankings_download(
     db: DatabaseRef,
     years: Vec<i32>,
     categories: Vec<itsf::RankingCategory>,
     classes: Vec<itsf::RankingClass>,
-    max_rank: usize,
-    force: bool,
+    options: DownloadOptions,
 ) -> Weak<BackgroundOperationProgress> {
     let (arc, weak) = BackgroundOperationProgress::new("ITSF Rankings Download", 1);
     tokio::spawn(async move {
-        match do_itsf_rankings_downloads(&db, years, categories, classes, arc.clone(), max_rank, force).await {
+        match do_itsf_rankings_downloads(&db, years, categories, classes, arc.clone(), options).await {
             Ok(_) => {}
             Err(err) => log::error!("failed to download ITSF rankings: {}", err),
         };
@@ -138,13 +200,24 @@ pub fn start_itsf_rankings_download(
     weak
 }
 
+/// Downloads the ITSF rankings in the foreground, e.g. from the command line.
+pub async fn run_itsf_rankings_download(
+    db: &DatabaseRef,
+    years: Vec<i32>,
+    categories: Vec<itsf::RankingCategory>,
+    classes: Vec<itsf::RankingClass>,
+    options: DownloadOptions,
+) -> Result<(), ScrapingError> {
+    let (arc, _weak) = BackgroundOperationProgress::new("ITSF Rankings Download", 1);
+    do_itsf_rankings_downloads(db, years, categories, classes, arc, options).await
+}
+
 async fn do_dtfb_rankings_download(
     db: DatabaseRef,
     seasons: Vec<i32>,
     progress: Arc<BackgroundOperationProgress>,
-    max_rank: usize,
-    force: bool,
-) -> Result<(), String> {
+    options: DownloadOptions,
+) -> Result<(), ScrapingError> {
     progress.log(format!(
         "[DTFB] starting download of DTFB rankings for seasons {:?}",
         seasons
@@ -155,7 +228,7 @@ async fn do_dtfb_rankings_download(
     for season in seasons {
         let ranking_ids = dtfb_players::collect_dtfb_rankings_for_season(season).await?;
         for ranking_id in ranking_ids {
-            let rankings = dtfb_players::collect_dtfb_ids_from_rankings(ranking_id, max_rank).await?;
+            let rankings = dtfb_players::collect_dtfb_ids_from_rankings(ranking_id, options.max_rank).await?;
             for id in rankings {
                 dtfb_player_ids.insert(id);
             }
@@ -168,32 +241,30 @@ async fn do_dtfb_rankings_download(
     let mut dtfb_players = Vec::new();
 
     // download DTFB player profiles for every single player
-    const MAX_CONCURRENT: usize = 5;
     while !dtfb_player_ids.is_empty() {
         let mut player_futures = Vec::new();
-        let count = dtfb_player_ids.len().min(MAX_CONCURRENT);
+        let count = dtfb_player_ids.len().min(options.max_concurrent);
         for _ in 0..count {
             let dtfb_id = dtfb_player_ids.pop().unwrap();
             player_futures.push(dtfb_players::DtfbPlayerInfo::download(dtfb_id));
         }
 
-        for dtfb_player in join_all(player_futures).await {
-            if let Ok(dtfb_player) = dtfb_player {
-                progress.log(format!(
-                    "[DTFB] .. downloaded player info for DTFB={}, ITSF={}",
-                    dtfb_player.dtfb_id, dtfb_player.itsf_id,
-                ));
-                dtfb_players.push(dtfb_player);
-            }
+        for dtfb_player in join_all(player_futures).await.into_iter().flatten() {
+            progress.log(format!(
+                "[DTFB] .. downloaded player info for DTFB={}, ITSF={}",
+                dtfb_player.dtfb_id, dtfb_player.itsf_id,
+            ));
+            dtfb_players.push(dtfb_player);
         }
     }
 
     let itsf_player_ids: Vec<i32> = dtfb_players.iter().map(|player| player.itsf_id).collect();
-    download_itsf_players(&db, &itsf_player_ids, progress.clone(), force).await?;
+    download_itsf_players(&db, &itsf_player_ids, progress.clone(), options).await?;
 
     // add DTFB player data to DB
+    let source = progress.change_source();
     for dtfb_player in dtfb_players {
-        db.set_player_dtfb_id(dtfb_player.itsf_id, dtfb_player.dtfb_id);
+        db.set_player_dtfb_id(dtfb_player.itsf_id, dtfb_player.dtfb_id, &source)?;
 
         for result in dtfb_player.championship_results {
             db.add_player_dtfb_championship_result(
@@ -204,7 +275,8 @@ async fn do_dtfb_rankings_download(
                     category: result.category,
                     class: result.class,
                 },
-            );
+                &source,
+            )?;
         }
 
         for ranking in dtfb_player.national_rankings {
@@ -215,11 +287,12 @@ async fn do_dtfb_rankings_download(
                     place: ranking.place,
                     category: ranking.category,
                 },
-            );
+                &source,
+            )?;
         }
 
         for team in dtfb_player.teams {
-            db.add_player_dtfb_team(dtfb_player.itsf_id, team.0, team.1.clone());
+            db.add_player_dtfb_team(dtfb_player.itsf_id, team.0, team.1.clone(), &source)?;
         }
     }
 
@@ -228,15 +301,15 @@ async fn do_dtfb_rankings_download(
     Ok(())
 }
 
+/// Starts downloading the DTFB rankings in the background, and returns a handle to follow its progress.
 pub fn start_dtfb_rankings_download(
     db: DatabaseRef,
     seasons: Vec<i32>,
-    max_rank: usize,
-    force: bool,
+    options: DownloadOptions,
 ) -> Weak<BackgroundOperationProgress> {
     let (arc, weak) = BackgroundOperationProgress::new("DTFB Rankings Download", 1);
     tokio::spawn(async move {
-        match do_dtfb_rankings_download(db, seasons, arc.clone(), max_rank, force).await {
+        match do_dtfb_rankings_download(db, seasons, arc.clone(), options).await {
             Ok(_) => {}
             Err(err) => log::error!("failed to download DTFB rankings: {}", err),
         };
@@ -244,3 +317,13 @@ pub fn start_dtfb_rankings_download(
     });
     weak
 }
+
+/// Downloads the DTFB rankings in the foreground, e.g. from the command line.
+pub async fn run_dtfb_rankings_download(
+    db: DatabaseRef,
+    seasons: Vec<i32>,
+    options: DownloadOptions,
+) -> Result<(), ScrapingError> {
+    let (arc, _weak) = BackgroundOperationProgress::new("DTFB Rankings Download", 1);
+    do_dtfb_rankings_download(db, seasons, arc, options).await
+}
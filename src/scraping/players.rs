@@ -1,6 +1,6 @@
 use crate::data::{itsf::PlayerCategory, Player, PlayerImage};
 
-use super::download;
+use super::{download, ScrapingError};
 use reqwest::StatusCode;
 use scraper::{ElementRef, Html, Selector};
 
@@ -91,11 +91,11 @@ fn parse_player_info_from(itsf_id: i32, html: &Html) -> Result<Player, String> {
 
     Ok(Player {
         itsf_id,
-        first_name: first_name,
-        last_name: last_name,
+        first_name,
+        last_name,
         birth_year,
         country_code: Some(country_code.into()),
-        category: category,
+        category,
         itsf_rankings: Vec::new(),
         dtfb_id: None,
         dtfb_championship_results: Vec::new(),
@@ -105,23 +105,17 @@ fn parse_player_info_from(itsf_id: i32, html: &Html) -> Result<Player, String> {
     })
 }
 
-async fn download_player_info_from(itsf_id: i32, url: &str) -> Result<Player, String> {
-    let body = download::download(url, &[]).await?;
-    let itsf = Html::parse_document(&body);
-    parse_player_info_from(itsf_id, &itsf)
-}
-
-pub async fn download_player_info(itsf_id: i32) -> Result<Player, String> {
+pub async fn download_player_info(itsf_id: i32) -> Result<Player, ScrapingError> {
     let url = format!("https://www.tablesoccer.org/page/player&numlic={:08}", itsf_id);
-    download_player_info_from(itsf_id, &url)
-        .await
-        .map_err(|msg| format!("Player[{}]: {}", url, msg))
+    let body = download::download(&url, &[]).await?;
+    let itsf = Html::parse_document(&body);
+    parse_player_info_from(itsf_id, &itsf).map_err(|message| ScrapingError::parse(&url, message))
 }
 
-pub async fn download_player_image(itsf_id: i32) -> Result<Option<PlayerImage>, String> {
+pub async fn download_player_image(itsf_id: i32) -> Result<Option<PlayerImage>, ScrapingError> {
     let url = format!("https://media.fast4foos.org/photos/players/{:08}.jpg", itsf_id);
 
-    let response = match reqwest::get(url).await {
+    let response = match reqwest::get(&url).await {
         Ok(response) => {
             if response.status() == StatusCode::NOT_FOUND {
                 return Ok(None);
@@ -134,11 +128,14 @@ pub async fn download_player_image(itsf_id: i32) -> Result<Option<PlayerImage>,
                     return Ok(None);
                 }
             }
-            return Err(err.to_string());
+            return Err(ScrapingError::download(&url, err));
         }
     };
 
-    let bytes = response.bytes().await.map_err(|err| err.to_string())?;
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(|err| ScrapingError::download(&url, err))?;
 
     Ok(Some(PlayerImage {
         itsf_id,
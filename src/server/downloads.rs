@@ -0,0 +1,255 @@
+use actix_web::{web, Error, HttpResponse};
+use chrono::Datelike;
+use std::time::Duration;
+
+use super::errors::ApiError;
+use super::AppState;
+use crate::api::Permission;
+use crate::api::{DownloadParams, ErrorInfo, JsonErr, JsonOk};
+use crate::auth::Caller;
+use crate::config;
+use crate::data::itsf;
+use crate::json;
+use crate::scraping;
+
+impl AppState {
+    fn download_options(&self, max_rank: Option<usize>, force: bool) -> scraping::DownloadOptions {
+        scraping::DownloadOptions {
+            max_rank: max_rank.unwrap_or(self.scraping.max_rank),
+            force,
+            max_concurrent: self.scraping.max_concurrent,
+        }
+    }
+
+    /// Returns all years whose rankings can be downloaded
+    fn all_years(&self) -> Vec<i32> {
+        let curr_year = chrono::Utc::now().naive_local().year();
+        (self.scraping.min_year..curr_year + 1).collect()
+    }
+}
+
+/// Starts a download of the ITSF rankings, unless another download is still running.
+fn start_itsf_download(
+    data: &web::Data<AppState>,
+    years: Vec<i32>,
+    options: scraping::DownloadOptions,
+) -> Result<bool, Error> {
+    let mut download = AppState::get_download(data)?;
+    if download.upgrade().is_some() {
+        return Ok(false);
+    }
+
+    let categories = vec![
+        itsf::RankingCategory::Open,
+        itsf::RankingCategory::Women,
+        itsf::RankingCategory::Senior,
+        itsf::RankingCategory::Junior,
+    ];
+    let classes = vec![
+        itsf::RankingClass::Singles,
+        itsf::RankingClass::Doubles,
+        itsf::RankingClass::Combined,
+    ];
+    *download = scraping::start_itsf_rankings_download(data.data.clone(), years, categories, classes, options);
+    Ok(true)
+}
+
+fn download_itsf(
+    data: web::Data<AppState>,
+    years: Vec<i32>,
+    options: scraping::DownloadOptions,
+) -> Result<HttpResponse, Error> {
+    if !start_itsf_download(&data, years, options)? {
+        return Err(ApiError::job_running().into());
+    }
+    Ok(HttpResponse::Ok().json(json::ok("Started download")))
+}
+
+impl DownloadParams {
+    fn parse_year(&self, min_year: i32) -> Option<i32> {
+        let curr_year = chrono::Utc::now().naive_local().year();
+        match &self.year {
+            Some(year_str) => year_str.parse::<i32>().ok().and_then(|year| {
+                if year >= min_year && year <= curr_year {
+                    Some(year)
+                } else {
+                    None
+                }
+            }),
+            None => Some(curr_year),
+        }
+    }
+
+    fn parse_force(&self) -> bool {
+        match &self.force {
+            Some(force_str) => force_str == "true",
+            None => false,
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/download_itsf",
+    tag = "downloads",
+    params(DownloadParams),
+    responses(
+        (status = 200, description = "Started the download, see `/download_status`", body = JsonOk<String>),
+        (status = 400, description = "Invalid year", body = JsonErr<ErrorInfo>),
+        (status = 409, description = "Another download is running", body = JsonErr<ErrorInfo>),
+        (status = 401, description = "Missing or invalid credentials", body = JsonErr<ErrorInfo>),
+        (status = 403, description = "Not authorized", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::post("/download_itsf")]
+async fn download_itsf_single(
+    data: web::Data<AppState>,
+    params: web::Query<DownloadParams>,
+    auth: Caller,
+) -> Result<HttpResponse, Error> {
+    auth.require(Permission::Scrape)?;
+
+    let options = data.download_options(params.max_rank, params.parse_force());
+    match params.parse_year(data.scraping.min_year) {
+        Some(year) => download_itsf(data, vec![year], options),
+        None => Err(ApiError::invalid_field("year", "invalid year").into()),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/download_itsf_all",
+    tag = "downloads",
+    responses(
+        (status = 200, description = "Started the download, see `/download_status`", body = JsonOk<String>),
+        (status = 409, description = "Another download is running", body = JsonErr<ErrorInfo>),
+        (status = 401, description = "Missing or invalid credentials", body = JsonErr<ErrorInfo>),
+        (status = 403, description = "Not authorized", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::post("/download_itsf_all")]
+async fn download_all_itsf(data: web::Data<AppState>, auth: Caller) -> Result<HttpResponse, Error> {
+    auth.require(Permission::Scrape)?;
+
+    let years = data.all_years();
+    let options = data.download_options(None, false);
+    download_itsf(data, years, options)
+}
+
+/// Starts a download of the DTFB rankings, unless another download is still running.
+fn start_dtfb_download(
+    data: &web::Data<AppState>,
+    seasons: Vec<i32>,
+    options: scraping::DownloadOptions,
+) -> Result<bool, Error> {
+    let mut download = AppState::get_download(data)?;
+    if download.upgrade().is_some() {
+        return Ok(false);
+    }
+
+    *download = scraping::start_dtfb_rankings_download(data.data.clone(), seasons, options);
+    Ok(true)
+}
+
+fn download_dtfb(
+    data: web::Data<AppState>,
+    seasons: Vec<i32>,
+    options: scraping::DownloadOptions,
+) -> Result<HttpResponse, Error> {
+    if !start_dtfb_download(&data, seasons, options)? {
+        return Err(ApiError::job_running().into());
+    }
+    Ok(HttpResponse::Ok().json(json::ok("Started download")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/download_dtfb",
+    tag = "downloads",
+    params(DownloadParams),
+    responses(
+        (status = 200, description = "Started the download, see `/download_status`", body = JsonOk<String>),
+        (status = 400, description = "Invalid year", body = JsonErr<ErrorInfo>),
+        (status = 409, description = "Another download is running", body = JsonErr<ErrorInfo>),
+        (status = 401, description = "Missing or invalid credentials", body = JsonErr<ErrorInfo>),
+        (status = 403, description = "Not authorized", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::post("/download_dtfb")]
+async fn download_dtfb_single(
+    data: web::Data<AppState>,
+    params: web::Query<DownloadParams>,
+    auth: Caller,
+) -> Result<HttpResponse, Error> {
+    auth.require(Permission::Scrape)?;
+
+    let options = data.download_options(params.max_rank, params.parse_force());
+    match params.parse_year(data.scraping.min_year) {
+        Some(year) => download_dtfb(data, vec![year], options),
+        None => Err(ApiError::invalid_field("year", "invalid year").into()),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/download_dtfb_all",
+    tag = "downloads",
+    responses(
+        (status = 200, description = "Started the download, see `/download_status`", body = JsonOk<String>),
+        (status = 409, description = "Another download is running", body = JsonErr<ErrorInfo>),
+        (status = 401, description = "Missing or invalid credentials", body = JsonErr<ErrorInfo>),
+        (status = 403, description = "Not authorized", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::post("/download_dtfb_all")]
+async fn download_dtfb_all(data: web::Data<AppState>, auth: Caller) -> Result<HttpResponse, Error> {
+    auth.require(Permission::Scrape)?;
+
+    let years = data.all_years();
+    let options = data.download_options(None, false);
+    download_dtfb(data, years, options)
+}
+
+/// Periodically downloads the rankings of the current year, skipping runs while another download is in progress.
+pub fn schedule(state: &web::Data<AppState>, schedules: &config::SchedulesConfig) {
+    type Start = fn(&web::Data<AppState>, Vec<i32>, scraping::DownloadOptions) -> Result<bool, Error>;
+    let schedules: [(&str, u64, Start); 2] = [
+        ("ITSF", schedules.itsf_hours, start_itsf_download),
+        ("DTFB", schedules.dtfb_hours, start_dtfb_download),
+    ];
+
+    for (name, hours, start) in schedules {
+        if hours == 0 {
+            continue;
+        }
+        log::info!("Downloading {} rankings every {} hours", name, hours);
+        let state = state.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(Duration::from_secs(hours * 60 * 60));
+            // the first tick completes immediately
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let year = chrono::Utc::now().naive_local().year();
+                match start(&state, vec![year], state.download_options(None, false)) {
+                    Ok(true) => log::info!("Started scheduled {} download for {}", name, year),
+                    Ok(false) => log::info!("Skipping scheduled {} download, another one is in progress", name),
+                    Err(err) => log::error!("Failed to start scheduled {} download: {}", name, err),
+                }
+            }
+        });
+    }
+}
+
+/// The download endpoints, which are merged into the OpenAPI document of the other endpoints.
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(download_itsf_single, download_all_itsf, download_dtfb_single, download_dtfb_all))]
+pub struct ApiDoc;
+
+pub fn configure(config: &mut web::ServiceConfig) {
+    config
+        .service(download_itsf_single)
+        .service(download_all_itsf)
+        .service(download_dtfb_single)
+        .service(download_dtfb_all);
+}
@@ -0,0 +1,242 @@
+//! Error responses. Handlers return an [`ApiError`], and [`finish_response`] turns every error response,
+//! including those of actix' extractors and of the data layer, into the JSON envelope of `crate::api`.
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::{JsonPayloadError, PathError, QueryPayloadError};
+use actix_web::http::header::{HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, WWW_AUTHENTICATE};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use std::fmt;
+
+use crate::api::{ErrorCode, ErrorDetail, ErrorInfo, JsonErr, REQUEST_ID_HEADER};
+use crate::{error, tokens};
+
+#[derive(Debug)]
+pub(crate) struct ApiError {
+    code: ErrorCode,
+    message: String,
+    details: Vec<ErrorDetail>,
+}
+
+impl ApiError {
+    pub(crate) fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            details: Vec::new(),
+        }
+    }
+
+    /// An invalid request, with the problem of one of its fields.
+    pub(crate) fn invalid_field(field: &str, message: impl Into<String>) -> Self {
+        let message = message.into();
+        let mut err = Self::new(ErrorCode::InvalidRequest, format!("invalid {}", field));
+        err.details.push(ErrorDetail {
+            field: String::from(field),
+            message,
+        });
+        err
+    }
+
+    pub(crate) fn unauthenticated() -> Self {
+        Self::new(ErrorCode::Unauthenticated, "missing or invalid credentials")
+    }
+
+    pub(crate) fn forbidden() -> Self {
+        Self::new(ErrorCode::Forbidden, "not authorized")
+    }
+
+    pub(crate) fn invalid_csrf_token() -> Self {
+        Self::new(ErrorCode::InvalidCsrfToken, "missing or wrong CSRF token")
+    }
+
+    pub(crate) fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NotFound, message)
+    }
+
+    pub(crate) fn job_running() -> Self {
+        Self::new(ErrorCode::JobRunning, "Ranking query still in progress")
+    }
+
+    fn info(&self) -> ErrorInfo {
+        ErrorInfo {
+            code: self.code,
+            message: self.message.clone(),
+            details: self.details.clone(),
+            request_id: String::new(),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.code.status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        // lets browsers ask for a password
+        if self.code == ErrorCode::Unauthenticated {
+            response.insert_header((WWW_AUTHENTICATE, "Basic"));
+        }
+        response.json(JsonErr { error: self.info() })
+    }
+}
+
+fn error_code(err: &error::Error) -> ErrorCode {
+    use error::Error;
+    match err {
+        Error::Database(_) | Error::Data(_) | Error::Io(_) => ErrorCode::Internal,
+        Error::Unavailable(_) => ErrorCode::Unavailable,
+        Error::Invalid(_) => ErrorCode::InvalidRequest,
+        Error::NotFound(_) => ErrorCode::NotFound,
+        Error::RateLimited(_) => ErrorCode::RateLimited,
+    }
+}
+
+/// Error handler of the path extractor, which would otherwise answer unparseable IDs with 404. The parameter is
+/// named if there is only one.
+pub(crate) fn path_error(err: PathError, req: &HttpRequest) -> actix_web::Error {
+    let mut names = req.match_info().iter().map(|(name, _)| name);
+    let field = match (names.next(), names.next()) {
+        (Some(name), None) => name,
+        _ => "path",
+    };
+    let PathError::Deserialize(err) = err else {
+        return ApiError::new(ErrorCode::InvalidRequest, err.to_string()).into();
+    };
+    ApiError::invalid_field(field, err.to_string()).into()
+}
+
+/// Names the part of the request that an extractor failed on.
+fn extractor_detail(err: &actix_web::Error) -> Option<ErrorDetail> {
+    let (field, message) = if let Some(QueryPayloadError::Deserialize(err)) = err.as_error::<QueryPayloadError>() {
+        ("query", err.to_string())
+    } else if let Some(JsonPayloadError::Deserialize(err)) = err.as_error::<JsonPayloadError>() {
+        ("body", err.to_string())
+    } else {
+        return None;
+    };
+    Some(ErrorDetail {
+        field: String::from(field),
+        message,
+    })
+}
+
+fn error_info(status: StatusCode, err: &actix_web::Error) -> ErrorInfo {
+    if let Some(err) = err.as_error::<ApiError>() {
+        return err.info();
+    }
+    let data_error = err.as_error::<error::Error>();
+    let code = match data_error {
+        Some(err) => error_code(err),
+        None => ErrorCode::from_status(status.as_u16()),
+    };
+    let detail = extractor_detail(err);
+    let message = match (&detail, data_error) {
+        (Some(detail), _) => format!("invalid {}", detail.field),
+        (None, Some(err)) => err.public_message(),
+        // the details are only logged, see `finish_response`
+        (None, None) if status.is_server_error() => {
+            String::from(status.canonical_reason().unwrap_or("Internal Server Error"))
+        }
+        (None, None) => err.to_string(),
+    };
+    ErrorInfo {
+        code,
+        message,
+        details: detail.into_iter().collect(),
+        request_id: String::new(),
+    }
+}
+
+/// Returns the ID of a request, which is taken from its `X-Request-Id` header if that looks like one.
+pub(crate) fn request_id(req: &ServiceRequest) -> String {
+    let header = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok());
+    match header {
+        Some(id) if !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_graphic()) => String::from(id),
+        _ => tokens::random_hex(8),
+    }
+}
+
+/// Replaces the body of error responses with the JSON envelope, keeping their status and headers, and adds the
+/// request ID to all responses. Server errors are logged with the request ID.
+pub(crate) fn finish_response<B: MessageBody + 'static>(
+    response: ServiceResponse<B>,
+    request_id: &str,
+) -> ServiceResponse<BoxBody> {
+    let mut response = match response.response().error() {
+        Some(err) => {
+            let status = response.status();
+            let mut info = error_info(status, err);
+            info.request_id = String::from(request_id);
+            if status.is_server_error() {
+                log::error!("Request {} failed: {}", request_id, err);
+            }
+
+            let (req, old) = response.into_parts();
+            let mut new = HttpResponse::build(status).json(JsonErr { error: info });
+            for (name, value) in old.headers() {
+                if name != CONTENT_TYPE && name != CONTENT_LENGTH {
+                    new.headers_mut().append(name.clone(), value.clone());
+                }
+            }
+            ServiceResponse::new(req, new)
+        }
+        None => response.map_into_boxed_body(),
+    };
+    if let (Ok(name), Ok(value)) = (
+        HeaderName::try_from(REQUEST_ID_HEADER),
+        HeaderValue::from_str(request_id),
+    ) {
+        response.headers_mut().insert(name, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(err: impl Into<actix_web::Error>) -> ErrorInfo {
+        let err = err.into();
+        error_info(err.as_response_error().status_code(), &err)
+    }
+
+    #[test]
+    fn server_errors_hide_their_details() {
+        let db = info(error::Error::Database(String::from(
+            "no such table: players in /srv/db.sqlite",
+        )));
+        assert_eq!(
+            (db.code, db.message.as_str()),
+            (ErrorCode::Internal, "Internal Server Error")
+        );
+        let busy = info(error::Error::Unavailable(String::from("database is locked")));
+        assert_eq!(
+            (busy.code, busy.message.as_str()),
+            (ErrorCode::Unavailable, "Service Unavailable")
+        );
+        let other = info(actix_web::error::ErrorInternalServerError("internal lock"));
+        assert_eq!(other.message, "Internal Server Error");
+
+        // errors of the request are explained
+        let invalid = info(error::Error::Invalid(String::from("Can't merge a player into itself")));
+        assert_eq!(invalid.message, "Can't merge a player into itself");
+        let missing = info(ApiError::not_found("No such player: 7"));
+        assert_eq!(
+            (missing.code, missing.message.as_str()),
+            (ErrorCode::NotFound, "No such player: 7")
+        );
+    }
+}
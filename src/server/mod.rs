@@ -0,0 +1,1247 @@
+//! The actix web server, which serves the player data and the web UI, and lets authorized users
+//! change the data or start downloads.
+
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::http::header::{ContentType, HeaderName, HeaderValue, LINK, LOCATION};
+use actix_web::{middleware::Logger, web, App, Error, HttpRequest, HttpResponse, HttpServer};
+use futures_util::future::{ready, Either};
+use futures_util::StreamExt;
+use std::io::Write;
+use std::sync::{Mutex, MutexGuard, Weak};
+use std::time::{Duration, UNIX_EPOCH};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::api::{
+    AddCommentInfo, AddTokenInfo, AddUserInfo, ChangeFeed, ChangePasswordInfo, ChangesParams, CreatedToken,
+    DownloadStatus, ErrorCode, ErrorInfo, ImportParams, ImportReport, JsonErr, JsonOk, LoginInfo, MergePlayersInfo,
+    Permission, PlayerBatch, PlayerBatchEntry, PlayerBatchInfo, PlayerChange, PlayerData, PlayerIds, PlayerImageInfo,
+    PlayerJson, PlayerMerge, PlayerSection, RemoveUserInfo, RevertMergeInfo, RevokeTokenInfo, Seed, SeedingRequest,
+    SessionInfo, TokenInfo, UserInfo, API_PREFIX,
+};
+use crate::auth::{self, Caller, MaybeCaller};
+use crate::config::Config;
+use crate::data::{self, itsf};
+#[cfg(feature = "tls")]
+use crate::tls;
+use crate::users;
+use crate::{background, caching, json, ratelimit, sessions, streaming, tokens};
+use errors::ApiError;
+
+#[cfg(feature = "scraping")]
+mod downloads;
+pub(crate) mod errors;
+mod openapi;
+
+/// The error for callers without the `read` permission, if they need it.
+fn read_denied(auth: Option<&Caller>) -> ApiError {
+    match auth {
+        None => ApiError::unauthenticated(),
+        Some(caller) => caller
+            .require(Permission::Read)
+            .err()
+            .unwrap_or_else(ApiError::forbidden),
+    }
+}
+
+/// Returns the fields that have to be hidden from the caller, or an error if it may not read at all.
+fn read_access(data: &AppState, auth: Option<&Caller>) -> Result<data::Redaction, ApiError> {
+    data.privacy.redaction(auth).ok_or_else(|| read_denied(auth))
+}
+
+/// Marks responses of the deprecated aliases, see RFC 9745
+static DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+
+fn redirect_to(location: &str) -> HttpResponse {
+    HttpResponse::Found().append_header((LOCATION, location)).finish()
+}
+
+pub(crate) struct AppState {
+    pub(crate) data: data::DatabaseRef,
+    pub(crate) users: users::UserStore,
+    pub(crate) tokens: tokens::TokenStore,
+    pub(crate) privacy: auth::PrivacyRules,
+    pub(crate) sessions: sessions::SessionStore,
+    pub(crate) rate_limits: ratelimit::RateLimits,
+    max_import_size: u64,
+    #[cfg(feature = "scraping")]
+    scraping: crate::config::ScrapingConfig,
+    download: Mutex<Weak<background::BackgroundOperationProgress>>,
+}
+impl AppState {
+    fn get_download(
+        this: &web::Data<AppState>,
+    ) -> Result<MutexGuard<'_, Weak<background::BackgroundOperationProgress>>, Error> {
+        this.download
+            .lock()
+            .map_err(|_| actix_web::error::ErrorInternalServerError("internal lock"))
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/db.zip",
+    tag = "archive",
+    responses(
+        (status = 200, description = "Zip archive of the DB and all images", body = openapi::Binary, content_type = "application/zip"),
+        (status = 304, description = "Not modified since the `If-None-Match` or `If-Modified-Since` header"),
+        (status = 401, description = "Reading requires credentials", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::get("/db.zip")]
+async fn download_db_zip(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    MaybeCaller(auth): MaybeCaller,
+) -> Result<HttpResponse, Error> {
+    let redaction = read_access(&data, auth.as_ref())?;
+
+    let version = data.data.get_version()?;
+    let (image_count, newest_image) = data.data.get_images_modified();
+    let newest_image = newest_image.unwrap_or(UNIX_EPOCH);
+    let etag = format!(
+        "db-{}-{}-{}-{}",
+        redaction.cache_key(),
+        version.seq,
+        image_count,
+        newest_image.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()
+    );
+    let last_modified = (UNIX_EPOCH + Duration::from_secs(version.timestamp.max(0) as u64)).max(newest_image);
+    let validators = caching::Validators::new(etag, last_modified);
+    if let Some(not_modified) = validators.check(&req) {
+        return Ok(not_modified);
+    }
+
+    let mut response = HttpResponse::Ok();
+    validators.apply(&mut response);
+    response.content_type(ContentType::octet_stream());
+
+    let db = data.data.clone();
+    Ok(streaming::stream_response(response, move |writer| {
+        db.write_zip_archive(writer, &redaction)
+    }))
+}
+
+/// Restores an archive in the format of `/db.zip`. Defaults to merging, which never deletes any players.
+#[utoipa::path(
+    post,
+    path = "/import",
+    tag = "archive",
+    request_body(content = openapi::Binary, content_type = "application/zip", description = "An archive in the format of `/db.zip`"),
+    params(ImportParams),
+    responses(
+        (status = 200, description = "Imported players", body = JsonOk<ImportReport>),
+        (status = 409, description = "A download is running", body = JsonErr<ErrorInfo>),
+        (status = 401, description = "Missing or invalid credentials", body = JsonErr<ErrorInfo>),
+        (status = 403, description = "Not authorized", body = JsonErr<ErrorInfo>),
+        (status = 413, description = "The archive is larger than `MAX_IMPORT_MB`", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::post("/import")]
+async fn import_db_zip(
+    data: web::Data<AppState>,
+    params: web::Query<ImportParams>,
+    auth: Caller,
+    mut payload: web::Payload,
+) -> Result<HttpResponse, Error> {
+    let source = data::ChangeSource::Import {
+        user: auth.name().to_string(),
+    };
+    auth.require(Permission::Admin)?;
+
+    if AppState::get_download(&data)?.upgrade().is_some() {
+        return Err(ApiError::job_running().into());
+    }
+
+    // the archive is read twice (for verification and import), so it is buffered in a file
+    let mut archive = tempfile::NamedTempFile::new().map_err(actix_web::error::ErrorInternalServerError)?;
+    let mut size = 0;
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        size += chunk.len() as u64;
+        if size > data.max_import_size {
+            let message = format!("archives may have at most {} bytes", data.max_import_size);
+            return Err(ApiError::new(ErrorCode::PayloadTooLarge, message).into());
+        }
+        archive = web::block(move || archive.write_all(&chunk).map(|()| archive))
+            .await?
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+    }
+
+    let mode = params.mode.unwrap_or(data::ImportMode::Merge);
+    let db = data.data.clone();
+    let report = web::block(move || db.import_zip_archive(archive.path(), mode, &source)).await??;
+    Ok(HttpResponse::Ok().json(json::ok(report)))
+}
+
+/// Returns the image metadata of a player if the view includes it, and the modification time of the image.
+fn image_info(
+    data: &AppState,
+    itsf_lic: i32,
+    redaction: &data::Redaction,
+    view: &data::PlayerView,
+) -> Option<(PlayerImageInfo, Duration)> {
+    if !view.includes(PlayerSection::Image) || redaction.hides(data::PrivateField::Image) {
+        return None;
+    }
+    let metadata = data.data.get_player_image_metadata(itsf_lic)?;
+    let modified = metadata
+        .modified()
+        .unwrap_or(UNIX_EPOCH)
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let info = PlayerImageInfo {
+        url: format!("{}/image/{}.jpg", API_PREFIX, itsf_lic),
+        size: metadata.len(),
+        modified: modified.as_secs() as i64,
+    };
+    Some((info, modified))
+}
+
+/// Redacts a player and shapes it according to the view.
+fn player_json(
+    mut player: data::Player,
+    redaction: &data::Redaction,
+    view: &data::PlayerView,
+    image: Option<PlayerImageInfo>,
+) -> PlayerJson {
+    redaction.apply(&mut player);
+    let image_url = if redaction.hides(data::PrivateField::Image) {
+        String::new()
+    } else {
+        format!("{}/image/{}.jpg", API_PREFIX, player.itsf_id)
+    };
+
+    if !view.includes(PlayerSection::CombinedRankings) {
+        player
+            .itsf_rankings
+            .retain(|ranking| ranking.class != itsf::RankingClass::Combined);
+    }
+    view.select_years(&mut player.itsf_rankings, |r| r.year);
+    view.select_years(&mut player.dtfb_national_rankings, |r| r.year);
+    view.select_years(&mut player.dtfb_championship_results, |r| r.year);
+    view.select_years(&mut player.dtfb_league_teams, |t| t.year);
+
+    let dtfb = view.includes(PlayerSection::Dtfb);
+    PlayerJson {
+        ids: view.includes(PlayerSection::Ids).then_some(PlayerIds {
+            itsf_id: player.itsf_id,
+            dtfb_id: player.dtfb_id,
+            category: player.category,
+        }),
+        first_name: player.first_name,
+        last_name: player.last_name,
+        birth_year: player.birth_year,
+        country_code: player.country_code.unwrap_or_default(),
+        image_url,
+        image,
+        itsf_rankings: player.itsf_rankings,
+        dtfb_rankings: dtfb.then_some(player.dtfb_national_rankings),
+        dm_placements: dtfb.then_some(player.dtfb_championship_results),
+        dtfl_teams: dtfb.then_some(player.dtfb_league_teams),
+        comment: player.comments.last().map(|c| c.text.clone()).unwrap_or_default(),
+        comments: view.includes(PlayerSection::Comments).then_some(player.comments),
+    }
+}
+
+/// Builds the JSON response for a player, or a `304 Not Modified` if the client's copy is up-to-date.
+fn player_response(
+    req: &HttpRequest,
+    data: &AppState,
+    itsf_lic: i32,
+    redaction: &data::Redaction,
+    view: &data::PlayerView,
+) -> Result<HttpResponse, Error> {
+    // the version is queried before the player, so that a concurrent modification can't result in an outdated
+    // player being cached with the new version's ETag
+    let version = data.data.get_player_version(itsf_lic)?;
+    let mut etag = format!(
+        "player-{}-{}-{}-{}",
+        itsf_lic,
+        version.seq,
+        redaction.cache_key(),
+        view.cache_key()
+    );
+    let mut timestamp = version.timestamp;
+
+    // images are stored outside of the DB, so their changes don't show up in the player's version
+    let image = image_info(data, itsf_lic, redaction, view).map(|(info, modified)| {
+        etag += &format!("-{}-{}", info.size, modified.as_millis());
+        timestamp = timestamp.max(info.modified);
+        info
+    });
+
+    let validators = caching::Validators::from_timestamp(etag, timestamp);
+    if let Some(not_modified) = validators.check(req) {
+        return Ok(not_modified);
+    }
+
+    let player = match data.data.get_player(itsf_lic)? {
+        Some(player) => player,
+        None => return Err(ApiError::not_found("No such player").into()),
+    };
+
+    let mut response = HttpResponse::Ok();
+    validators.apply(&mut response);
+    Ok(response.json(json::ok(player_json(player, redaction, view, image))))
+}
+
+/// Parses the query of the endpoints that return a single player.
+fn player_view(params: &data::PlayerViewParams) -> Result<data::PlayerView, ApiError> {
+    data::PlayerView::from_params(params).map_err(|err| ApiError::new(ErrorCode::InvalidRequest, err))
+}
+
+#[utoipa::path(
+    get,
+    path = "/player/{itsf_lic}",
+    tag = "players",
+    params(data::PlayerViewParams),
+    responses(
+        (status = 200, description = "The player", body = JsonOk<PlayerJson>),
+        (status = 302, description = "The player was merged, redirects to the other player"),
+        (status = 304, description = "Not modified"),
+        (status = 404, description = "No such player", body = JsonErr<ErrorInfo>),
+        (status = 400, description = "Invalid section, year range or sort", body = JsonErr<ErrorInfo>),
+        (status = 401, description = "Reading requires credentials", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::get("/player/{itsf_lic}")]
+async fn get_player(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    itsf_lic: web::Path<i32>,
+    params: web::Query<data::PlayerViewParams>,
+    MaybeCaller(auth): MaybeCaller,
+) -> Result<HttpResponse, Error> {
+    let redaction = read_access(&data, auth.as_ref())?;
+    let view = player_view(&params)?;
+    let itsf_lic = itsf_lic.into_inner();
+
+    if let Some(canonical_id) = data.data.get_redirect(itsf_lic)? {
+        let mut location = format!("{}/player/{}", API_PREFIX, canonical_id);
+        if !req.query_string().is_empty() {
+            location += &format!("?{}", req.query_string());
+        }
+        return Ok(redirect_to(&location));
+    }
+
+    player_response(&req, &data, itsf_lic, &redaction, &view)
+}
+
+#[utoipa::path(
+    get,
+    path = "/player/{itsf_lic}/history",
+    tag = "players",
+    responses(
+        (status = 200, description = "All changes of the player, oldest first", body = JsonOk<Vec<PlayerChange>>),
+        (status = 404, description = "No such player", body = JsonErr<ErrorInfo>),
+        (status = 401, description = "Reading requires credentials", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::get("/player/{itsf_lic}/history")]
+async fn get_player_history(
+    data: web::Data<AppState>,
+    itsf_lic: web::Path<i32>,
+    MaybeCaller(auth): MaybeCaller,
+) -> Result<HttpResponse, Error> {
+    let redaction = read_access(&data, auth.as_ref())?;
+    let itsf_lic = itsf_lic.into_inner();
+
+    let mut history = data.data.get_player_history(itsf_lic)?;
+    if history.is_empty() && data.data.get_player(itsf_lic)?.is_none() {
+        return Err(ApiError::not_found("No such player").into());
+    }
+    redaction.apply_to_history(&mut history);
+
+    Ok(HttpResponse::Ok().json(json::ok(history)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/player/by-dtfb/{dtfb_id}",
+    tag = "players",
+    params(data::PlayerViewParams),
+    responses(
+        (status = 200, description = "The player", body = JsonOk<PlayerJson>),
+        (status = 404, description = "No such player", body = JsonErr<ErrorInfo>),
+        (status = 400, description = "Invalid section, year range or sort", body = JsonErr<ErrorInfo>),
+        (status = 401, description = "Reading requires credentials", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::get("/player/by-dtfb/{dtfb_id}")]
+async fn get_player_by_dtfb_id(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    dtfb_id: web::Path<i32>,
+    params: web::Query<data::PlayerViewParams>,
+    MaybeCaller(auth): MaybeCaller,
+) -> Result<HttpResponse, Error> {
+    let redaction = read_access(&data, auth.as_ref())?;
+    let view = player_view(&params)?;
+    match data.data.find_player_by_dtfb_id(dtfb_id.into_inner())? {
+        Some(itsf_lic) => player_response(&req, &data, itsf_lic, &redaction, &view),
+        None => Err(ApiError::not_found("No such player").into()),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/player/by-slug/{slug}",
+    tag = "players",
+    params(data::PlayerViewParams),
+    responses(
+        (status = 200, description = "The player", body = JsonOk<PlayerJson>),
+        (status = 404, description = "No such player", body = JsonErr<ErrorInfo>),
+        (status = 400, description = "Invalid section, year range or sort", body = JsonErr<ErrorInfo>),
+        (status = 401, description = "Reading requires credentials", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::get("/player/by-slug/{slug}")]
+async fn get_player_by_slug(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    slug: web::Path<String>,
+    params: web::Query<data::PlayerViewParams>,
+    MaybeCaller(auth): MaybeCaller,
+) -> Result<HttpResponse, Error> {
+    let redaction = read_access(&data, auth.as_ref())?;
+    let view = player_view(&params)?;
+    match data.data.find_player_by_slug(&slug)? {
+        Some(itsf_lic) => player_response(&req, &data, itsf_lic, &redaction, &view),
+        None => Err(ApiError::not_found("No such player").into()),
+    }
+}
+
+/// Most players that one `/players/batch` request can ask for, and most participants of `/seeding`
+const MAX_BATCH_SIZE: usize = 1000;
+
+#[utoipa::path(
+    post,
+    path = "/players/batch",
+    tag = "players",
+    params(data::PlayerViewParams),
+    request_body = PlayerBatchInfo,
+    responses(
+        (status = 200, description = "The players, with `found: false` for unknown IDs", body = JsonOk<PlayerBatch>),
+        (status = 400, description = "Too many IDs, or invalid section, year range or sort", body = JsonErr<ErrorInfo>),
+        (status = 401, description = "Reading requires credentials", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::post("/players/batch")]
+async fn get_player_batch(
+    data: web::Data<AppState>,
+    params: web::Query<data::PlayerViewParams>,
+    request: web::Json<PlayerBatchInfo>,
+    MaybeCaller(auth): MaybeCaller,
+) -> Result<HttpResponse, Error> {
+    let redaction = read_access(&data, auth.as_ref())?;
+    let view = player_view(&params)?;
+    if request.itsf_lics.len() > MAX_BATCH_SIZE {
+        let message = format!("at most {} players per request", MAX_BATCH_SIZE);
+        return Err(ApiError::invalid_field("itsf_lics", message).into());
+    }
+
+    let (version, players) = data.data.get_players(&request.itsf_lics)?;
+    let players = request
+        .itsf_lics
+        .iter()
+        .zip(players)
+        .map(|(&itsf_lic, player)| PlayerBatchEntry {
+            itsf_lic,
+            found: player.is_some(),
+            merged_into: player.as_ref().map(|p| p.itsf_id).filter(|id| *id != itsf_lic),
+            player: player.map(|player| {
+                let image = image_info(&data, player.itsf_id, &redaction, &view).map(|(info, _)| info);
+                player_json(player, &redaction, &view, image)
+            }),
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(json::ok(PlayerBatch {
+        seq: version.seq,
+        players,
+    })))
+}
+
+#[utoipa::path(
+    post,
+    path = "/seeding",
+    tag = "seeding",
+    request_body = SeedingRequest,
+    responses(
+        (status = 200, description = "The participants in the order of their seeds", body = JsonOk<Vec<Seed>>),
+        (status = 400, description = "Unknown or duplicate players, too many participants, or no rankings", body = JsonErr<ErrorInfo>),
+        (status = 401, description = "Reading requires credentials", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::post("/seeding")]
+async fn seed_participants(
+    data: web::Data<AppState>,
+    request: web::Json<SeedingRequest>,
+    MaybeCaller(auth): MaybeCaller,
+) -> Result<HttpResponse, Error> {
+    read_access(&data, auth.as_ref())?;
+    if request.participants.len() > MAX_BATCH_SIZE {
+        let message = format!("at most {} participants per request", MAX_BATCH_SIZE);
+        return Err(ApiError::invalid_field("participants", message).into());
+    }
+    let seeds = data.data.seed(&request)?;
+    Ok(HttpResponse::Ok().json(json::ok(seeds)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/listplayers",
+    tag = "players",
+    params(data::PlayerFilterParams),
+    responses(
+        (status = 200, description = "The names of all matching players", body = JsonOk<Vec<PlayerData>>),
+        (status = 400, description = "Invalid filter", body = JsonErr<ErrorInfo>),
+        (status = 401, description = "Reading requires credentials", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::get("/listplayers")]
+async fn list_players(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    params: web::Query<data::PlayerFilterParams>,
+    MaybeCaller(auth): MaybeCaller,
+) -> Result<HttpResponse, Error> {
+    // names are never private
+    read_access(&data, auth.as_ref())?;
+    let filter = match data::PlayerFilter::from_params(&params) {
+        Ok(filter) => filter,
+        Err(err) => return Err(ApiError::new(ErrorCode::InvalidRequest, err).into()),
+    };
+
+    let version = data.data.get_version()?;
+    let etag = format!("players-{}-{}", version.seq, filter.cache_key());
+    let validators = caching::Validators::from_timestamp(etag, version.timestamp);
+    if let Some(not_modified) = validators.check(&req) {
+        return Ok(not_modified);
+    }
+
+    let mut players = Vec::new();
+    for itsf_lic in data.data.find_players(&filter)? {
+        if let Some(player) = data.data.get_player(itsf_lic)? {
+            players.push(PlayerData {
+                itsf_lic,
+                slug: data.data.get_player_slug(itsf_lic)?.unwrap_or_default(),
+                first_name: player.first_name,
+                last_name: player.last_name,
+            });
+        }
+    }
+
+    let mut response = HttpResponse::Ok();
+    validators.apply(&mut response);
+    Ok(response.json(json::ok(players)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/export/{table}.{format}",
+    tag = "players",
+    params(data::PlayerFilterParams),
+    responses(
+        (status = 200, description = "One row per player, ranking or league team", content(
+            (String = "text/csv"),
+            (String = "application/x-ndjson"),
+        )),
+        (status = 400, description = "Invalid filter", body = JsonErr<ErrorInfo>),
+        (status = 401, description = "Reading requires credentials", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::get("/export/{table}.{format}")]
+async fn export_table(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    path: web::Path<(data::ExportTable, data::ExportFormat)>,
+    params: web::Query<data::PlayerFilterParams>,
+    MaybeCaller(auth): MaybeCaller,
+) -> Result<HttpResponse, Error> {
+    let redaction = read_access(&data, auth.as_ref())?;
+    let (table, format) = path.into_inner();
+    let filter = match data::PlayerFilter::from_params(&params) {
+        Ok(filter) => filter,
+        Err(err) => return Err(ApiError::new(ErrorCode::InvalidRequest, err).into()),
+    };
+
+    let version = data.data.get_version()?;
+    let etag = format!(
+        "{}-{}-{}-{}-{}",
+        req.match_info().query("table"),
+        req.match_info().query("format"),
+        version.seq,
+        filter.cache_key(),
+        redaction.cache_key()
+    );
+    let validators = caching::Validators::from_timestamp(etag, version.timestamp);
+    if let Some(not_modified) = validators.check(&req) {
+        return Ok(not_modified);
+    }
+
+    let mut response = HttpResponse::Ok();
+    validators.apply(&mut response);
+    response.content_type(format.content_type());
+
+    let db = data.data.clone();
+    Ok(streaming::stream_response(response, move |writer| {
+        db.write_export(writer, table, format, &filter, &redaction)
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/changes",
+    tag = "players",
+    params(ChangesParams),
+    responses(
+        (status = 200, description = "All players that changed after `since`, or all players with `reset` set if `since` is ahead of the server", body = JsonOk<ChangeFeed>),
+        (status = 401, description = "Reading requires credentials", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::get("/changes")]
+async fn get_changes(
+    data: web::Data<AppState>,
+    params: web::Query<ChangesParams>,
+    MaybeCaller(auth): MaybeCaller,
+) -> Result<HttpResponse, Error> {
+    let redaction = read_access(&data, auth.as_ref())?;
+    let since = params.since.unwrap_or(0);
+    let mut feed = data.data.get_changes_since(since)?;
+    for player in feed.added.iter_mut().chain(feed.updated.iter_mut()) {
+        redaction.apply(player);
+    }
+    Ok(HttpResponse::Ok().json(json::ok(feed)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/image/{itsf_lic}.jpg",
+    tag = "players",
+    responses(
+        (status = 200, description = "The image of the player", body = openapi::Binary, content_type = "image/jpeg"),
+        (status = 404, description = "No image", body = JsonErr<ErrorInfo>),
+        (status = 401, description = "Missing or invalid credentials", body = JsonErr<ErrorInfo>),
+        (status = 403, description = "Not authorized", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::get("/image/{itsf_lic}.jpg")]
+async fn get_player_image(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    itsf_lic: web::Path<i32>,
+    MaybeCaller(auth): MaybeCaller,
+) -> Result<HttpResponse, Error> {
+    if read_access(&data, auth.as_ref())?.hides(data::PrivateField::Image) {
+        return Err(read_denied(auth.as_ref()).into());
+    }
+    let itsf_lic = itsf_lic.into_inner();
+
+    if let Some(canonical_id) = data.data.get_redirect(itsf_lic)? {
+        return Ok(redirect_to(&format!("{}/image/{}.jpg", API_PREFIX, canonical_id)));
+    }
+
+    let metadata = match data.data.get_player_image_metadata(itsf_lic) {
+        Some(metadata) => metadata,
+        None => return Err(ApiError::not_found("No image").into()),
+    };
+    let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+    let etag = format!(
+        "image-{}-{}-{}",
+        itsf_lic,
+        metadata.len(),
+        modified.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()
+    );
+    let validators = caching::Validators::new(etag, modified);
+    if let Some(not_modified) = validators.check(&req) {
+        return Ok(not_modified);
+    }
+
+    match data.data.get_player_image(itsf_lic) {
+        Some(player_image) => {
+            let mut response = HttpResponse::Ok();
+            validators.apply(&mut response);
+            Ok(response
+                .append_header(("Content-Type", "image/jpeg"))
+                .body(player_image.image_data))
+        }
+        None => Err(ApiError::not_found("No image").into()),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/download_status",
+    tag = "downloads",
+    responses(
+        (status = 200, description = "The running download, if any", body = JsonOk<DownloadStatus>),
+        (status = 401, description = "Reading requires credentials", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::get("/download_status")]
+async fn download_status(data: web::Data<AppState>, MaybeCaller(auth): MaybeCaller) -> Result<HttpResponse, Error> {
+    read_access(&data, auth.as_ref())?;
+    let download = AppState::get_download(&data)?;
+    let status = match download.upgrade() {
+        Some(download) => DownloadStatus {
+            running: true,
+            job_id: download.get_id(),
+            title: download.get_title().to_string(),
+            progress: download.get_progress(),
+            log: download.get_log(),
+        },
+        None => DownloadStatus {
+            running: false,
+            job_id: 0,
+            title: String::new(),
+            progress: (0, 0),
+            log: Vec::new(),
+        },
+    };
+    Ok(HttpResponse::Ok().json(json::ok(status)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/add_comment",
+    tag = "players",
+    responses(
+        (status = 200, description = "Added the comment", body = JsonOk<String>),
+        (status = 401, description = "Missing or invalid credentials", body = JsonErr<ErrorInfo>),
+        (status = 403, description = "Not authorized", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::post("/add_comment")]
+async fn add_player_comment(
+    data: web::Data<AppState>,
+    info: web::Json<AddCommentInfo>,
+    auth: Caller,
+) -> Result<HttpResponse, Error> {
+    let source = data::ChangeSource::Comment {
+        user: auth.name().to_string(),
+    };
+    auth.require(Permission::Comment)?;
+
+    data.data
+        .add_player_comment(info.itsf_lic, info.comment.clone(), &source)?;
+    Ok(HttpResponse::Ok().json(json::ok("added comment")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/merge_players",
+    tag = "merges",
+    responses(
+        (status = 200, description = "Merged the players", body = JsonOk<PlayerMerge>),
+        (status = 401, description = "Missing or invalid credentials", body = JsonErr<ErrorInfo>),
+        (status = 403, description = "Not authorized", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::post("/merge_players")]
+async fn merge_players(
+    data: web::Data<AppState>,
+    info: web::Json<MergePlayersInfo>,
+    auth: Caller,
+) -> Result<HttpResponse, Error> {
+    let user = auth.name().to_string();
+    auth.require(Permission::Admin)?;
+
+    let merge = data
+        .data
+        .merge_players(info.source_itsf_lic, info.target_itsf_lic, &user)?;
+    Ok(HttpResponse::Ok().json(json::ok(merge)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/revert_merge",
+    tag = "merges",
+    responses(
+        (status = 200, description = "Restored the merged player", body = JsonOk<PlayerMerge>),
+        (status = 401, description = "Missing or invalid credentials", body = JsonErr<ErrorInfo>),
+        (status = 403, description = "Not authorized", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::post("/revert_merge")]
+async fn revert_merge(
+    data: web::Data<AppState>,
+    info: web::Json<RevertMergeInfo>,
+    auth: Caller,
+) -> Result<HttpResponse, Error> {
+    let user = auth.name().to_string();
+    auth.require(Permission::Admin)?;
+
+    let merge = data.data.revert_merge(info.merge_id, &user)?;
+    Ok(HttpResponse::Ok().json(json::ok(merge)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/listmerges",
+    tag = "merges",
+    responses(
+        (status = 200, description = "All merges", body = JsonOk<Vec<PlayerMerge>>),
+        (status = 401, description = "Missing or invalid credentials", body = JsonErr<ErrorInfo>),
+        (status = 403, description = "Not authorized", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::get("/listmerges")]
+async fn list_merges(data: web::Data<AppState>, auth: Caller) -> Result<HttpResponse, Error> {
+    auth.require(Permission::Admin)?;
+
+    Ok(HttpResponse::Ok().json(json::ok(data.data.get_merges()?)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/listusers",
+    tag = "users",
+    responses(
+        (status = 200, description = "All users", body = JsonOk<Vec<UserInfo>>),
+        (status = 401, description = "Missing or invalid credentials", body = JsonErr<ErrorInfo>),
+        (status = 403, description = "Not authorized", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::get("/listusers")]
+async fn list_users(data: web::Data<AppState>, auth: Caller) -> Result<HttpResponse, Error> {
+    auth.require(Permission::Admin)?;
+
+    Ok(HttpResponse::Ok().json(json::ok(data.users.list()?)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/add_user",
+    tag = "users",
+    responses(
+        (status = 200, description = "Added the user", body = JsonOk<String>),
+        (status = 400, description = "Invalid name or password, or the user exists", body = JsonErr<ErrorInfo>),
+        (status = 401, description = "Missing or invalid credentials", body = JsonErr<ErrorInfo>),
+        (status = 403, description = "Not authorized", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::post("/add_user")]
+async fn add_user(
+    data: web::Data<AppState>,
+    info: web::Json<AddUserInfo>,
+    auth: Caller,
+) -> Result<HttpResponse, Error> {
+    auth.require(Permission::Admin)?;
+
+    // hashing the password takes a while
+    let users = data.clone();
+    let (name, password, role) = (info.name.clone(), info.password.clone(), info.role);
+    web::block(move || users.users.add(&name, &password, role)).await??;
+    log::info!("{} added user {} ({:?})", auth.name(), info.name, info.role);
+    Ok(HttpResponse::Ok().json(json::ok("added user")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/remove_user",
+    tag = "users",
+    responses(
+        (status = 200, description = "Removed the user", body = JsonOk<String>),
+        (status = 401, description = "Missing or invalid credentials", body = JsonErr<ErrorInfo>),
+        (status = 403, description = "Not authorized", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::post("/remove_user")]
+async fn remove_user(
+    data: web::Data<AppState>,
+    info: web::Json<RemoveUserInfo>,
+    auth: Caller,
+) -> Result<HttpResponse, Error> {
+    auth.require(Permission::Admin)?;
+
+    data.users.remove(&info.name)?;
+    data.sessions.remove_user(&info.name)?;
+    log::info!("{} removed user {}", auth.name(), info.name);
+    Ok(HttpResponse::Ok().json(json::ok("removed user")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/change_password",
+    tag = "users",
+    responses(
+        (status = 200, description = "Changed the password and ended all sessions of the user", body = JsonOk<String>),
+        (status = 400, description = "Invalid password", body = JsonErr<ErrorInfo>),
+        (status = 401, description = "Missing or invalid credentials", body = JsonErr<ErrorInfo>),
+        (status = 403, description = "Not authorized", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::post("/change_password")]
+async fn change_password(
+    data: web::Data<AppState>,
+    info: web::Json<ChangePasswordInfo>,
+    auth: Caller,
+) -> Result<HttpResponse, Error> {
+    // API tokens don't have a password of their own
+    let Some(name) = info.name.as_deref().or(auth.user()) else {
+        return Err(ApiError::invalid_field("name", "missing user name").into());
+    };
+    let permission = if auth.user() == Some(name) {
+        Permission::Read
+    } else {
+        Permission::Admin
+    };
+    auth.require(permission)?;
+
+    let users = data.clone();
+    let (user, password) = (name.to_string(), info.password.clone());
+    web::block(move || users.users.set_password(&user, &password)).await??;
+    data.sessions.remove_user(name)?;
+    log::info!("{} changed the password of {}", auth.name(), name);
+    Ok(HttpResponse::Ok().json(json::ok("changed password")))
+}
+
+/// Starts a session of the web UI, identified by a cookie. API clients should use basic auth or tokens instead.
+#[utoipa::path(
+    post,
+    path = "/login",
+    tag = "sessions",
+    responses(
+        (status = 200, description = "Started a session, whose ID is set as cookie", body = JsonOk<SessionInfo>),
+        (status = 401, description = "Invalid user name or password", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::post("/login")]
+async fn login(req: HttpRequest, data: web::Data<AppState>, info: web::Json<LoginInfo>) -> Result<HttpResponse, Error> {
+    let client = data.rate_limits.client_ip(&req);
+    data.rate_limits.logins.check(&client)?;
+    let users = data.clone();
+    let (name, password) = (info.name.clone(), info.password.clone());
+    let Some(role) = web::block(move || users.users.authenticate(&name, &password)).await? else {
+        data.rate_limits.logins.record_failure(&client);
+        return Err(ApiError::new(ErrorCode::Unauthenticated, "invalid user name or password").into());
+    };
+
+    let (session_id, csrf_token) = data.sessions.create(&info.name)?;
+    log::info!("{} logged in", info.name);
+    let session = SessionInfo {
+        name: info.name.clone(),
+        role,
+        csrf_token,
+    };
+    Ok(HttpResponse::Ok()
+        .cookie(data.sessions.cookie(&session_id))
+        .json(json::ok(session)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/logout",
+    tag = "sessions",
+    responses(
+        (status = 200, description = "Ended the session", body = JsonOk<String>),
+        (status = 403, description = "The session's CSRF token is missing", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::post("/logout")]
+async fn logout(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    if let Some(cookie) = req.cookie(sessions::SESSION_COOKIE) {
+        // otherwise other sites could log users out
+        if let Some((_, csrf_token)) = data.sessions.get(cookie.value()) {
+            if !auth::has_csrf_token(&req, &csrf_token) {
+                return Err(ApiError::invalid_csrf_token().into());
+            }
+            data.sessions.remove(cookie.value())?;
+        }
+    }
+    Ok(HttpResponse::Ok()
+        .cookie(data.sessions.removal_cookie())
+        .json(json::ok("logged out")))
+}
+
+/// Returns the user and CSRF token of the current session, e.g. after reloading a page.
+#[utoipa::path(
+    get,
+    path = "/session",
+    tag = "sessions",
+    responses(
+        (status = 200, description = "The current session", body = JsonOk<SessionInfo>),
+        (status = 401, description = "Not logged in", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::get("/session")]
+async fn get_session(req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let session = req
+        .cookie(sessions::SESSION_COOKIE)
+        .and_then(|cookie| data.sessions.get(cookie.value()))
+        .and_then(|(name, csrf_token)| {
+            let role = data.users.role(&name)?;
+            Some(SessionInfo { name, role, csrf_token })
+        });
+    match session {
+        Some(session) => Ok(HttpResponse::Ok().json(json::ok(session))),
+        None => Err(ApiError::new(ErrorCode::Unauthenticated, "not logged in").into()),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/listtokens",
+    tag = "tokens",
+    responses(
+        (status = 200, description = "All tokens", body = JsonOk<Vec<TokenInfo>>),
+        (status = 401, description = "Missing or invalid credentials", body = JsonErr<ErrorInfo>),
+        (status = 403, description = "Not authorized", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::get("/listtokens")]
+async fn list_tokens(data: web::Data<AppState>, auth: Caller) -> Result<HttpResponse, Error> {
+    auth.require(Permission::Admin)?;
+
+    Ok(HttpResponse::Ok().json(json::ok(data.tokens.list()?)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/add_token",
+    tag = "tokens",
+    responses(
+        (status = 200, description = "The new token, including its secret", body = JsonOk<CreatedToken>),
+        (status = 401, description = "Missing or invalid credentials", body = JsonErr<ErrorInfo>),
+        (status = 403, description = "Not authorized", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::post("/add_token")]
+async fn add_token(
+    data: web::Data<AppState>,
+    info: web::Json<AddTokenInfo>,
+    auth: Caller,
+) -> Result<HttpResponse, Error> {
+    auth.require(Permission::Admin)?;
+
+    // writing the tokens file blocks
+    let tokens = data.clone();
+    let (name, scopes, created_by) = (info.name.clone(), info.scopes.clone(), auth.name().to_string());
+    let token = web::block(move || tokens.tokens.create(&name, &scopes, info.valid_days, &created_by)).await??;
+    log::info!("{} added token {} ({})", auth.name(), token.info.id, token.info.name);
+    Ok(HttpResponse::Ok().json(json::ok(token)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/revoke_token",
+    tag = "tokens",
+    responses(
+        (status = 200, description = "Revoked the token", body = JsonOk<String>),
+        (status = 401, description = "Missing or invalid credentials", body = JsonErr<ErrorInfo>),
+        (status = 403, description = "Not authorized", body = JsonErr<ErrorInfo>),
+    )
+)]
+#[actix_web::post("/revoke_token")]
+async fn revoke_token(
+    data: web::Data<AppState>,
+    info: web::Json<RevokeTokenInfo>,
+    auth: Caller,
+) -> Result<HttpResponse, Error> {
+    auth.require(Permission::Admin)?;
+
+    let tokens = data.clone();
+    let id = info.id.clone();
+    web::block(move || tokens.tokens.revoke(&id)).await??;
+    log::info!("{} revoked token {}", auth.name(), info.id);
+    Ok(HttpResponse::Ok().json(json::ok("revoked token")))
+}
+
+/// Redirects all requests of the plain HTTP listener to the same URL on the HTTPS server.
+#[cfg(feature = "tls")]
+async fn redirect_to_https(req: HttpRequest, https_port: web::Data<u16>) -> HttpResponse {
+    let connection = req.connection_info();
+    let host = connection.host();
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    };
+    let path = req.uri().path_and_query().map(|path| path.as_str()).unwrap_or("/");
+    let location = match **https_port {
+        443 => format!("https://{}{}", host, path),
+        port => format!("https://{}:{}{}", host, port, path),
+    };
+    HttpResponse::PermanentRedirect()
+        .append_header((LOCATION, location))
+        .finish()
+}
+
+/// Returns the path below `API_PREFIX` of a request to one of the deprecated aliases at the root. Static files
+/// are served from the root, too, but they aren't matched by a route pattern.
+fn successor_path(req: &HttpRequest) -> Option<String> {
+    let pattern = req.match_pattern()?;
+    if pattern.is_empty() || pattern.starts_with(API_PREFIX) {
+        return None;
+    }
+    Some(format!("{}{}", API_PREFIX, req.path()))
+}
+
+/// Marks responses of the deprecated aliases, and links to the same endpoint below `API_PREFIX`.
+fn mark_deprecated<B>(response: &mut ServiceResponse<B>) {
+    let Some(successor) = successor_path(response.request()) else {
+        return;
+    };
+    let headers = response.headers_mut();
+    headers.insert(DEPRECATION.clone(), HeaderValue::from_static("true"));
+    if let Ok(link) = HeaderValue::from_str(&format!("<{}>; rel=\"successor-version\"", successor)) {
+        headers.insert(LINK, link);
+    }
+}
+
+/// Registers all endpoints of the JSON API.
+fn configure_api(config: &mut web::ServiceConfig) {
+    config
+        .service(download_db_zip)
+        .service(import_db_zip)
+        .service(get_player)
+        .service(get_player_by_dtfb_id)
+        .service(get_player_by_slug)
+        .service(get_player_batch)
+        .service(seed_participants)
+        .service(get_player_history)
+        .service(get_player_image)
+        .service(list_players)
+        .service(export_table)
+        .service(get_changes)
+        .service(download_status)
+        .configure(configure_downloads)
+        .service(add_player_comment)
+        .service(merge_players)
+        .service(revert_merge)
+        .service(list_merges)
+        .service(list_users)
+        .service(add_user)
+        .service(remove_user)
+        .service(change_password)
+        .service(login)
+        .service(logout)
+        .service(get_session)
+        .service(list_tokens)
+        .service(add_token)
+        .service(revoke_token);
+}
+
+/// Registers the endpoints that start downloads, if scraping is enabled.
+#[cfg_attr(not(feature = "scraping"), allow(unused_variables))]
+fn configure_downloads(config: &mut web::ServiceConfig) {
+    #[cfg(feature = "scraping")]
+    downloads::configure(config);
+}
+
+/// Runs the web server until it is stopped.
+pub async fn serve(config: Config) -> std::io::Result<()> {
+    run(config, None).await
+}
+
+/// Runs the web server on a socket that is already bound, e.g. to a free port in tests. TLS is not used.
+pub async fn serve_listener(config: Config, listener: std::net::TcpListener) -> std::io::Result<()> {
+    run(config, Some(listener)).await
+}
+
+async fn run(config: Config, listener: Option<std::net::TcpListener>) -> std::io::Result<()> {
+    let auth_config = &config.auth;
+    let load_error =
+        |what: &str, err: crate::error::Error| std::io::Error::other(format!("Failed to load {}: {}", what, err));
+    // held until the server stops, so that the CLI doesn't change the DB behind its back
+    let _lock = data::DatabaseLock::acquire(&config.storage.database).map_err(|err| load_error("DB", err))?;
+    let state = AppState {
+        data: data::DatabaseRef::load(&config.storage.database, &config.storage.images)
+            .map_err(|err| load_error("DB", err))?,
+        users: users::UserStore::open(&auth_config.users_file).map_err(|err| load_error("users", err))?,
+        tokens: tokens::TokenStore::open(&auth_config.tokens_file).map_err(|err| load_error("tokens", err))?,
+        privacy: auth::PrivacyRules::new(auth_config.read_requires_auth, auth_config.private_fields.clone()),
+        sessions: sessions::SessionStore::new(config.server.tls.is_some()),
+        rate_limits: ratelimit::RateLimits::new(auth_config),
+        max_import_size: config.storage.max_import_size,
+        #[cfg(feature = "scraping")]
+        scraping: config.scraping.clone(),
+        download: Mutex::new(Weak::new()),
+    };
+    let state = web::Data::new(state);
+    #[cfg(feature = "scraping")]
+    downloads::schedule(&state, &config.schedules);
+
+    let html_path = config.server.html_root.clone();
+    let openapi = openapi::document();
+    let address = config.server.bind_address;
+    let port = config.server.port;
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .wrap_fn(|req, srv| {
+                let limited = req
+                    .app_data::<web::Data<AppState>>()
+                    .map_or(Ok(()), |state| state.rate_limits.check_request(&req));
+                match limited {
+                    Ok(()) => Either::Left(srv.call(req)),
+                    Err(err) => Either::Right(ready(Ok(req.error_response(err)))),
+                }
+            })
+            .wrap_fn(|req, srv| {
+                let request_id = errors::request_id(&req);
+                let response = srv.call(req);
+                async move {
+                    let mut response = response.await?;
+                    mark_deprecated(&mut response);
+                    Ok(errors::finish_response(response, &request_id))
+                }
+            })
+            .wrap(Logger::new(
+                r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T %{x-request-id}o"#,
+            ))
+            .app_data(state.clone())
+            .app_data(web::PathConfig::default().error_handler(errors::path_error))
+            // registered before the API scope, which would otherwise answer these paths with 404
+            .service(
+                SwaggerUi::new(format!("{}/docs/{{_:.*}}", API_PREFIX))
+                    .url(format!("{}/openapi.json", API_PREFIX), openapi.clone()),
+            )
+            .route(
+                &format!("{}/docs", API_PREFIX),
+                web::get().to(|| async { redirect_to(&format!("{}/docs/", API_PREFIX)) }),
+            )
+            .service(
+                web::scope(API_PREFIX)
+                    .configure(configure_api)
+                    .default_service(web::to(|| async {
+                        Err::<HttpResponse, _>(ApiError::not_found("No such endpoint"))
+                    })),
+            )
+            // deprecated aliases of the API, from before it was versioned
+            .configure(configure_api)
+            .service(actix_files::Files::new("", &html_path).index_file("start.html"))
+    });
+
+    if let Some(listener) = listener {
+        log::info!("Starting HTTP server at http://{}", listener.local_addr()?);
+        return server.listen(listener)?.run().await;
+    }
+
+    #[cfg(feature = "tls")]
+    if let Some(tls_files) = &config.server.tls {
+        let server_config = tls::server_config(tls_files)
+            .map_err(|err| std::io::Error::other(format!("Failed to load TLS certificate: {}", err)))?;
+        log::info!("Starting HTTPS server at https://{}:{}", address, port);
+        let server = server.bind_rustls((address, port), server_config)?;
+
+        return match config.server.http_redirect_port {
+            Some(redirect_port) => {
+                log::info!("Redirecting HTTP requests on port {} to HTTPS", redirect_port);
+                let redirect = HttpServer::new(move || {
+                    App::new()
+                        .app_data(web::Data::new(port))
+                        .default_service(web::to(redirect_to_https))
+                })
+                .bind((address, redirect_port))?;
+                futures_util::future::try_join(server.run(), redirect.run())
+                    .await
+                    .map(|_| ())
+            }
+            None => server.run().await,
+        };
+    }
+
+    log::info!("Starting HTTP server at http://{}:{}", address, port);
+    server.bind((address, port))?.run().await
+}
@@ -0,0 +1,91 @@
+//! The OpenAPI document of the JSON API, generated from the `utoipa::path` attributes of the handlers.
+
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::api::{API_PREFIX, CSRF_HEADER};
+use crate::sessions::SESSION_COOKIE;
+
+/// Schema of binary response and request bodies, i.e. images and zip archives
+#[derive(utoipa::ToSchema)]
+#[schema(value_type = String, format = Binary)]
+#[allow(dead_code)]
+pub(super) struct Binary(Vec<u8>);
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "ITSF player database",
+        description = "Players with their ITSF and DTFB rankings. Reading is public unless the server requires \
+                       credentials for it; changes require a user or token with the matching permission."
+    ),
+    paths(
+        super::get_player,
+        super::get_player_by_dtfb_id,
+        super::get_player_by_slug,
+        super::get_player_batch,
+        super::seed_participants,
+        super::get_player_history,
+        super::get_player_image,
+        super::list_players,
+        super::export_table,
+        super::get_changes,
+        super::add_player_comment,
+        super::download_db_zip,
+        super::import_db_zip,
+        super::merge_players,
+        super::revert_merge,
+        super::list_merges,
+        super::download_status,
+        super::list_users,
+        super::add_user,
+        super::remove_user,
+        super::change_password,
+        super::login,
+        super::logout,
+        super::get_session,
+        super::list_tokens,
+        super::add_token,
+        super::revoke_token,
+    ),
+    modifiers(&SecuritySchemes),
+    security((), ("basic" = []), ("token" = []), ("session" = [])),
+    tags(
+        (name = "players", description = "Player data, and comments on players"),
+        (name = "archive", description = "The whole DB as zip archive"),
+        (name = "merges", description = "Merging duplicate players"),
+        (name = "seeding", description = "Seeding tournaments by the stored rankings"),
+        (name = "downloads", description = "Downloading rankings from the ITSF and DTFB websites"),
+        (name = "users"),
+        (name = "sessions", description = "Cookie-based sessions of the web UI"),
+        (name = "tokens", description = "API tokens for scripts"),
+    )
+)]
+struct ApiDoc;
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("basic", SecurityScheme::Http(Http::new(HttpAuthScheme::Basic)));
+        components.add_security_scheme("token", SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)));
+        let session = ApiKeyValue::with_description(
+            SESSION_COOKIE,
+            &format!(
+                "Requests that change something also have to send the session's CSRF token as `{}`",
+                CSRF_HEADER
+            ),
+        );
+        components.add_security_scheme("session", SecurityScheme::ApiKey(ApiKey::Cookie(session)));
+    }
+}
+
+/// Returns the document of all endpoints, whose paths are relative to `API_PREFIX`.
+pub fn document() -> utoipa::openapi::OpenApi {
+    let mut document = ApiDoc::openapi();
+    #[cfg(feature = "scraping")]
+    document.merge(super::downloads::ApiDoc::openapi());
+    document.servers = Some(vec![utoipa::openapi::Server::new(API_PREFIX)]);
+    document
+}
@@ -0,0 +1,97 @@
+use actix_web::cookie::{Cookie, SameSite};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::error::{Error, Result};
+use crate::tokens::random_hex;
+
+pub const SESSION_COOKIE: &str = "session";
+
+/// Sessions expire after this many seconds, even if they are in use
+const SESSION_LIFETIME: i64 = 12 * 60 * 60;
+
+struct Session {
+    user: String,
+    csrf_token: String,
+    expires: i64,
+}
+
+/// Login sessions of the web UI, which are identified by a cookie instead of basic auth.
+/// Sessions are only kept in memory, so everyone has to log in again after a restart.
+pub struct SessionStore {
+    sessions: Mutex<HashMap<String, Session>>,
+    /// Marks cookies as HTTPS-only
+    secure: bool,
+}
+
+impl SessionStore {
+    pub fn new(secure: bool) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            secure,
+        }
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, HashMap<String, Session>>> {
+        self.sessions
+            .lock()
+            .map_err(|_| Error::Unavailable(String::from("sessions lock was poisoned")))
+    }
+
+    /// Returns the ID and CSRF token of a new session.
+    pub fn create(&self, user: &str) -> Result<(String, String)> {
+        let now = chrono::Utc::now().timestamp();
+        let id = random_hex(32);
+        let csrf_token = random_hex(32);
+
+        let mut sessions = self.lock()?;
+        sessions.retain(|_, session| session.expires > now);
+        sessions.insert(
+            id.clone(),
+            Session {
+                user: String::from(user),
+                csrf_token: csrf_token.clone(),
+                expires: now + SESSION_LIFETIME,
+            },
+        );
+        Ok((id, csrf_token))
+    }
+
+    /// Returns the user and CSRF token of a session that hasn't expired yet.
+    pub fn get(&self, id: &str) -> Option<(String, String)> {
+        let now = chrono::Utc::now().timestamp();
+        let sessions = self.lock().ok()?;
+        sessions
+            .get(id)
+            .filter(|session| session.expires > now)
+            .map(|session| (session.user.clone(), session.csrf_token.clone()))
+    }
+
+    pub fn remove(&self, id: &str) -> Result<()> {
+        self.lock()?.remove(id);
+        Ok(())
+    }
+
+    /// Removes all sessions of a user, e.g. after a password change.
+    pub fn remove_user(&self, user: &str) -> Result<()> {
+        self.lock()?.retain(|_, session| session.user != user);
+        Ok(())
+    }
+
+    /// The cookie is not readable by scripts, and not sent along with cross-site requests.
+    pub fn cookie(&self, id: &str) -> Cookie<'static> {
+        Cookie::build(SESSION_COOKIE, id.to_string())
+            .path("/")
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(SameSite::Strict)
+            .max_age(actix_web::cookie::time::Duration::seconds(SESSION_LIFETIME))
+            .finish()
+    }
+
+    pub fn removal_cookie(&self) -> Cookie<'static> {
+        let mut cookie = self.cookie("");
+        cookie.make_removal();
+        cookie
+    }
+}
@@ -0,0 +1,63 @@
+use actix_web::web::Bytes;
+use actix_web::{HttpResponse, HttpResponseBuilder};
+use std::io::{self, Write};
+use tokio::sync::mpsc;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Number of chunks that may be buffered while the client is slower than the producer
+const MAX_BUFFERED_CHUNKS: usize = 4;
+
+/// Collects written data into chunks, and sends them to a streaming response body.
+pub struct ChannelWriter {
+    sender: mpsc::Sender<Result<Bytes, io::Error>>,
+    buffer: Vec<u8>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            let chunk = Bytes::from(std::mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_SIZE)));
+            self.sender
+                .blocking_send(Ok(chunk))
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs `f` on a blocking thread, and streams everything it writes as the response body, chunk by chunk.
+/// If `f` fails, the response is aborted, so the client doesn't mistake a truncated body for a complete one.
+pub fn stream_response<F, E>(mut response: HttpResponseBuilder, f: F) -> HttpResponse
+where
+    F: FnOnce(&mut ChannelWriter) -> Result<(), E> + Send + 'static,
+    E: std::fmt::Display,
+{
+    let (sender, receiver) = mpsc::channel(MAX_BUFFERED_CHUNKS);
+
+    actix_web::rt::task::spawn_blocking(move || {
+        let mut writer = ChannelWriter {
+            sender: sender.clone(),
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        };
+        let result = f(&mut writer)
+            .map_err(|err| err.to_string())
+            .and_then(|_| writer.flush().map_err(|err| err.to_string()));
+        if let Err(err) = result {
+            log::error!("Failed to stream response: {}", err);
+            let _ = sender.blocking_send(Err(io::Error::other(err)));
+        }
+    });
+
+    let body = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+    response.streaming(body)
+}
@@ -0,0 +1,229 @@
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::{self, CertifiedKey};
+use rustls::{Certificate, PrivateKey, ServerConfig, SignatureScheme};
+use rustls_pemfile::{read_all, Item};
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::config::TlsFiles;
+
+/// How often the certificate and key files are checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+fn files_modified(files: &TlsFiles) -> (Option<SystemTime>, Option<SystemTime>) {
+    let modified = |path: &str| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    (modified(&files.cert_path), modified(&files.key_path))
+}
+
+fn read_pem(path: &str) -> Result<Vec<Item>, String> {
+    let file = File::open(path).map_err(|err| format!("{}: {}", path, err))?;
+    read_all(&mut BufReader::new(file)).map_err(|err| format!("Failed to parse {}: {}", path, err))
+}
+
+/// Loads the certificate chain and the first private key, which can be in PKCS#1 (RSA), PKCS#8 or SEC1 (EC) format.
+fn load_certified_key(files: &TlsFiles) -> Result<CertifiedKey, String> {
+    let certs: Vec<Certificate> = read_pem(&files.cert_path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(cert) => Some(Certificate(cert)),
+            _ => None,
+        })
+        .collect();
+    if certs.is_empty() {
+        return Err(format!("no certificate in {}", files.cert_path));
+    }
+
+    let key = read_pem(&files.key_path)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or(format!("no private key in {}", files.key_path))?;
+    let key = sign::any_supported_type(&key).map_err(|_| format!("unsupported private key in {}", files.key_path))?;
+    check_key_matches(&certs[0], key.as_ref())
+        .map_err(|err| format!("{} and {}: {}", files.cert_path, files.key_path, err))?;
+
+    Ok(CertifiedKey::new(certs, key))
+}
+
+/// Signs a message with the key and verifies it with the certificate, so that a key that doesn't belong to the
+/// certificate is noticed when loading it, instead of failing every handshake.
+fn check_key_matches(cert: &Certificate, key: &dyn sign::SigningKey) -> Result<(), String> {
+    let algorithms = [
+        (SignatureScheme::ECDSA_NISTP256_SHA256, &webpki::ECDSA_P256_SHA256),
+        (SignatureScheme::ECDSA_NISTP384_SHA384, &webpki::ECDSA_P384_SHA384),
+        (SignatureScheme::ED25519, &webpki::ED25519),
+        (SignatureScheme::RSA_PKCS1_SHA256, &webpki::RSA_PKCS1_2048_8192_SHA256),
+    ];
+    let schemes: Vec<SignatureScheme> = algorithms.iter().map(|(scheme, _)| *scheme).collect();
+    let signer = key
+        .choose_scheme(&schemes)
+        .ok_or("no supported signature scheme for the key")?;
+    let algorithm = algorithms
+        .iter()
+        .find(|(scheme, _)| *scheme == signer.scheme())
+        .map(|(_, algorithm)| *algorithm)
+        .ok_or("no supported signature scheme for the key")?;
+
+    let message = b"itsf-playerdb key check";
+    let signature = signer
+        .sign(message)
+        .map_err(|err| format!("failed to sign with the key: {}", err))?;
+    let cert = webpki::EndEntityCert::try_from(cert.0.as_slice())
+        .map_err(|err| format!("failed to parse the certificate: {}", err))?;
+    cert.verify_signature(algorithm, message, &signature)
+        .map_err(|_| String::from("the private key doesn't belong to the certificate"))
+}
+
+/// Serves the most recently loaded certificate. If a renewed certificate can't be loaded, the old one is kept.
+struct ReloadingResolver {
+    files: TlsFiles,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for ReloadingResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        self.current.read().ok().map(|current| current.clone())
+    }
+}
+
+impl ReloadingResolver {
+    fn watch(self: Arc<Self>) {
+        std::thread::spawn(move || {
+            let mut modified = files_modified(&self.files);
+            loop {
+                std::thread::sleep(RELOAD_INTERVAL);
+                self.reload_if_modified(&mut modified);
+            }
+        });
+    }
+
+    /// Loads the files again if their modification times differ from `modified`, which is then updated.
+    fn reload_if_modified(&self, modified: &mut (Option<SystemTime>, Option<SystemTime>)) {
+        let now_modified = files_modified(&self.files);
+        if now_modified == *modified {
+            return;
+        }
+        *modified = now_modified;
+
+        match load_certified_key(&self.files) {
+            Ok(key) => {
+                if let Ok(mut current) = self.current.write() {
+                    *current = Arc::new(key);
+                    log::info!("Reloaded TLS certificate from {}", self.files.cert_path);
+                }
+            }
+            Err(err) => log::error!("Failed to reload TLS certificate, keeping the old one: {}", err),
+        }
+    }
+}
+
+/// Creates a rustls config, whose certificate is reloaded whenever the files change.
+pub fn server_config(files: &TlsFiles) -> Result<ServerConfig, String> {
+    let resolver = Arc::new(ReloadingResolver {
+        files: files.clone(),
+        current: RwLock::new(Arc::new(load_certified_key(files)?)),
+    });
+    resolver.clone().watch();
+
+    Ok(ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn testdata(name: &str) -> String {
+        format!("{}/testdata/tls/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn files(cert: &str, key: &str) -> TlsFiles {
+        TlsFiles {
+            cert_path: testdata(cert),
+            key_path: testdata(key),
+        }
+    }
+
+    fn cert_of(path: &str) -> Certificate {
+        match read_pem(path).unwrap().remove(0) {
+            Item::X509Certificate(cert) => Certificate(cert),
+            _ => panic!("no certificate in {}", path),
+        }
+    }
+
+    #[test]
+    fn loads_rsa_pkcs8_and_ec_keys() {
+        for (cert, key) in [
+            ("rsa.crt", "rsa.key"),
+            ("rsa.crt", "rsa-pkcs8.key"),
+            ("ec.crt", "ec.key"),
+        ] {
+            let loaded = load_certified_key(&files(cert, key)).unwrap_or_else(|err| panic!("{}: {}", key, err));
+            assert_eq!(loaded.cert, vec![cert_of(&testdata(cert))]);
+        }
+    }
+
+    #[test]
+    fn rejects_keys_of_other_certificates() {
+        for (cert, key) in [("rsa.crt", "ec.key"), ("ec.crt", "rsa.key")] {
+            let err = load_certified_key(&files(cert, key)).err().unwrap();
+            assert!(
+                err.ends_with("the private key doesn't belong to the certificate"),
+                "{}",
+                err
+            );
+        }
+        let err = load_certified_key(&files("rsa.key", "rsa.key")).err().unwrap();
+        assert!(err.starts_with("no certificate in"), "{}", err);
+        let err = load_certified_key(&files("rsa.crt", "rsa.crt")).err().unwrap();
+        assert!(err.starts_with("no private key in"), "{}", err);
+    }
+
+    #[test]
+    fn reloads_replaced_certificates() {
+        let dir = tempfile::tempdir().unwrap();
+        let tls_files = TlsFiles {
+            cert_path: dir.path().join("cert.pem").to_str().unwrap().to_string(),
+            key_path: dir.path().join("key.pem").to_str().unwrap().to_string(),
+        };
+        // the modification times are set explicitly, since the file system may not resolve a quick replacement
+        let replace = |cert: &str, key: &str, age: u64| {
+            let modified = SystemTime::now() - Duration::from_secs(age);
+            for (source, target) in [(cert, &tls_files.cert_path), (key, &tls_files.key_path)] {
+                std::fs::copy(testdata(source), target).unwrap();
+                File::options()
+                    .write(true)
+                    .open(Path::new(target))
+                    .unwrap()
+                    .set_modified(modified)
+                    .unwrap();
+            }
+        };
+        let current = |resolver: &ReloadingResolver| resolver.current.read().unwrap().cert.clone();
+
+        replace("rsa.crt", "rsa.key", 20);
+        let resolver = ReloadingResolver {
+            files: tls_files.clone(),
+            current: RwLock::new(Arc::new(load_certified_key(&tls_files).unwrap())),
+        };
+        let mut modified = files_modified(&tls_files);
+        resolver.reload_if_modified(&mut modified);
+        assert_eq!(current(&resolver), vec![cert_of(&testdata("rsa.crt"))]);
+
+        replace("ec.crt", "ec.key", 10);
+        resolver.reload_if_modified(&mut modified);
+        assert_eq!(current(&resolver), vec![cert_of(&testdata("ec.crt"))]);
+
+        // a broken replacement keeps the old certificate
+        replace("rsa.crt", "ec.key", 0);
+        resolver.reload_if_modified(&mut modified);
+        assert_eq!(current(&resolver), vec![cert_of(&testdata("ec.crt"))]);
+    }
+}
@@ -0,0 +1,232 @@
+//! API tokens, which grant some permissions to scripts without a user's password. Only hashes of them are stored.
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::sync::Mutex;
+
+use crate::api::{CreatedToken, Permission, TokenInfo};
+use crate::error::{Error, Result};
+
+/// Prefix of all token secrets, so that they are easy to recognize, e.g. in leaked logs
+const TOKEN_PREFIX: &str = "pdb_";
+
+/// `last_used` is kept exact in memory, but only written to the file if it changed by at least this many seconds
+const LAST_USED_WRITE_INTERVAL: i64 = 60;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct Token {
+    id: String,
+    name: String,
+    scopes: Vec<Permission>,
+    created: i64,
+    created_by: String,
+    expires: i64,
+    last_used: Option<i64>,
+    /// SHA-256 of the secret. Tokens are long and random, so a slow password hash isn't needed.
+    hash: String,
+}
+
+impl From<&Token> for TokenInfo {
+    fn from(token: &Token) -> Self {
+        Self {
+            id: token.id.clone(),
+            name: token.name.clone(),
+            scopes: token.scopes.clone(),
+            created: token.created,
+            created_by: token.created_by.clone(),
+            expires: token.expires,
+            last_used: token.last_used,
+        }
+    }
+}
+
+pub fn random_hex(bytes: usize) -> String {
+    let mut buffer = vec![0u8; bytes];
+    OsRng.fill_bytes(&mut buffer);
+    buffer.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn hash_secret(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+/// API tokens, which scripts can send as `Authorization: Bearer <token>` instead of a user's password.
+/// Each token only grants its listed scopes, a token with only the `admin` scope can't add comments.
+///
+/// Tokens are stored as a JSON list in the tokens file, which is created on the first write.
+pub struct TokenStore {
+    path: String,
+    tokens: Mutex<Vec<Token>>,
+}
+
+impl TokenStore {
+    pub fn open(path: &str) -> Result<Self> {
+        let tokens = match std::fs::read(path) {
+            Ok(content) => serde_json::from_slice(&content)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(Error::io(path, err)),
+        };
+        Ok(Self {
+            path: String::from(path),
+            tokens: Mutex::new(tokens),
+        })
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Vec<Token>>> {
+        self.tokens
+            .lock()
+            .map_err(|_| Error::Unavailable(String::from("tokens lock was poisoned")))
+    }
+
+    /// Writes to a temporary file first, like the users file.
+    fn write(&self, tokens: &[Token]) -> Result<()> {
+        let temp_path = format!("{}.tmp", self.path);
+        let content = serde_json::to_vec_pretty(tokens)?;
+        std::fs::write(&temp_path, content).map_err(|err| Error::io(&temp_path, err))?;
+        std::fs::rename(&temp_path, &self.path).map_err(|err| Error::io(&self.path, err))
+    }
+
+    /// Returns the name and scopes of a valid, non-expired token, and updates its last-used timestamp.
+    pub fn authenticate(&self, secret: &str) -> Option<(String, Vec<Permission>)> {
+        let hash = hash_secret(secret);
+        let now = chrono::Utc::now().timestamp();
+
+        let mut tokens = self.lock().ok()?;
+        let token = tokens.iter_mut().find(|token| token.hash == hash)?;
+        if token.expires <= now {
+            return None;
+        }
+        let needs_write = token
+            .last_used
+            .is_none_or(|last_used| now - last_used >= LAST_USED_WRITE_INTERVAL);
+        token.last_used = Some(now);
+        let result = (token.name.clone(), token.scopes.clone());
+
+        if needs_write {
+            if let Err(err) = self.write(&tokens) {
+                log::error!("Failed to update token usage: {}", err);
+            }
+        }
+        Some(result)
+    }
+
+    pub fn list(&self) -> Result<Vec<TokenInfo>> {
+        let tokens = self.lock()?;
+        Ok(tokens.iter().map(TokenInfo::from).collect())
+    }
+
+    pub fn create(&self, name: &str, scopes: &[Permission], valid_days: i64, created_by: &str) -> Result<CreatedToken> {
+        if name.trim().is_empty() {
+            return Err(Error::Invalid(String::from("Token name must not be empty")));
+        }
+        if scopes.is_empty() {
+            return Err(Error::Invalid(String::from("Token needs at least one scope")));
+        }
+        if valid_days < 1 {
+            return Err(Error::Invalid(String::from("Token must be valid for at least one day")));
+        }
+
+        let mut scopes = scopes.to_vec();
+        scopes.sort();
+        scopes.dedup();
+
+        let secret = format!("{}{}", TOKEN_PREFIX, random_hex(32));
+        let now = chrono::Utc::now().timestamp();
+        let token = Token {
+            id: random_hex(4),
+            name: String::from(name.trim()),
+            scopes,
+            created: now,
+            created_by: String::from(created_by),
+            expires: now.saturating_add(valid_days.saturating_mul(24 * 60 * 60)),
+            last_used: None,
+            hash: hash_secret(&secret),
+        };
+        let info = TokenInfo::from(&token);
+
+        let mut tokens = self.lock()?;
+        tokens.push(token);
+        if let Err(err) = self.write(&tokens) {
+            tokens.pop();
+            return Err(err);
+        }
+        Ok(CreatedToken { token: secret, info })
+    }
+
+    pub fn revoke(&self, id: &str) -> Result<()> {
+        let mut tokens = self.lock()?;
+        let index = tokens
+            .iter()
+            .position(|token| token.id == id)
+            .ok_or(Error::NotFound(format!("No such token: {}", id)))?;
+        let token = tokens.remove(index);
+        if let Err(err) = self.write(&tokens) {
+            tokens.insert(index, token);
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> (TokenStore, tempfile::TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tokens.json");
+        (TokenStore::open(path.to_str().unwrap()).unwrap(), dir)
+    }
+
+    #[test]
+    fn tokens_grant_their_scopes() {
+        let (tokens, dir) = store();
+        let created = tokens
+            .create(
+                " sync ",
+                &[Permission::Read, Permission::Comment, Permission::Read],
+                30,
+                "anna",
+            )
+            .unwrap();
+        assert!(created.token.starts_with(TOKEN_PREFIX));
+        assert_eq!(created.info.name, "sync");
+        assert_eq!(created.info.scopes, vec![Permission::Read, Permission::Comment]);
+
+        let (name, scopes) = tokens.authenticate(&created.token).unwrap();
+        assert_eq!(
+            (name.as_str(), scopes),
+            ("sync", vec![Permission::Read, Permission::Comment])
+        );
+        assert!(tokens.authenticate("pdb_0000").is_none());
+
+        // only the hash is stored, and the tokens survive a restart
+        let content = std::fs::read_to_string(dir.path().join("tokens.json")).unwrap();
+        assert!(!content.contains(&created.token));
+        let reopened = TokenStore::open(dir.path().join("tokens.json").to_str().unwrap()).unwrap();
+        assert!(reopened.authenticate(&created.token).is_some());
+        assert!(reopened.list().unwrap()[0].last_used.is_some());
+    }
+
+    #[test]
+    fn revoked_and_expired_tokens_are_rejected() {
+        let (tokens, _dir) = store();
+        let revoked = tokens.create("old", &[Permission::Admin], 1, "anna").unwrap();
+        tokens.revoke(&revoked.info.id).unwrap();
+        assert!(tokens.authenticate(&revoked.token).is_none());
+        assert!(matches!(tokens.revoke(&revoked.info.id), Err(Error::NotFound(_))));
+
+        let expired = tokens.create("expired", &[Permission::Read], 1, "anna").unwrap();
+        tokens.lock().unwrap()[0].expires = chrono::Utc::now().timestamp();
+        assert!(tokens.authenticate(&expired.token).is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_tokens() {
+        let (tokens, _dir) = store();
+        assert!(tokens.create(" ", &[Permission::Read], 1, "anna").is_err());
+        assert!(tokens.create("sync", &[], 1, "anna").is_err());
+        assert!(tokens.create("sync", &[Permission::Read], 0, "anna").is_err());
+        assert!(tokens.list().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,338 @@
+//! The users of the server, with their roles and Argon2 password hashes, stored in the users file.
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::api::{Role, UserInfo};
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone)]
+struct User {
+    role: Role,
+    /// Argon2 hash in PHC string format
+    password_hash: String,
+}
+
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|err| Error::Data(format!("Failed to hash password: {}", err)))
+}
+
+/// Compares a password to a hash, in constant time.
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(hash) => Argon2::default().verify_password(password.as_bytes(), &hash).is_ok(),
+        Err(_) => false,
+    }
+}
+
+struct UsersInner {
+    users: HashMap<String, User>,
+    /// Modification time of the users file when it was last read
+    modified: Option<SystemTime>,
+    /// Hash that is checked for unknown users, so that they take as long to reject as known ones
+    dummy_hash: String,
+}
+
+/// The users file has one `name:role:password-hash` line per user. It is re-read whenever it is modified,
+/// so that users can be managed without restarting the server.
+///
+/// Lines in the old `name:password` format are converted on load: the password is hashed, and the user
+/// becomes an admin, since every listed user used to have full access.
+pub struct UserStore {
+    path: String,
+    inner: Mutex<UsersInner>,
+}
+
+fn file_modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.contains(':') || name.contains(char::is_whitespace) {
+        return Err(Error::Invalid(format!("invalid user name: '{}'", name)));
+    }
+    Ok(())
+}
+
+fn validate_password(password: &str) -> Result<()> {
+    if password.len() < 8 {
+        return Err(Error::Invalid(String::from(
+            "Password needs to have at least 8 characters",
+        )));
+    }
+    Ok(())
+}
+
+impl UserStore {
+    pub fn open(path: &str) -> Result<Self> {
+        let store = Self {
+            path: String::from(path),
+            inner: Mutex::new(UsersInner {
+                users: HashMap::new(),
+                modified: None,
+                dummy_hash: hash_password("")?,
+            }),
+        };
+        store.reload()?;
+        Ok(store)
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, UsersInner>> {
+        self.inner
+            .lock()
+            .map_err(|_| Error::Unavailable(String::from("users lock was poisoned")))
+    }
+
+    fn reload(&self) -> Result<()> {
+        let mut inner = self.lock()?;
+        let content = std::fs::read_to_string(&self.path).map_err(|err| Error::io(&self.path, err))?;
+
+        let mut users = HashMap::new();
+        let mut converted = false;
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |msg: String| Error::Data(format!("{}, line {}: {}", self.path, index + 1, msg));
+
+            let parts: Vec<&str> = line.splitn(3, ':').collect();
+            let (name, user) = match parts[..] {
+                [name, role, password_hash] => {
+                    PasswordHash::new(password_hash).map_err(|err| invalid(err.to_string()))?;
+                    let user = User {
+                        role: Role::try_from_str(role).map_err(invalid)?,
+                        password_hash: String::from(password_hash),
+                    };
+                    (name, user)
+                }
+                [name, password] => {
+                    converted = true;
+                    let user = User {
+                        role: Role::Admin,
+                        password_hash: hash_password(password)?,
+                    };
+                    (name, user)
+                }
+                _ => return Err(invalid(String::from("expected 'name:role:password-hash'"))),
+            };
+            validate_name(name)?;
+            users.insert(String::from(name), user);
+        }
+
+        inner.users = users;
+        if converted {
+            log::warn!("Converting plaintext passwords in {} to hashes", self.path);
+            self.write(&mut inner)?;
+        }
+        inner.modified = file_modified(&self.path);
+        Ok(())
+    }
+
+    /// Re-reads the users file if it was modified since it was last read.
+    fn reload_if_modified(&self) -> Result<()> {
+        let modified = self.lock()?.modified;
+        if file_modified(&self.path) != modified {
+            log::info!("Reloading {}", self.path);
+            self.reload()?;
+        }
+        Ok(())
+    }
+
+    /// Writes all users to a temporary file first, which then replaces the users file, so that
+    /// it is never left half-written.
+    fn write(&self, inner: &mut UsersInner) -> Result<()> {
+        let mut names: Vec<&String> = inner.users.keys().collect();
+        names.sort();
+
+        let temp_path = format!("{}.tmp", self.path);
+        let mut file = std::fs::File::create(&temp_path).map_err(|err| Error::io(&temp_path, err))?;
+        for name in names {
+            let user = &inner.users[name];
+            writeln!(file, "{}:{}:{}", name, user.role.to_str(), user.password_hash)
+                .map_err(|err| Error::io(&temp_path, err))?;
+        }
+        file.sync_all().map_err(|err| Error::io(&temp_path, err))?;
+        std::fs::rename(&temp_path, &self.path).map_err(|err| Error::io(&self.path, err))?;
+
+        inner.modified = file_modified(&self.path);
+        Ok(())
+    }
+
+    /// Returns the role of the user, if the password is correct. Verifying takes a while by design, so this
+    /// should not be called on an async thread, and the users are not locked while verifying.
+    pub fn authenticate(&self, name: &str, password: &str) -> Option<Role> {
+        if let Err(err) = self.reload_if_modified() {
+            log::error!("Failed to reload users: {}", err);
+        }
+
+        let (user, dummy_hash) = {
+            let inner = self.lock().ok()?;
+            (inner.users.get(name).cloned(), inner.dummy_hash.clone())
+        };
+        match user {
+            Some(user) => verify_password(password, &user.password_hash).then_some(user.role),
+            None => {
+                verify_password(password, &dummy_hash);
+                None
+            }
+        }
+    }
+
+    /// Returns the current role of a user, e.g. to check that a logged-in user still exists.
+    pub fn role(&self, name: &str) -> Option<Role> {
+        if let Err(err) = self.reload_if_modified() {
+            log::error!("Failed to reload users: {}", err);
+        }
+        self.lock().ok()?.users.get(name).map(|user| user.role)
+    }
+
+    pub fn list(&self) -> Result<Vec<UserInfo>> {
+        self.reload_if_modified()?;
+        let inner = self.lock()?;
+        let mut users: Vec<UserInfo> = inner
+            .users
+            .iter()
+            .map(|(name, user)| UserInfo {
+                name: name.clone(),
+                role: user.role,
+            })
+            .collect();
+        users.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(users)
+    }
+
+    pub fn add(&self, name: &str, password: &str, role: Role) -> Result<()> {
+        validate_name(name)?;
+        validate_password(password)?;
+        self.reload_if_modified()?;
+
+        let password_hash = hash_password(password)?;
+        let mut inner = self.lock()?;
+        if inner.users.contains_key(name) {
+            return Err(Error::Invalid(format!("User {} already exists", name)));
+        }
+        let user = User { role, password_hash };
+        inner.users.insert(String::from(name), user);
+        self.write(&mut inner)
+    }
+
+    pub fn remove(&self, name: &str) -> Result<()> {
+        self.reload_if_modified()?;
+
+        let mut inner = self.lock()?;
+        let user = inner
+            .users
+            .get(name)
+            .ok_or(Error::NotFound(format!("No such user: {}", name)))?;
+        let admin_count = inner.users.values().filter(|u| u.role == Role::Admin).count();
+        if user.role == Role::Admin && admin_count == 1 {
+            return Err(Error::Invalid(String::from("Can't remove the last admin")));
+        }
+        inner.users.remove(name);
+        self.write(&mut inner)
+    }
+
+    pub fn set_password(&self, name: &str, password: &str) -> Result<()> {
+        validate_password(password)?;
+        self.reload_if_modified()?;
+
+        let password_hash = hash_password(password)?;
+        let mut inner = self.lock()?;
+        let user = inner
+            .users
+            .get_mut(name)
+            .ok_or(Error::NotFound(format!("No such user: {}", name)))?;
+        user.password_hash = password_hash;
+        self.write(&mut inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Permission;
+
+    fn store(content: &str) -> (UserStore, tempfile::TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.txt");
+        std::fs::write(&path, content).unwrap();
+        (UserStore::open(path.to_str().unwrap()).unwrap(), dir)
+    }
+
+    #[test]
+    fn roles_include_lower_roles() {
+        assert!(Role::Viewer.allows(Permission::Read));
+        assert!(!Role::Viewer.allows(Permission::Comment));
+        assert!(Role::Commenter.allows(Permission::Comment));
+        assert!(!Role::Commenter.allows(Permission::Scrape));
+        assert!(Role::ScraperOperator.allows(Permission::Scrape));
+        assert!(!Role::ScraperOperator.allows(Permission::Admin));
+        assert!([
+            Permission::Read,
+            Permission::Comment,
+            Permission::Scrape,
+            Permission::Admin
+        ]
+        .into_iter()
+        .all(|permission| Role::Admin.allows(permission)));
+    }
+
+    #[test]
+    fn converts_plaintext_passwords() {
+        let (users, dir) = store("# old format\nanna:password1\n");
+        assert_eq!(users.authenticate("anna", "password1"), Some(Role::Admin));
+        assert_eq!(users.authenticate("anna", "password2"), None);
+        assert_eq!(users.authenticate("tom", "password1"), None);
+
+        let content = std::fs::read_to_string(dir.path().join("users.txt")).unwrap();
+        assert!(content.starts_with("anna:admin:$argon2"));
+        assert!(!content.contains("password1"));
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.txt");
+        std::fs::write(&path, "anna:owner:$argon2id$v=19$m=19456,t=2,p=1$c2FsdHNhbHQ$aGFzaA\n").unwrap();
+        assert!(UserStore::open(path.to_str().unwrap()).is_err());
+        std::fs::write(&path, "anna\n").unwrap();
+        assert!(UserStore::open(path.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn manages_users() {
+        let (users, _dir) = store("anna:password1\n");
+        users.add("tom", "password2", Role::Viewer).unwrap();
+        assert!(users.add("tom", "password3", Role::Admin).is_err());
+        assert!(users.add("to m", "password3", Role::Admin).is_err());
+        assert!(users.add("lisa", "short", Role::Admin).is_err());
+        assert_eq!(users.role("tom"), Some(Role::Viewer));
+
+        users.set_password("tom", "password3").unwrap();
+        assert_eq!(users.authenticate("tom", "password2"), None);
+        assert_eq!(users.authenticate("tom", "password3"), Some(Role::Viewer));
+
+        let names: Vec<String> = users.list().unwrap().into_iter().map(|user| user.name).collect();
+        assert_eq!(names, vec!["anna", "tom"]);
+    }
+
+    #[test]
+    fn keeps_the_last_admin() {
+        let (users, _dir) = store("anna:password1\n");
+        users.add("tom", "password2", Role::Admin).unwrap();
+        users.remove("tom").unwrap();
+        assert!(matches!(users.remove("anna"), Err(Error::Invalid(_))));
+        assert!(matches!(users.remove("tom"), Err(Error::NotFound(_))));
+        assert_eq!(users.role("anna"), Some(Role::Admin));
+    }
+}